@@ -0,0 +1,306 @@
+use hyper::{body::Bytes, header::CONTENT_TYPE, HeaderMap};
+use quick_xml::{escape::resolve_predefined_entity, events::Event, Reader, XmlVersion};
+use serde_json::{json, Map, Value};
+
+const XML_ATTRIBUTE_PREFIX: &str = "@";
+const XML_TEXT_KEY: &str = "#text";
+
+/// Request body representation used as input of conditions
+#[derive(Debug, PartialEq)]
+pub(crate) struct ParsedBody {
+    /// Structured body: parsed JSON, form or XML content, empty object otherwise
+    pub(crate) body: Value,
+    /// Raw body as UTF-8 string if it can't be represented as a structured content
+    pub(crate) text: Option<String>,
+    /// Size of the raw body in bytes
+    pub(crate) size: usize,
+}
+
+#[derive(Debug, PartialEq)]
+enum BodyKind {
+    Form,
+    Xml,
+    Other,
+}
+
+impl BodyKind {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let Some(mime) = headers
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(';').next())
+            .map(|v| v.trim().to_ascii_lowercase())
+        else {
+            return BodyKind::Other;
+        };
+
+        match mime.as_str() {
+            "application/x-www-form-urlencoded" => BodyKind::Form,
+            "application/xml" | "text/xml" => BodyKind::Xml,
+            m if m.ends_with("+xml") => BodyKind::Xml,
+            _ => BodyKind::Other,
+        }
+    }
+}
+
+/// Parses body according to the `Content-Type` header.
+///
+/// Form and XML contents are converted to JSON values, any other content is parsed as JSON.
+/// If content can't be parsed, it's exposed as text.
+pub(crate) fn parse_body(headers: &HeaderMap, body: &Bytes) -> ParsedBody {
+    let parsed = match BodyKind::from_headers(headers) {
+        BodyKind::Form => Some(parse_form(body)),
+        BodyKind::Xml => parse_xml(body),
+        BodyKind::Other => serde_json::from_slice(body).ok(),
+    };
+
+    match parsed {
+        Some(value) => ParsedBody {
+            body: value,
+            text: None,
+            size: body.len(),
+        },
+        None => ParsedBody {
+            body: json!({}),
+            text: Some(String::from_utf8_lossy(body).into_owned()),
+            size: body.len(),
+        },
+    }
+}
+
+/// Converts form to an object, values of repeated keys are collected into an array
+fn parse_form(body: &[u8]) -> Value {
+    let mut form = Map::new();
+    for (key, value) in form_urlencoded::parse(body) {
+        let value = Value::String(value.into_owned());
+        match form.get_mut(key.as_ref()) {
+            Some(Value::Array(values)) => values.push(value),
+            Some(existing) => *existing = Value::Array(vec![existing.take(), value]),
+            None => {
+                form.insert(key.into_owned(), value);
+            }
+        }
+    }
+
+    Value::Object(form)
+}
+
+#[derive(Default)]
+struct XmlElement {
+    name: String,
+    attributes: Map<String, Value>,
+    children: Vec<(String, Value)>,
+    text: String,
+}
+
+impl XmlElement {
+    fn new(start: &quick_xml::events::BytesStart) -> Option<Self> {
+        let mut attributes = Map::new();
+        for attr in start.attributes() {
+            let attr = attr.ok()?;
+            let value = attr.normalized_value(XmlVersion::Implicit1_0).ok()?;
+            attributes.insert(
+                format!("{XML_ATTRIBUTE_PREFIX}{}", attr.key.as_ref()),
+                Value::String(value.into_owned()),
+            );
+        }
+
+        Some(Self {
+            name: start.name().as_ref().to_string(),
+            attributes,
+            ..Default::default()
+        })
+    }
+
+    /// Text-only element becomes a string, any other is an object with attributes (`@name`),
+    /// children (repeated children are collected into an array) and text (`#text`)
+    fn into_value(self) -> (String, Value) {
+        // Text is trimmed as a whole to drop formatting whitespaces and keep spaces around entities
+        let text = self.text.trim();
+        if self.attributes.is_empty() && self.children.is_empty() {
+            return (self.name, Value::String(text.into()));
+        }
+
+        // Child values are strings or objects only, so an array means repeated children
+        let mut object = self.attributes;
+        for (name, value) in self.children {
+            match object.get_mut(&name) {
+                Some(Value::Array(values)) => values.push(value),
+                Some(existing) => *existing = Value::Array(vec![existing.take(), value]),
+                None => {
+                    object.insert(name, value);
+                }
+            }
+        }
+        if !text.is_empty() {
+            object.insert(XML_TEXT_KEY.into(), Value::String(text.into()));
+        }
+
+        (self.name, Value::Object(object))
+    }
+}
+
+/// Converts XML document to an object with the root element name as the single key
+fn parse_xml(body: &[u8]) -> Option<Value> {
+    let body = std::str::from_utf8(body).ok()?;
+    let mut reader = Reader::from_str(body);
+
+    let mut stack: Vec<XmlElement> = vec![];
+    let mut root = None;
+    loop {
+        let closed = match reader.read_event().ok()? {
+            Event::Start(start) => {
+                stack.push(XmlElement::new(&start)?);
+                None
+            }
+            Event::Empty(start) => Some(XmlElement::new(&start)?),
+            Event::End(_) => Some(stack.pop()?),
+            Event::Text(text) => {
+                let text = text.xml10_content();
+                match stack.last_mut() {
+                    Some(element) => element.text.push_str(&text),
+                    None if text.trim().is_empty() => {}
+                    None => return None,
+                }
+                None
+            }
+            Event::CData(data) => {
+                stack.last_mut()?.text.push_str(&data.xml10_content());
+                None
+            }
+            Event::GeneralRef(reference) => {
+                let element = stack.last_mut()?;
+                match reference.resolve_char_ref().ok()? {
+                    Some(ch) => element.text.push(ch),
+                    None => element
+                        .text
+                        .push_str(resolve_predefined_entity(&reference.into_inner())?),
+                }
+                None
+            }
+            Event::Eof => break,
+            Event::Comment(_) | Event::Decl(_) | Event::PI(_) | Event::DocType(_) => None,
+        };
+
+        if let Some(element) = closed {
+            let (name, value) = element.into_value();
+            match stack.last_mut() {
+                Some(parent) => parent.children.push((name, value)),
+                None if root.is_none() => root = Some(json!({ name: value })),
+                None => return None,
+            }
+        }
+    }
+
+    if stack.is_empty() {
+        root
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    fn parse(content_type: Option<&str>, body: &'static str) -> ParsedBody {
+        let mut headers = HeaderMap::new();
+        if let Some(content_type) = content_type {
+            headers.insert(CONTENT_TYPE, HeaderValue::from_str(content_type).unwrap());
+        }
+        parse_body(&headers, &Bytes::from_static(body.as_bytes()))
+    }
+
+    #[test]
+    fn json_body() {
+        let expected = ParsedBody {
+            body: json!({"key": "value"}),
+            text: None,
+            size: 16,
+        };
+        assert_eq!(
+            parse(Some("application/json"), r#"{"key": "value"}"#),
+            expected
+        );
+        assert_eq!(parse(None, r#"{"key": "value"}"#), expected);
+        assert_eq!(parse(Some("text/plain"), r#"{"key": "value"}"#), expected);
+    }
+
+    #[test]
+    fn form_body() {
+        let parsed = parse(
+            Some("application/x-www-form-urlencoded; charset=utf-8"),
+            "name=John+Doe&tag=a&tag=b&tag=c&empty=&email=john%40example.com",
+        );
+        assert_eq!(
+            parsed.body,
+            json!({
+                "name": "John Doe",
+                "tag": ["a", "b", "c"],
+                "empty": "",
+                "email": "john@example.com"
+            })
+        );
+        assert_eq!(parsed.text, None);
+        assert_eq!(parsed.size, 63);
+    }
+
+    #[test]
+    fn xml_body() {
+        let parsed = parse(
+            Some("application/atom+xml"),
+            r#"<?xml version="1.0"?>
+            <!-- event -->
+            <event type="push" id="1">
+                <repo>crate</repo>
+                <commit sha="a1">first &amp; <![CDATA[<best>]]></commit>
+                <commit sha="b2"/>
+                <empty/>
+                <note>&#65;&lt;</note>
+            </event>"#,
+        );
+        assert_eq!(
+            parsed.body,
+            json!({
+                "event": {
+                    "@type": "push",
+                    "@id": "1",
+                    "repo": "crate",
+                    "commit": [
+                        {"@sha": "a1", "#text": "first & <best>"},
+                        {"@sha": "b2"}
+                    ],
+                    "empty": "",
+                    "note": "A<"
+                }
+            })
+        );
+        assert_eq!(parsed.text, None);
+
+        let parsed = parse(Some("text/xml"), "<a><b>1</b><b>2</b><b>3</b></a>");
+        assert_eq!(parsed.body, json!({"a": {"b": ["1", "2", "3"]}}));
+    }
+
+    #[test]
+    fn text_body() {
+        let parsed = parse(Some("text/plain"), "just some text");
+        assert_eq!(
+            parsed,
+            ParsedBody {
+                body: json!({}),
+                text: Some("just some text".into()),
+                size: 14
+            }
+        );
+
+        let parsed = parse(Some("application/xml"), "<a><b></a>");
+        assert_eq!(parsed.body, json!({}));
+        assert_eq!(parsed.text, Some("<a><b></a>".into()));
+
+        let parsed = parse(None, "");
+        assert_eq!(parsed.body, json!({}));
+        assert_eq!(parsed.text, Some("".into()));
+        assert_eq!(parsed.size, 0);
+    }
+}
@@ -0,0 +1,355 @@
+use crate::config::compression::{CompressionConfig, ContentEncoding};
+use flate2::{
+    read::{MultiGzDecoder, ZlibDecoder},
+    write::{GzEncoder, ZlibEncoder},
+    Compression,
+};
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    body::Bytes,
+    header::{HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, VARY},
+    HeaderMap, Response,
+};
+use std::{
+    io::{self, Read, Write},
+    str::FromStr,
+};
+use tracing::debug;
+
+const BROTLI_BUFFER_SIZE: usize = 4096;
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW_SIZE: u32 = 22;
+const ZSTD_DEFAULT_LEVEL: i32 = 0;
+const IDENTITY_ENCODING: &str = "identity";
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum CompressionError {
+    #[error("unsupported content encoding `{0}`")]
+    Unsupported(String),
+    #[error("unable to decode `{encoding}` content: {cause}")]
+    Decode {
+        encoding: ContentEncoding,
+        cause: io::Error,
+    },
+    #[error("decoded `{encoding}` content exceeds {limit} bytes")]
+    TooLarge {
+        encoding: ContentEncoding,
+        limit: usize,
+    },
+    #[error("unable to encode content with `{encoding}`: {cause}")]
+    Encode {
+        encoding: ContentEncoding,
+        cause: io::Error,
+    },
+}
+
+/// Compresses body with the encoding
+pub(crate) fn encode(encoding: ContentEncoding, body: &[u8]) -> Result<Bytes, CompressionError> {
+    let encoded = match encoding {
+        ContentEncoding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(body).and_then(|_| encoder.finish())
+        }
+        ContentEncoding::Deflate => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(body).and_then(|_| encoder.finish())
+        }
+        ContentEncoding::Br => {
+            let mut encoder = brotli::CompressorWriter::new(
+                Vec::new(),
+                BROTLI_BUFFER_SIZE,
+                BROTLI_QUALITY,
+                BROTLI_WINDOW_SIZE,
+            );
+            encoder.write_all(body).map(|_| encoder.into_inner())
+        }
+        ContentEncoding::Zstd => zstd::stream::encode_all(body, ZSTD_DEFAULT_LEVEL),
+    };
+
+    encoded
+        .map(Bytes::from)
+        .map_err(|cause| CompressionError::Encode { encoding, cause })
+}
+
+/// Decompresses body encoded with the encoding, decoded body can't exceed `limit` bytes
+pub(crate) fn decode(
+    encoding: ContentEncoding,
+    body: &[u8],
+    limit: usize,
+) -> Result<Bytes, CompressionError> {
+    let decoder: Box<dyn Read + '_> = match encoding {
+        ContentEncoding::Gzip => Box::new(MultiGzDecoder::new(body)),
+        ContentEncoding::Deflate => Box::new(ZlibDecoder::new(body)),
+        ContentEncoding::Br => Box::new(brotli::Decompressor::new(body, BROTLI_BUFFER_SIZE)),
+        ContentEncoding::Zstd => Box::new(
+            zstd::stream::read::Decoder::new(body)
+                .map_err(|cause| CompressionError::Decode { encoding, cause })?,
+        ),
+    };
+
+    // One more byte is read to detect exceeding of the limit
+    let mut decoded = Vec::new();
+    decoder
+        .take((limit as u64).saturating_add(1))
+        .read_to_end(&mut decoded)
+        .map_err(|cause| CompressionError::Decode { encoding, cause })?;
+    if decoded.len() > limit {
+        return Err(CompressionError::TooLarge { encoding, limit });
+    }
+
+    Ok(Bytes::from(decoded))
+}
+
+/// Decodes body according to the `Content-Encoding` header, each decoding step is limited by `limit` bytes.
+/// Returns `None` if the body isn't encoded.
+pub(crate) fn decode_content(
+    headers: &HeaderMap,
+    body: &Bytes,
+    limit: usize,
+) -> Result<Option<Bytes>, CompressionError> {
+    let mut encodings = vec![];
+    for value in headers.get_all(CONTENT_ENCODING) {
+        let value = value
+            .to_str()
+            .map_err(|_| CompressionError::Unsupported(format!("{value:?}")))?;
+        for encoding in value.split(',').map(str::trim) {
+            if encoding.is_empty() || encoding.eq_ignore_ascii_case(IDENTITY_ENCODING) {
+                continue;
+            }
+            encodings.push(
+                ContentEncoding::from_str(encoding)
+                    .map_err(|_| CompressionError::Unsupported(encoding.into()))?,
+            );
+        }
+    }
+    if encodings.is_empty() {
+        return Ok(None);
+    }
+
+    // Encodings are listed in the order they were applied, so decode in reverse order
+    let mut body = body.clone();
+    for encoding in encodings.into_iter().rev() {
+        body = decode(encoding, &body, limit)?;
+    }
+
+    Ok(Some(body))
+}
+
+/// Selects the most preferable encoding accepted by the client according to `Accept-Encoding` header.
+/// Equally weighted encodings are selected in order of `supported` list.
+pub(crate) fn negotiate(
+    headers: &HeaderMap,
+    supported: &[ContentEncoding],
+) -> Option<ContentEncoding> {
+    let accepted: Vec<(&str, f32)> = headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|item| {
+            let mut parts = item.split(';').map(str::trim);
+            let name = parts.next().filter(|name| !name.is_empty())?;
+            let weight = parts
+                .find_map(|p| p.strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.parse::<f32>().ok())?;
+            Some((name, weight))
+        })
+        .collect();
+    let weight_of = |name: &str| {
+        accepted
+            .iter()
+            .find(|(accepted, _)| accepted.eq_ignore_ascii_case(name))
+            .or_else(|| accepted.iter().find(|(accepted, _)| *accepted == "*"))
+            .map_or(0.0, |(_, weight)| *weight)
+    };
+
+    let mut selected = None;
+    let mut selected_weight = 0.0;
+    for encoding in supported {
+        let weight = weight_of(&encoding.to_string());
+        if weight > selected_weight {
+            selected = Some(*encoding);
+            selected_weight = weight;
+        }
+    }
+
+    selected
+}
+
+/// Compresses response body if it's large enough, isn't encoded yet and client accepts one of the configured encodings
+pub(crate) async fn compress_response(
+    cfg: &CompressionConfig,
+    req_headers: &HeaderMap,
+    resp: Response<Full<Bytes>>,
+) -> Response<Full<Bytes>> {
+    if cfg.encodings().is_empty() {
+        return resp;
+    }
+
+    let (mut parts, body) = resp.into_parts();
+    parts
+        .headers
+        .append(VARY, HeaderValue::from_static("accept-encoding"));
+    let body = body
+        .collect()
+        .await
+        .expect("collecting of in-memory body can't fail")
+        .to_bytes();
+    if parts.headers.contains_key(CONTENT_ENCODING) || body.len() < cfg.min_size() {
+        return Response::from_parts(parts, Full::from(body));
+    }
+    let Some(encoding) = negotiate(req_headers, cfg.encodings()) else {
+        return Response::from_parts(parts, Full::from(body));
+    };
+
+    match encode(encoding, &body) {
+        Ok(encoded) => {
+            debug!(%encoding, original = body.len(), compressed = encoded.len(), "compress response");
+            parts
+                .headers
+                .insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.into()));
+            parts
+                .headers
+                .insert(CONTENT_LENGTH, HeaderValue::from(encoded.len()));
+            Response::from_parts(parts, Full::from(encoded))
+        }
+        Err(e) => {
+            debug!(error = %e, "unable to compress response, sending as is");
+            Response::from_parts(parts, Full::from(body))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_ENCODINGS: [ContentEncoding; 4] = [
+        ContentEncoding::Gzip,
+        ContentEncoding::Deflate,
+        ContentEncoding::Br,
+        ContentEncoding::Zstd,
+    ];
+
+    fn headers(name: hyper::header::HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn encode_decode_roundtrip() {
+        let body = "{\"key\": \"value\"}".repeat(100);
+        for encoding in ALL_ENCODINGS {
+            let encoded = encode(encoding, body.as_bytes()).unwrap();
+            assert!(encoded.len() < body.len(), "{encoding} should compress");
+            assert_eq!(
+                decode(encoding, &encoded, usize::MAX).unwrap(),
+                body,
+                "{encoding}"
+            );
+        }
+    }
+
+    #[test]
+    fn decode_content_by_header() {
+        let body = Bytes::from_static(b"{\"key\": \"value\"}");
+        let gzip = encode(ContentEncoding::Gzip, &body).unwrap();
+        let gzip_br = encode(ContentEncoding::Br, &gzip).unwrap();
+
+        assert_eq!(
+            decode_content(&HeaderMap::new(), &body, usize::MAX).unwrap(),
+            None
+        );
+        assert_eq!(
+            decode_content(&headers(CONTENT_ENCODING, "identity"), &body, usize::MAX).unwrap(),
+            None
+        );
+        assert_eq!(
+            decode_content(&headers(CONTENT_ENCODING, "GZIP"), &gzip, usize::MAX).unwrap(),
+            Some(body.clone())
+        );
+        assert_eq!(
+            decode_content(&headers(CONTENT_ENCODING, "gzip, br"), &gzip_br, usize::MAX).unwrap(),
+            Some(body.clone())
+        );
+        assert!(matches!(
+            decode_content(&headers(CONTENT_ENCODING, "compress"), &body, usize::MAX),
+            Err(CompressionError::Unsupported(_))
+        ));
+        assert!(matches!(
+            decode_content(&headers(CONTENT_ENCODING, "gzip"), &body, usize::MAX),
+            Err(CompressionError::Decode { .. })
+        ));
+    }
+
+    #[test]
+    fn decode_within_limit() {
+        let body = "a".repeat(1000);
+        for encoding in ALL_ENCODINGS {
+            let encoded = encode(encoding, body.as_bytes()).unwrap();
+            assert_eq!(
+                decode(encoding, &encoded, 1000).unwrap(),
+                body,
+                "{encoding}"
+            );
+            assert!(
+                matches!(
+                    decode(encoding, &encoded, 999),
+                    Err(CompressionError::TooLarge { limit: 999, .. })
+                ),
+                "{encoding}"
+            );
+        }
+    }
+
+    #[test]
+    fn negotiate_encoding() {
+        let supported = [ContentEncoding::Br, ContentEncoding::Gzip];
+        let negotiate_with =
+            |accept: &str| negotiate(&headers(ACCEPT_ENCODING, accept), &supported);
+
+        assert_eq!(negotiate(&HeaderMap::new(), &supported), None);
+        assert_eq!(
+            negotiate_with("gzip, deflate, br"),
+            Some(ContentEncoding::Br)
+        );
+        assert_eq!(
+            negotiate_with("gzip, br;q=0.5"),
+            Some(ContentEncoding::Gzip)
+        );
+        assert_eq!(negotiate_with("deflate"), None);
+        assert_eq!(negotiate_with("*"), Some(ContentEncoding::Br));
+        assert_eq!(
+            negotiate_with("br;q=0, *;q=0.1"),
+            Some(ContentEncoding::Gzip)
+        );
+        assert_eq!(negotiate_with("identity"), None);
+    }
+
+    #[tokio::test]
+    async fn compress_large_responses_only() {
+        let cfg: CompressionConfig =
+            serde_yaml_ng::from_str("encodings: [gzip]\nmin_size: 100").unwrap();
+        let req_headers = headers(ACCEPT_ENCODING, "gzip");
+        let large = "a".repeat(200);
+
+        let resp =
+            compress_response(&cfg, &req_headers, Response::new(Full::from(large.clone()))).await;
+        assert_eq!(resp.headers()[CONTENT_ENCODING], "gzip");
+        assert_eq!(resp.headers()[VARY], "accept-encoding");
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            decode(ContentEncoding::Gzip, &body, usize::MAX).unwrap(),
+            large
+        );
+
+        let resp = compress_response(&cfg, &req_headers, Response::new(Full::from("small"))).await;
+        assert!(resp.headers().get(CONTENT_ENCODING).is_none());
+
+        let resp =
+            compress_response(&cfg, &HeaderMap::new(), Response::new(Full::from(large))).await;
+        assert!(resp.headers().get(CONTENT_ENCODING).is_none());
+        assert_eq!(resp.headers()[VARY], "accept-encoding");
+    }
+}
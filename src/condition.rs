@@ -0,0 +1,229 @@
+use crate::{body_parser::parse_body, context::Context};
+use hyper::{body::Bytes, header::COOKIE, http::request::Parts};
+use jaq_json::Val;
+use serde_json::{json, Map, Value};
+use std::{
+    cell::OnceCell,
+    collections::HashMap,
+    net::SocketAddr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::debug;
+
+/// Input of the targets' conditions.
+/// It's built on the first use only and shared between all targets of the request.
+pub struct ConditionInput<'a> {
+    ctx: &'a Context<'a>,
+    addr: &'a SocketAddr,
+    req: &'a Parts,
+    body: &'a Bytes,
+    value: OnceCell<Val>,
+}
+
+impl<'a> ConditionInput<'a> {
+    pub(crate) fn new(
+        ctx: &'a Context<'a>,
+        addr: &'a SocketAddr,
+        req: &'a Parts,
+        body: &'a Bytes,
+    ) -> Self {
+        Self {
+            ctx,
+            addr,
+            req,
+            body,
+            value: OnceCell::new(),
+        }
+    }
+
+    /// Returns input value, it's cheap to clone
+    pub(crate) fn value(&self) -> &Val {
+        self.value.get_or_init(|| {
+            let input = condition_input(self.ctx, self.addr, self.req, self.body);
+            debug!("condition input=`{:#?}`", input);
+            Val::from(input)
+        })
+    }
+}
+
+/// Builds input object of the target's condition
+fn condition_input(ctx: &Context, addr: &SocketAddr, req: &Parts, body: &Bytes) -> Value {
+    // Input content
+    // .body
+    // .body_text
+    // .body_size
+    // .env{}
+    // .request.method
+    // .request.headers{}
+    // .request.uri.full
+    // .request.uri.host
+    // .request.uri.path
+    // .request.uri.query
+    // .request.query_params{}
+    // .request.cookies{}
+    // .request.source_ip
+    // .request.source_port
+    // .request.listener
+    // .request.http_version
+    // .timestamp
+    let body = parse_body(&req.headers, body);
+    let headers: HashMap<String, String> = req
+        .headers
+        .iter()
+        .map(|(k, v)| {
+            (
+                k.to_string(),
+                String::from_utf8_lossy(v.as_bytes()).into_owned(),
+            )
+        })
+        .collect();
+    let env = ctx.iter().collect::<HashMap<&String, &String>>();
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    json!({
+        "body": body.body,
+        "body_text": body.text,
+        "body_size": body.size,
+        "env": env,
+        "request": {
+            "method": req.method.as_str(),
+            "headers": headers,
+            "uri": {
+                "full": req.uri.to_string(),
+                "host": req.uri.host(),
+                "path": req.uri.path(),
+                "query": req.uri.query()
+            },
+            "query_params": query_params(req.uri.query()),
+            "cookies": cookies(req),
+            "source_ip": addr.ip().to_string(),
+            "source_port": addr.port(),
+            "listener": ctx.get(&"CTX_LISTENER_NAME".into()),
+            "http_version": format!("{:?}", req.version)
+        },
+        "timestamp": timestamp
+    })
+}
+
+/// Collects query parameters into a map of lists, since any parameter may be repeated
+fn query_params(query: Option<&str>) -> Map<String, Value> {
+    let mut params = Map::new();
+    for (name, value) in form_urlencoded::parse(query.unwrap_or_default().as_bytes()) {
+        let values = params
+            .entry(name.into_owned())
+            .or_insert_with(|| Value::Array(vec![]));
+        if let Value::Array(values) = values {
+            values.push(Value::String(value.into_owned()));
+        }
+    }
+
+    params
+}
+
+/// Collects cookies from all `Cookie` headers, the first one wins if cookie is repeated
+fn cookies(req: &Parts) -> Map<String, Value> {
+    let mut cookies = Map::new();
+    for pair in req
+        .headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+    {
+        if let Some((name, value)) = pair.split_once('=') {
+            let name = name.trim();
+            if !name.is_empty() && !cookies.contains_key(name) {
+                let value = value.trim().trim_matches('"');
+                cookies.insert(name.into(), Value::String(value.into()));
+            }
+        }
+    }
+
+    cookies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::test_context::get_test_ctx;
+    use hyper::{header::HeaderValue, Request, Version};
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn request_attributes() {
+        let (req, _) = Request::builder()
+            .uri("https://www.google.com/test-path?tag=a&id=1&tag=b+c&empty")
+            .header("Cookie", "session=abc; theme=\"dark\"")
+            .header("Cookie", "session=xyz; lang=en")
+            .method("PUT")
+            .version(Version::HTTP_2)
+            .body(())
+            .unwrap()
+            .into_parts();
+        let addr = SocketAddr::new(Ipv4Addr::new(4, 3, 2, 1).into(), 12345);
+        let ctx = get_test_ctx().with_request(&addr, &req, "TEST-LISTENER".into());
+
+        let input = condition_input(&ctx, &addr, &req, &Bytes::from_static(b"ping"));
+        let request = &input["request"];
+
+        assert_eq!(request["method"], "PUT");
+        assert_eq!(
+            request["query_params"],
+            json!({"tag": ["a", "b c"], "id": ["1"], "empty": [""]})
+        );
+        assert_eq!(
+            request["cookies"],
+            json!({"session": "abc", "theme": "dark", "lang": "en"})
+        );
+        assert_eq!(request["source_ip"], "4.3.2.1");
+        assert_eq!(request["source_port"], 12345);
+        assert_eq!(request["listener"], "TEST-LISTENER");
+        assert_eq!(request["http_version"], "HTTP/2.0");
+        assert_eq!(input["body_text"], "ping");
+        assert_eq!(input["body_size"], 4);
+        assert!(input["timestamp"].as_u64().unwrap() > 0);
+    }
+
+    #[test]
+    fn input_is_built_once() {
+        let (req, _) = Request::builder().uri("/").body(()).unwrap().into_parts();
+        let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 80);
+        let body = Bytes::from_static(b"{\"key\": \"value\"}");
+        let input = ConditionInput::new(get_test_ctx(), &addr, &req, &body);
+
+        assert!(std::ptr::eq(input.value(), input.value()));
+        let value: Value = input.value().clone().into();
+        assert_eq!(value["body"], json!({"key": "value"}));
+    }
+
+    #[test]
+    fn empty_query_and_cookies() {
+        let (req, _) = Request::builder().uri("/").body(()).unwrap().into_parts();
+        let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 80);
+
+        let input = condition_input(get_test_ctx(), &addr, &req, &Bytes::new());
+
+        assert_eq!(input["request"]["query_params"], json!({}));
+        assert_eq!(input["request"]["cookies"], json!({}));
+        assert_eq!(input["request"]["listener"], Value::Null);
+        assert_eq!(input["request"]["http_version"], "HTTP/1.1");
+    }
+
+    #[test]
+    fn non_utf8_header() {
+        let (req, _) = Request::builder()
+            .uri("/")
+            .header("x-name", HeaderValue::from_bytes(b"caf\xe9").unwrap())
+            .body(())
+            .unwrap()
+            .into_parts();
+        let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 80);
+
+        let input = condition_input(get_test_ctx(), &addr, &req, &Bytes::new());
+
+        assert_eq!(input["request"]["headers"]["x-name"], "caf\u{fffd}");
+    }
+}
@@ -1,8 +1,22 @@
+pub mod aggregate;
 pub mod aws_sigv4;
+pub mod cache;
+pub mod compare;
+pub mod compression;
+pub mod cors;
+pub mod errors;
+pub mod extract;
 pub mod headers;
+pub mod hmac;
+pub mod jq;
 pub mod listener;
+pub mod oauth2;
+pub mod proxy_headers;
+pub mod quorum;
 pub mod response;
 pub mod target;
+pub mod target_response;
+pub mod uri;
 
 use crate::context::Context;
 use listener::ListenerConfig;
@@ -44,6 +58,23 @@ pub trait ConfigValidator {
     fn validate(&self) -> Result<(), ConfigError>;
 }
 
+/// String value which must never be exposed in logs, like shared secrets or passwords.
+#[derive(Deserialize, Clone, PartialEq, Eq, Hash)]
+#[serde(transparent)]
+pub struct SecretString(String);
+
+impl SecretString {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("\"***\"")
+    }
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct AppConfig {
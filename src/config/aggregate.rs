@@ -0,0 +1,259 @@
+use super::{
+    jq::{JqError, JqFilter},
+    ConfigValidator,
+};
+use crate::config::ConfigError;
+use jaq_json::Val;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value as JsonValue};
+use std::collections::HashMap;
+use strum_macros::Display;
+
+/// How to merge JSON bodies of the responses into a single document
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Display)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum AggregateMode {
+    /// Object with target ids as keys and bodies as values
+    #[default]
+    Keyed,
+    /// Array with all bodies, array bodies are flattened
+    Concat,
+    /// Deep merge of all bodies in order of targets
+    Merge,
+    /// Custom `jq` expression over the list of responses
+    Reduce,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct AggregateConfig {
+    #[serde(default)]
+    mode: AggregateMode,
+    reducer: Option<JqFilter>,
+}
+
+/// Response of a single target to aggregate: parsed JSON body or reason why it can't be used
+#[derive(Debug)]
+pub(crate) struct AggregateItem {
+    pub(crate) target_id: String,
+    pub(crate) status: u16,
+    pub(crate) headers: HashMap<String, String>,
+    pub(crate) body: Result<JsonValue, String>,
+}
+
+impl AggregateItem {
+    fn error_object(&self, error: &str) -> JsonValue {
+        json!({"status": self.status, "error": error})
+    }
+}
+
+impl AggregateConfig {
+    pub fn mode(&self) -> AggregateMode {
+        self.mode
+    }
+
+    pub fn reducer(&self) -> Option<&JqFilter> {
+        self.reducer.as_ref()
+    }
+
+    /// Merges all items into a single JSON document according to the mode.
+    /// Items with errors are represented by error objects.
+    pub(crate) fn aggregate(&self, items: Vec<AggregateItem>) -> Result<JsonValue, JqError> {
+        let result = match self.mode {
+            AggregateMode::Keyed => JsonValue::Object(
+                items
+                    .into_iter()
+                    .map(|item| {
+                        let value = match &item.body {
+                            Ok(body) => body.clone(),
+                            Err(e) => item.error_object(e),
+                        };
+                        (item.target_id, value)
+                    })
+                    .collect(),
+            ),
+            AggregateMode::Concat => {
+                let mut result = vec![];
+                for item in items {
+                    match item.body {
+                        Ok(JsonValue::Array(values)) => result.extend(values),
+                        Ok(value) => result.push(value),
+                        Err(e) => result.push(json!({
+                            "target_id": item.target_id,
+                            "status": item.status,
+                            "error": e
+                        })),
+                    }
+                }
+                JsonValue::Array(result)
+            }
+            AggregateMode::Merge => {
+                let mut result = JsonValue::Object(Map::new());
+                for item in items {
+                    let value = match &item.body {
+                        Ok(body) => body.clone(),
+                        Err(e) => json!({"errors": {&item.target_id: item.error_object(e)}}),
+                    };
+                    deep_merge(&mut result, value);
+                }
+                result
+            }
+            AggregateMode::Reduce => {
+                // Input content
+                // .[].target_id
+                // .[].status
+                // .[].headers{}
+                // .[].body or .[].error
+                let input: Vec<JsonValue> = items
+                    .into_iter()
+                    .map(|item| {
+                        let (key, value) = match item.body {
+                            Ok(body) => ("body", body),
+                            Err(e) => ("error", JsonValue::String(e)),
+                        };
+                        json!({
+                            "target_id": item.target_id,
+                            "status": item.status,
+                            "headers": item.headers,
+                            key: value
+                        })
+                    })
+                    .collect();
+                let reducer = self
+                    .reducer
+                    .as_ref()
+                    .expect("reducer is validated to be present in `reduce` mode");
+                reducer.first(Val::from(JsonValue::Array(input)))?
+            }
+        };
+
+        Ok(result)
+    }
+}
+
+/// Merges objects recursively, any other value replaces the existing one
+fn deep_merge(target: &mut JsonValue, value: JsonValue) {
+    match (target, value) {
+        (JsonValue::Object(target), JsonValue::Object(value)) => {
+            for (k, v) in value {
+                match target.get_mut(&k) {
+                    Some(existing) => deep_merge(existing, v),
+                    None => {
+                        target.insert(k, v);
+                    }
+                }
+            }
+        }
+        (target, value) => *target = value,
+    }
+}
+
+impl ConfigValidator for AggregateConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        match (self.mode, &self.reducer) {
+            (AggregateMode::Reduce, None) => Err(ConfigError::ValidateConfig {
+                cause: format!(
+                    "`aggregate.reducer` should be specified for mode `{}`",
+                    AggregateMode::Reduce
+                ),
+            }),
+            (mode, Some(_)) if mode != AggregateMode::Reduce => Err(ConfigError::ValidateConfig {
+                cause: format!(
+                    "`aggregate.reducer` can be used with mode `{}` only",
+                    AggregateMode::Reduce
+                ),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items() -> Vec<AggregateItem> {
+        let item = |id: &str, status, body| AggregateItem {
+            target_id: id.into(),
+            status,
+            headers: HashMap::from([("content-type".into(), "application/json".into())]),
+            body,
+        };
+        vec![
+            item(
+                "A",
+                200,
+                Ok(json!({"hits": [1, 2], "meta": {"took": 5, "source": "A"}})),
+            ),
+            item(
+                "B",
+                200,
+                Ok(json!({"hits": [3], "meta": {"source": "B"}, "extra": true})),
+            ),
+            item("C", 503, Err("failed status".into())),
+        ]
+    }
+
+    fn aggregate(yaml: &str) -> JsonValue {
+        let cfg: AggregateConfig = serde_yaml_ng::from_str(yaml).unwrap();
+        assert!(cfg.validate().is_ok());
+        cfg.aggregate(items()).unwrap()
+    }
+
+    #[test]
+    fn keyed() {
+        assert_eq!(
+            aggregate("{}"),
+            json!({
+                "A": {"hits": [1, 2], "meta": {"took": 5, "source": "A"}},
+                "B": {"hits": [3], "meta": {"source": "B"}, "extra": true},
+                "C": {"status": 503, "error": "failed status"}
+            })
+        );
+    }
+
+    #[test]
+    fn concat() {
+        let cfg: AggregateConfig = serde_yaml_ng::from_str("mode: concat").unwrap();
+        let mut items = items();
+        items[0].body = Ok(json!([1, 2]));
+        items[1].body = Ok(json!({"id": 3}));
+        assert_eq!(
+            cfg.aggregate(items).unwrap(),
+            json!([1, 2, {"id": 3}, {"target_id": "C", "status": 503, "error": "failed status"}])
+        );
+    }
+
+    #[test]
+    fn merge() {
+        assert_eq!(
+            aggregate("mode: merge"),
+            json!({
+                "hits": [3],
+                "meta": {"took": 5, "source": "B"},
+                "extra": true,
+                "errors": {"C": {"status": 503, "error": "failed status"}}
+            })
+        );
+    }
+
+    #[test]
+    fn reduce() {
+        assert_eq!(
+            aggregate(
+                "mode: reduce\nreducer: '{hits: [.[] | .body.hits? // [] | .[]], failed: [.[] | select(.error) | .target_id]}'"
+            ),
+            json!({"hits": [1, 2, 3], "failed": ["C"]})
+        );
+    }
+
+    #[test]
+    fn wrong_config() {
+        for yaml in ["mode: reduce", "mode: keyed\nreducer: .", "reducer: ."] {
+            let cfg: AggregateConfig = serde_yaml_ng::from_str(yaml).unwrap();
+            assert!(cfg.validate().is_err(), "`{yaml}` should be rejected");
+        }
+        assert!(serde_yaml_ng::from_str::<AggregateConfig>("mode: sum").is_err());
+    }
+}
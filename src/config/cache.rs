@@ -0,0 +1,126 @@
+use super::ConfigValidator;
+use crate::config::ConfigError;
+use serde::Deserialize;
+use std::time::Duration;
+
+const DEFAULT_CACHE_KEY: &str = "${CTX_REQUEST_METHOD}:/${CTX_REQUEST_PATH}?${CTX_REQUEST_QUERY}";
+const DEFAULT_CACHE_TTL_SEC: u64 = 60;
+const DEFAULT_CACHE_MAX_ENTRIES: usize = 1000;
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CacheConfig {
+    #[serde(default = "CacheConfig::default_key")]
+    key: String,
+    #[serde(with = "humantime_serde", default = "CacheConfig::default_ttl")]
+    ttl: Duration,
+    #[serde(default = "CacheConfig::default_max_entries")]
+    max_entries: usize,
+    max_bytes: Option<usize>,
+    #[serde(default, with = "humantime_serde")]
+    stale_if_error: Option<Duration>,
+}
+
+impl CacheConfig {
+    fn default_key() -> String {
+        DEFAULT_CACHE_KEY.into()
+    }
+
+    fn default_ttl() -> Duration {
+        Duration::from_secs(DEFAULT_CACHE_TTL_SEC)
+    }
+
+    fn default_max_entries() -> usize {
+        DEFAULT_CACHE_MAX_ENTRIES
+    }
+
+    /// Returns template of the cache key
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns default time to live of the cached response
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    pub fn max_entries(&self) -> usize {
+        self.max_entries
+    }
+
+    pub fn max_bytes(&self) -> Option<usize> {
+        self.max_bytes
+    }
+
+    /// Returns how long expired response may be served if targets fail
+    pub fn stale_if_error(&self) -> Option<Duration> {
+        self.stale_if_error
+    }
+}
+
+impl ConfigValidator for CacheConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.key.trim().is_empty() {
+            return Err(ConfigError::ValidateConfig {
+                cause: "`cache.key` must not be empty".into(),
+            });
+        }
+        if self.ttl.is_zero() {
+            return Err(ConfigError::ValidateConfig {
+                cause: "`cache.ttl` must be greater than zero".into(),
+            });
+        }
+        if self.max_entries == 0 {
+            return Err(ConfigError::ValidateConfig {
+                cause: "`cache.max_entries` must be greater than zero".into(),
+            });
+        }
+        if self.max_bytes == Some(0) {
+            return Err(ConfigError::ValidateConfig {
+                cause: "`cache.max_bytes` must be greater than zero".into(),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_minimal() {
+        let cfg: CacheConfig = serde_yaml_ng::from_str("{}").unwrap();
+        assert_eq!(cfg.key(), DEFAULT_CACHE_KEY);
+        assert_eq!(cfg.ttl(), Duration::from_secs(60));
+        assert_eq!(cfg.max_entries(), 1000);
+        assert_eq!(cfg.max_bytes(), None);
+        assert_eq!(cfg.stale_if_error(), None);
+        assert!(cfg.validate().is_ok());
+    }
+
+    #[test]
+    fn parses_full() {
+        let cfg: CacheConfig = serde_yaml_ng::from_str(
+            "key: \"${CTX_REQUEST_PATH}:${CTX_REQUEST_HEADERS_ACCEPT}\"\nttl: 5m\nmax_entries: 10\nmax_bytes: 1048576\nstale_if_error: 1h",
+        )
+        .unwrap();
+        assert_eq!(
+            cfg.key(),
+            "${CTX_REQUEST_PATH}:${CTX_REQUEST_HEADERS_ACCEPT}"
+        );
+        assert_eq!(cfg.ttl(), Duration::from_secs(300));
+        assert_eq!(cfg.max_entries(), 10);
+        assert_eq!(cfg.max_bytes(), Some(1048576));
+        assert_eq!(cfg.stale_if_error(), Some(Duration::from_secs(3600)));
+        assert!(cfg.validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_zero_limits() {
+        for yaml in ["ttl: 0s", "max_entries: 0", "max_bytes: 0", "key: \"\""] {
+            let cfg: CacheConfig = serde_yaml_ng::from_str(yaml).unwrap();
+            assert!(cfg.validate().is_err(), "`{yaml}` should be rejected");
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use super::{target::TargetConfig, ConfigValidator};
+use crate::config::ConfigError;
+use core::fmt;
+use hyper::header::HeaderName;
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::{collections::HashSet, str::FromStr};
+
+/// Segment of the JSON path pattern
+#[derive(Debug, Clone, PartialEq)]
+enum JsonPathSegment {
+    /// `.key`
+    Key(String),
+    /// `.*`
+    AnyKey,
+    /// `[N]`
+    Index(usize),
+    /// `[]`
+    AnyIndex,
+}
+
+/// Item of the actual path inside JSON document
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum JsonPathItem {
+    Key(String),
+    Index(usize),
+}
+
+/// Formats path like `.items[0].id`, root is `.`
+pub(crate) fn format_json_path(path: &[JsonPathItem]) -> String {
+    if path.is_empty() {
+        return ".".into();
+    }
+    path.iter()
+        .map(|item| match item {
+            JsonPathItem::Key(key) => format!(".{key}"),
+            JsonPathItem::Index(index) => format!("[{index}]"),
+        })
+        .collect()
+}
+
+/// Pattern of the path inside JSON document, like `.meta.timestamp`, `.items[].id` or `.*.took`
+#[derive(Clone, PartialEq)]
+pub struct JsonPathPattern {
+    segments: Vec<JsonPathSegment>,
+    pattern: String,
+}
+
+impl fmt::Debug for JsonPathPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("JsonPathPattern")
+            .field(&self.pattern)
+            .finish()
+    }
+}
+
+impl FromStr for JsonPathPattern {
+    type Err = ConfigError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let error = || ConfigError::ValidateConfig {
+            cause: format!("invalid JSON path: `{value}`"),
+        };
+
+        let mut segments = vec![];
+        let mut rest = value.trim();
+        if rest.is_empty() {
+            return Err(error());
+        }
+        while !rest.is_empty() {
+            if let Some(tail) = rest.strip_prefix('[') {
+                let (index, tail) = tail.split_once(']').ok_or_else(error)?;
+                segments.push(match index.trim() {
+                    "" => JsonPathSegment::AnyIndex,
+                    index => JsonPathSegment::Index(index.parse().map_err(|_| error())?),
+                });
+                rest = tail;
+            } else if let Some(tail) = rest.strip_prefix('.') {
+                let end = tail.find(['.', '[']).unwrap_or(tail.len());
+                segments.push(match &tail[..end] {
+                    "" => return Err(error()),
+                    "*" => JsonPathSegment::AnyKey,
+                    key => JsonPathSegment::Key(key.into()),
+                });
+                rest = &tail[end..];
+            } else {
+                return Err(error());
+            }
+        }
+
+        Ok(Self {
+            segments,
+            pattern: value.trim().into(),
+        })
+    }
+}
+
+impl JsonPathPattern {
+    /// Returns `true` if the path is the pattern's path or it's inside it
+    pub(crate) fn matches(&self, path: &[JsonPathItem]) -> bool {
+        self.segments.len() <= path.len()
+            && self
+                .segments
+                .iter()
+                .zip(path)
+                .all(|(segment, item)| match (segment, item) {
+                    (JsonPathSegment::Key(key), JsonPathItem::Key(item)) => key == item,
+                    (JsonPathSegment::AnyKey, JsonPathItem::Key(_)) => true,
+                    (JsonPathSegment::Index(index), JsonPathItem::Index(item)) => index == item,
+                    (JsonPathSegment::AnyIndex, JsonPathItem::Index(_)) => true,
+                    _ => false,
+                })
+    }
+}
+
+impl<'de> Deserialize<'de> for JsonPathPattern {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct JsonPathPatternVisitor;
+        impl Visitor<'_> for JsonPathPatternVisitor {
+            type Value = JsonPathPattern;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("JSON path like `.items[].id`")
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                JsonPathPattern::from_str(v).map_err(|e| E::custom(e))
+            }
+        }
+
+        deserializer.deserialize_string(JsonPathPatternVisitor)
+    }
+}
+
+impl Serialize for JsonPathPattern {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.pattern)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CompareConfig {
+    baseline: String,
+    candidates: Option<Vec<String>>,
+    #[serde(default)]
+    ignore_headers: Vec<String>,
+    #[serde(default)]
+    ignore_json_paths: Vec<JsonPathPattern>,
+}
+
+impl CompareConfig {
+    /// Returns ID of the target which responses are treated as reference ones
+    pub fn baseline(&self) -> &str {
+        &self.baseline
+    }
+
+    /// Returns IDs of the targets to compare with baseline, all other targets by default
+    pub fn candidates(&self, targets: &[TargetConfig]) -> Vec<String> {
+        match &self.candidates {
+            Some(candidates) => candidates.clone(),
+            None => targets
+                .iter()
+                .map(TargetConfig::id)
+                .filter(|id| *id != self.baseline)
+                .collect(),
+        }
+    }
+
+    /// Returns `true` if the header shouldn't be compared
+    pub fn is_ignored_header(&self, name: &str) -> bool {
+        self.ignore_headers
+            .iter()
+            .any(|h| h.eq_ignore_ascii_case(name))
+    }
+
+    /// Returns `true` if the path inside JSON body shouldn't be compared
+    pub(crate) fn is_ignored_json_path(&self, path: &[JsonPathItem]) -> bool {
+        self.ignore_json_paths.iter().any(|p| p.matches(path))
+    }
+
+    /// Makes sure that baseline and candidates are the listener's targets
+    pub fn validate_targets(&self, targets: &[TargetConfig]) -> Result<(), ConfigError> {
+        let ids: HashSet<String> = targets.iter().map(TargetConfig::id).collect();
+        if !ids.contains(&self.baseline) {
+            return Err(ConfigError::ValidateConfig {
+                cause: format!(
+                    "`compare.baseline` points to unknown target_id `{}`",
+                    self.baseline
+                ),
+            });
+        }
+        let candidates = self.candidates(targets);
+        if candidates.is_empty() {
+            return Err(ConfigError::ValidateConfig {
+                cause: "at least one `compare` candidate target is required".into(),
+            });
+        }
+        for id in candidates {
+            if !ids.contains(&id) {
+                return Err(ConfigError::ValidateConfig {
+                    cause: format!("`compare.candidates` points to unknown target_id `{id}`"),
+                });
+            }
+            if id == self.baseline {
+                return Err(ConfigError::ValidateConfig {
+                    cause: format!("baseline target `{id}` can't be `compare` candidate"),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl ConfigValidator for CompareConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        for name in &self.ignore_headers {
+            HeaderName::from_str(name).map_err(|_| ConfigError::ValidateConfig {
+                cause: format!("invalid header name in `compare.ignore_headers`: `{name}`"),
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(items: &[&str]) -> Vec<JsonPathItem> {
+        items
+            .iter()
+            .map(|item| match item.parse() {
+                Ok(index) => JsonPathItem::Index(index),
+                Err(_) => JsonPathItem::Key(item.to_string()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn json_path_patterns() {
+        let pattern = |p: &str| JsonPathPattern::from_str(p).unwrap();
+
+        assert!(pattern(".meta.timestamp").matches(&path(&["meta", "timestamp"])));
+        assert!(pattern(".meta").matches(&path(&["meta", "timestamp"])));
+        assert!(!pattern(".meta.timestamp").matches(&path(&["meta"])));
+        assert!(!pattern(".meta.took").matches(&path(&["meta", "timestamp"])));
+        assert!(pattern(".items[].id").matches(&path(&["items", "3", "id"])));
+        assert!(pattern(".items[1].id").matches(&path(&["items", "1", "id"])));
+        assert!(!pattern(".items[1].id").matches(&path(&["items", "2", "id"])));
+        assert!(pattern(".*.took").matches(&path(&["A", "took"])));
+        assert!(!pattern(".*.took").matches(&path(&["0", "took"])));
+        assert!(pattern("[]").matches(&path(&["0"])));
+
+        for p in ["", "meta", ".", ".meta.", ".items[", ".items[x]", "..a"] {
+            assert!(
+                JsonPathPattern::from_str(p).is_err(),
+                "`{p}` should be rejected"
+            );
+        }
+
+        assert_eq!(
+            format_json_path(&path(&["items", "3", "id"])),
+            ".items[3].id"
+        );
+        assert_eq!(format_json_path(&[]), ".");
+    }
+}
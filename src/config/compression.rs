@@ -0,0 +1,140 @@
+use super::ConfigValidator;
+use crate::config::ConfigError;
+use serde::Deserialize;
+use strum_macros::{Display, EnumString, IntoStaticStr};
+
+const DEFAULT_COMPRESSION_MIN_SIZE: usize = 1024;
+const DEFAULT_MAX_DECODED_SIZE: usize = 10 * 1024 * 1024;
+
+/// Content encodings supported for compression and decompression of bodies
+#[derive(
+    Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Display, EnumString, IntoStaticStr,
+)]
+#[serde(deny_unknown_fields, rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum ContentEncoding {
+    Gzip,
+    Deflate,
+    Br,
+    Zstd,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CompressionConfig {
+    #[serde(default)]
+    decode_request: bool,
+    #[serde(default)]
+    encodings: Vec<ContentEncoding>,
+    #[serde(default = "CompressionConfig::default_min_size")]
+    min_size: usize,
+    #[serde(default = "CompressionConfig::default_max_decoded_size")]
+    max_decoded_size: usize,
+}
+
+impl CompressionConfig {
+    fn default_min_size() -> usize {
+        DEFAULT_COMPRESSION_MIN_SIZE
+    }
+
+    fn default_max_decoded_size() -> usize {
+        DEFAULT_MAX_DECODED_SIZE
+    }
+
+    /// Returns true if request body should be decoded before passing it to conditions and targets
+    pub fn decode_request(&self) -> bool {
+        self.decode_request
+    }
+
+    /// Returns encodings allowed for response compression, in the order of preference
+    pub fn encodings(&self) -> &[ContentEncoding] {
+        &self.encodings
+    }
+
+    /// Returns minimal size of the response body to compress
+    pub fn min_size(&self) -> usize {
+        self.min_size
+    }
+
+    /// Returns maximal size of the decoded request body
+    pub fn max_decoded_size(&self) -> usize {
+        self.max_decoded_size
+    }
+}
+
+impl ConfigValidator for CompressionConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        if !self.decode_request && self.encodings.is_empty() {
+            return Err(ConfigError::ValidateConfig {
+                cause: "`compression` should enable `decode_request` or define at least one of `encodings`".into(),
+            });
+        }
+        if self.max_decoded_size == 0 {
+            return Err(ConfigError::ValidateConfig {
+                cause: "`compression.max_decoded_size` should be positive".into(),
+            });
+        }
+        for (pos, encoding) in self.encodings.iter().enumerate() {
+            if self.encodings[..pos].contains(encoding) {
+                return Err(ConfigError::ValidateConfig {
+                    cause: format!("duplicate encoding `{encoding}` in `compression.encodings`"),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn parses_full() {
+        let cfg: CompressionConfig = serde_yaml_ng::from_str(
+            "decode_request: true\nencodings: [zstd, br, gzip, deflate]\nmin_size: 10\nmax_decoded_size: 100",
+        )
+        .unwrap();
+        assert!(cfg.decode_request());
+        assert_eq!(
+            cfg.encodings(),
+            &[
+                ContentEncoding::Zstd,
+                ContentEncoding::Br,
+                ContentEncoding::Gzip,
+                ContentEncoding::Deflate
+            ]
+        );
+        assert_eq!(cfg.min_size(), 10);
+        assert_eq!(cfg.max_decoded_size(), 100);
+        assert!(cfg.validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_useless_or_duplicate_config() {
+        let cfg: CompressionConfig = serde_yaml_ng::from_str("min_size: 10").unwrap();
+        assert!(cfg.validate().is_err());
+
+        let cfg: CompressionConfig =
+            serde_yaml_ng::from_str("encodings: [gzip, br, gzip]").unwrap();
+        assert!(cfg.validate().is_err());
+
+        let cfg: CompressionConfig =
+            serde_yaml_ng::from_str("decode_request: true\nmax_decoded_size: 0").unwrap();
+        assert!(cfg.validate().is_err());
+
+        let cfg: Result<CompressionConfig, _> = serde_yaml_ng::from_str("encodings: [lzma]");
+        assert!(cfg.is_err());
+    }
+
+    #[test]
+    fn encoding_names() {
+        assert_eq!(ContentEncoding::Br.to_string(), "br");
+        assert_eq!(
+            ContentEncoding::from_str("GZIP").unwrap(),
+            ContentEncoding::Gzip
+        );
+        assert!(ContentEncoding::from_str("identity").is_err());
+    }
+}
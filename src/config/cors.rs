@@ -0,0 +1,388 @@
+use super::{listener::ListenerConfig, ConfigValidator};
+use crate::config::ConfigError;
+use http_body_util::Full;
+use hyper::{
+    body::Bytes,
+    header::{
+        HeaderValue, ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+        ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS,
+        ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD,
+        ORIGIN, VARY,
+    },
+    HeaderMap, Method, Response, StatusCode,
+};
+use regex::Regex;
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer,
+};
+use std::time::Duration;
+use tracing::debug;
+
+const ANY_ORIGIN: &str = "*";
+const UNABLE_TO_CREATE_RESPONSE_ERROR: &str = "unable to create response, looks like a BUG";
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CorsConfig {
+    #[serde(default)]
+    allowed_origins: Vec<String>,
+    #[serde(default)]
+    allowed_origins_regex: Vec<OriginRegex>,
+    allowed_methods: Option<Vec<String>>,
+    allowed_headers: Option<Vec<String>>,
+    #[serde(default)]
+    exposed_headers: Vec<String>,
+    #[serde(default)]
+    allow_credentials: bool,
+    #[serde(default, with = "humantime_serde")]
+    max_age: Option<Duration>,
+}
+
+/// Regex which should match the whole origin, so it's anchored at load time
+#[derive(Debug)]
+pub struct OriginRegex(Regex);
+
+impl<'de> Deserialize<'de> for OriginRegex {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct OriginRegexVisitor;
+        impl Visitor<'_> for OriginRegexVisitor {
+            type Value = OriginRegex;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("regular expression to match request's origin")
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                // Pattern is checked alone to report errors without anchors
+                Regex::new(v)
+                    .and_then(|_| Regex::new(&format!("^(?:{v})$")))
+                    .map(OriginRegex)
+                    .map_err(|e| E::custom(format!("invalid origin regex `{v}`: {e}")))
+            }
+        }
+
+        deserializer.deserialize_string(OriginRegexVisitor)
+    }
+}
+
+impl CorsConfig {
+    fn is_any_origin_allowed(&self) -> bool {
+        self.allowed_origins.iter().any(|o| o == ANY_ORIGIN)
+    }
+
+    /// Verifies if the origin satisfies either exact or regex list of allowed origins
+    pub fn is_origin_allowed(&self, origin: &str) -> bool {
+        self.is_any_origin_allowed()
+            || self.allowed_origins.iter().any(|o| o == origin)
+            || self
+                .allowed_origins_regex
+                .iter()
+                .any(|re| re.0.is_match(origin))
+    }
+
+    fn is_method_allowed(&self, method: &str) -> bool {
+        if let Some(methods) = &self.allowed_methods {
+            methods.iter().any(|m| m.eq_ignore_ascii_case(method))
+        } else {
+            true
+        }
+    }
+
+    fn allow_origin_value(&self, origin: &HeaderValue) -> HeaderValue {
+        if self.is_any_origin_allowed() && !self.allow_credentials {
+            HeaderValue::from_static(ANY_ORIGIN)
+        } else {
+            origin.clone()
+        }
+    }
+}
+
+pub trait CorsBehavior {
+    fn is_preflight(&self, method: &Method, headers: &HeaderMap) -> bool;
+    fn preflight_response(
+        &self,
+        headers: &HeaderMap,
+        listener: &ListenerConfig,
+    ) -> Response<Full<Bytes>>;
+    fn apply_cors_headers(&self, req_headers: &HeaderMap, resp: &mut Response<Full<Bytes>>);
+}
+
+impl CorsBehavior for CorsConfig {
+    fn is_preflight(&self, method: &Method, headers: &HeaderMap) -> bool {
+        method == Method::OPTIONS
+            && headers.contains_key(ORIGIN)
+            && headers.contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+    }
+
+    fn preflight_response(
+        &self,
+        headers: &HeaderMap,
+        listener: &ListenerConfig,
+    ) -> Response<Full<Bytes>> {
+        let origin = headers.get(ORIGIN);
+        let method = headers
+            .get(ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|m| m.to_str().ok())
+            .unwrap_or_default();
+
+        let allowed = origin
+            .and_then(|o| o.to_str().ok())
+            .is_some_and(|o| self.is_origin_allowed(o))
+            && self.is_method_allowed(method)
+            && listener.is_method_allowed(method);
+        debug!(?origin, %method, %allowed, "cors preflight");
+
+        let mut resp = Response::builder().header(VARY, ORIGIN.as_str());
+        if !allowed {
+            return resp
+                .status(StatusCode::FORBIDDEN)
+                .body(Full::from(Bytes::new()))
+                .expect(UNABLE_TO_CREATE_RESPONSE_ERROR);
+        }
+        let origin = origin.expect("origin has been verified above, looks like a BUG");
+
+        resp = resp
+            .status(StatusCode::NO_CONTENT)
+            .header(ACCESS_CONTROL_ALLOW_ORIGIN, self.allow_origin_value(origin));
+        resp = if let Some(methods) = &self.allowed_methods {
+            resp.header(ACCESS_CONTROL_ALLOW_METHODS, methods.join(", "))
+        } else {
+            resp.header(ACCESS_CONTROL_ALLOW_METHODS, method)
+        };
+        if let Some(allowed_headers) = &self.allowed_headers {
+            resp = resp.header(ACCESS_CONTROL_ALLOW_HEADERS, allowed_headers.join(", "));
+        } else if let Some(requested_headers) = headers.get(ACCESS_CONTROL_REQUEST_HEADERS) {
+            resp = resp.header(ACCESS_CONTROL_ALLOW_HEADERS, requested_headers);
+        }
+        if self.allow_credentials {
+            resp = resp.header(ACCESS_CONTROL_ALLOW_CREDENTIALS, "true");
+        }
+        if let Some(max_age) = self.max_age {
+            resp = resp.header(ACCESS_CONTROL_MAX_AGE, max_age.as_secs());
+        }
+
+        resp.body(Full::from(Bytes::new()))
+            .expect(UNABLE_TO_CREATE_RESPONSE_ERROR)
+    }
+
+    fn apply_cors_headers(&self, req_headers: &HeaderMap, resp: &mut Response<Full<Bytes>>) {
+        let Some(origin) = req_headers.get(ORIGIN) else {
+            return;
+        };
+        let headers = resp.headers_mut();
+        headers.append(VARY, HeaderValue::from_static("origin"));
+        if !origin.to_str().is_ok_and(|o| self.is_origin_allowed(o)) {
+            debug!(?origin, "cors: origin isn't allowed");
+            return;
+        }
+
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, self.allow_origin_value(origin));
+        if self.allow_credentials {
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+        if !self.exposed_headers.is_empty() {
+            headers.insert(
+                ACCESS_CONTROL_EXPOSE_HEADERS,
+                HeaderValue::from_str(&self.exposed_headers.join(", "))
+                    .expect("header names are validated on load, looks like a BUG"),
+            );
+        }
+    }
+}
+
+impl ConfigValidator for CorsConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.allowed_origins.is_empty() && self.allowed_origins_regex.is_empty() {
+            return Err(ConfigError::ValidateConfig {
+                cause: "at least one of `cors.allowed_origins` or `cors.allowed_origins_regex` must be set".into(),
+            });
+        }
+        if self.allow_credentials && self.is_any_origin_allowed() {
+            return Err(ConfigError::ValidateConfig {
+                cause: "`cors.allow_credentials` can't be used with `*` origin".into(),
+            });
+        }
+        for method in self.allowed_methods.iter().flatten() {
+            if Method::from_bytes(method.as_bytes()).is_err() {
+                return Err(ConfigError::ValidateConfig {
+                    cause: format!("invalid method in `cors.allowed_methods`: `{method}`"),
+                });
+            }
+        }
+        for header in self
+            .allowed_headers
+            .iter()
+            .flatten()
+            .chain(self.exposed_headers.iter())
+        {
+            if hyper::header::HeaderName::from_bytes(header.as_bytes()).is_err() {
+                return Err(ConfigError::ValidateConfig {
+                    cause: format!("invalid header name in `cors` config: `{header}`"),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cors(yaml: &str) -> CorsConfig {
+        let cfg: CorsConfig = serde_yaml_ng::from_str(yaml).unwrap();
+        cfg.validate().unwrap();
+        cfg
+    }
+
+    fn listener() -> ListenerConfig {
+        serde_yaml_ng::from_str("targets:\n  - url: https://example.com/").unwrap()
+    }
+
+    fn request_headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (k, v) in pairs {
+            headers.insert(*k, HeaderValue::from_static(v));
+        }
+        headers
+    }
+
+    fn empty_response() -> Response<Full<Bytes>> {
+        Response::new(Full::from(Bytes::new()))
+    }
+
+    #[test]
+    fn origin_matching() {
+        let cfg = cors(
+            "allowed_origins: [https://app.example.com]\nallowed_origins_regex: ['^https://[a-z]+\\.example\\.org$']",
+        );
+        assert!(cfg.is_origin_allowed("https://app.example.com"));
+        assert!(cfg.is_origin_allowed("https://web.example.org"));
+        assert!(!cfg.is_origin_allowed("https://app.example.com.evil.com"));
+        assert!(!cfg.is_origin_allowed("http://web.example.org"));
+
+        // Whole origin should match even without explicit anchors
+        let cfg = cors("allowed_origins_regex: ['https://.*\\.example\\.com']");
+        assert!(cfg.is_origin_allowed("https://x.example.com"));
+        assert!(!cfg.is_origin_allowed("https://x.example.com.evil.net"));
+        assert!(!cfg.is_origin_allowed("evil://https://x.example.com"));
+
+        let cfg = cors("allowed_origins: ['*']");
+        assert!(cfg.is_origin_allowed("https://anything.example.net"));
+    }
+
+    #[test]
+    fn detects_preflight() {
+        let cfg = cors("allowed_origins: ['*']");
+        let preflight = request_headers(&[
+            ("origin", "https://app.example.com"),
+            ("access-control-request-method", "POST"),
+        ]);
+        assert!(cfg.is_preflight(&Method::OPTIONS, &preflight));
+        assert!(!cfg.is_preflight(&Method::POST, &preflight));
+        assert!(!cfg.is_preflight(
+            &Method::OPTIONS,
+            &request_headers(&[("origin", "https://app.example.com")])
+        ));
+    }
+
+    #[test]
+    fn allowed_preflight() {
+        let cfg = cors("allowed_origins: [https://app.example.com]\nallowed_methods: [GET, POST]\nallow_credentials: true\nmax_age: 10m");
+        let resp = cfg.preflight_response(
+            &request_headers(&[
+                ("origin", "https://app.example.com"),
+                ("access-control-request-method", "POST"),
+                ("access-control-request-headers", "content-type, x-token"),
+            ]),
+            &listener(),
+        );
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let headers = resp.headers();
+        assert_eq!(
+            headers[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example.com"
+        );
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_METHODS], "GET, POST");
+        assert_eq!(
+            headers[ACCESS_CONTROL_ALLOW_HEADERS],
+            "content-type, x-token"
+        );
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(headers[ACCESS_CONTROL_MAX_AGE], "600");
+        assert_eq!(headers[VARY], "origin");
+    }
+
+    #[test]
+    fn rejected_preflight() {
+        let cfg = cors("allowed_origins: [https://app.example.com]\nallowed_methods: [GET]");
+        let wrong_origin = cfg.preflight_response(
+            &request_headers(&[
+                ("origin", "https://evil.example.com"),
+                ("access-control-request-method", "GET"),
+            ]),
+            &listener(),
+        );
+        assert_eq!(wrong_origin.status(), StatusCode::FORBIDDEN);
+        assert!(!wrong_origin
+            .headers()
+            .contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        let wrong_method = cfg.preflight_response(
+            &request_headers(&[
+                ("origin", "https://app.example.com"),
+                ("access-control-request-method", "DELETE"),
+            ]),
+            &listener(),
+        );
+        assert_eq!(wrong_method.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn actual_response_headers() {
+        let cfg = cors("allowed_origins: ['*']\nexposed_headers: [x-target-id]");
+
+        let mut resp = empty_response();
+        cfg.apply_cors_headers(
+            &request_headers(&[("origin", "https://app.example.com")]),
+            &mut resp,
+        );
+        assert_eq!(resp.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert_eq!(resp.headers()[ACCESS_CONTROL_EXPOSE_HEADERS], "x-target-id");
+        assert!(!resp
+            .headers()
+            .contains_key(ACCESS_CONTROL_ALLOW_CREDENTIALS));
+
+        let mut resp = empty_response();
+        cfg.apply_cors_headers(&HeaderMap::new(), &mut resp);
+        assert!(resp.headers().is_empty());
+    }
+
+    #[test]
+    fn wrong_configs() {
+        let wrong_yaml = [
+            "allow_credentials: true",
+            "allowed_origins: ['*']\nallow_credentials: true",
+            "allowed_origins: ['*']\nallowed_methods: ['GET POST']",
+            "allowed_origins: ['*']\nexposed_headers: ['x header']",
+        ];
+        for yaml in wrong_yaml {
+            let cfg: CorsConfig = serde_yaml_ng::from_str(yaml).unwrap();
+            assert!(cfg.validate().is_err(), "unexpectedly valid `{yaml}`");
+        }
+
+        let wrong_regex: Result<CorsConfig, _> =
+            serde_yaml_ng::from_str("allowed_origins_regex: ['(']");
+        assert!(wrong_regex.is_err());
+    }
+}
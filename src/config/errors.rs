@@ -0,0 +1,313 @@
+use super::ConfigValidator;
+use crate::{
+    config::ConfigError,
+    context::{Context, ContextMap},
+};
+use hyper::{body::Bytes, header::HeaderValue, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value as JsonValue};
+use shellexpand::env_with_context_no_errors;
+use strum_macros::Display;
+
+const PROBLEM_JSON_CONTENT_TYPE: &str = "application/problem+json";
+const DEFAULT_TEMPLATE_CONTENT_TYPE: &str = "application/json";
+
+/// What went wrong, it's reported in the error response body
+#[derive(Debug, Clone, Copy, PartialEq, Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum ErrorKind {
+    /// Unable to connect to the target
+    Connect,
+    /// Target didn't respond in time
+    Timeout,
+    /// Unable to authorize or sign the target's request
+    Signing,
+    /// Any other error of the target's request
+    Upstream,
+    /// There is no suitable target's response
+    NoTargets,
+    /// More than one target satisfies condition of `conditional_routing` strategy
+    MultipleMatches,
+    MethodNotAllowed,
+    BadRequest,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    /// Request can't be processed, mostly due to config mistakes
+    Internal,
+}
+
+/// Error to report in the response body
+#[derive(Debug)]
+pub struct ErrorDetails {
+    kind: ErrorKind,
+    target_id: Option<String>,
+    message: String,
+}
+
+impl ErrorDetails {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            target_id: None,
+            message: message.into(),
+        }
+    }
+
+    pub fn with_target(mut self, target_id: impl Into<String>) -> Self {
+        self.target_id = Some(target_id.into());
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+}
+
+/// Format of the error response body
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub enum ErrorFormat {
+    /// RFC 7807 problem details JSON
+    #[default]
+    ProblemJson,
+    /// Body is rendered from the `template`
+    Template,
+    /// Empty body
+    Empty,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ErrorsConfig {
+    #[serde(default)]
+    format: ErrorFormat,
+    template: Option<String>,
+    content_type: Option<String>,
+    #[serde(default)]
+    hide_details: bool,
+}
+
+impl ErrorsConfig {
+    pub fn format(&self) -> ErrorFormat {
+        self.format
+    }
+
+    /// Returns `true` if the target's id and error message shouldn't be exposed
+    pub fn hide_details(&self) -> bool {
+        self.hide_details
+    }
+
+    /// Returns error variables to use in the template
+    fn vars(&self, status: StatusCode, details: &ErrorDetails) -> ContextMap {
+        // CTX_ERROR_STATUS
+        // CTX_ERROR_KIND
+        // CTX_ERROR_TARGET_ID
+        // CTX_ERROR_MESSAGE
+        // CTX_ERROR_MESSAGE_JSON
+        let mut vars = ContextMap::from([
+            ("CTX_ERROR_STATUS".into(), status.as_u16().to_string()),
+            ("CTX_ERROR_KIND".into(), details.kind.to_string()),
+        ]);
+        if !self.hide_details {
+            if let Some(target_id) = &details.target_id {
+                vars.insert("CTX_ERROR_TARGET_ID".into(), target_id.clone());
+            }
+            vars.insert("CTX_ERROR_MESSAGE".into(), details.message.clone());
+            // Quoted and escaped, so it's safe to put into JSON template as is
+            vars.insert(
+                "CTX_ERROR_MESSAGE_JSON".into(),
+                JsonValue::from(details.message.as_str()).to_string(),
+            );
+        }
+
+        vars
+    }
+
+    /// Returns body and its content type of the error response, `None` if body should be empty
+    pub(crate) fn body(
+        &self,
+        status: StatusCode,
+        details: &ErrorDetails,
+        ctx: &Context,
+    ) -> Option<(Bytes, HeaderValue)> {
+        match self.format {
+            ErrorFormat::ProblemJson => {
+                let mut problem = Map::new();
+                problem.insert("type".into(), json!("about:blank"));
+                problem.insert(
+                    "title".into(),
+                    json!(status.canonical_reason().unwrap_or("Unknown Error")),
+                );
+                problem.insert("status".into(), json!(status.as_u16()));
+                if !self.hide_details {
+                    problem.insert("detail".into(), json!(details.message));
+                }
+                if let Some(request_id) = ctx.get(&"CTX_REQUEST_ID".into()) {
+                    problem.insert("instance".into(), json!(format!("urn:uuid:{request_id}")));
+                    problem.insert("request_id".into(), json!(request_id));
+                }
+                problem.insert("kind".into(), json!(details.kind.to_string()));
+                if let (false, Some(target_id)) = (self.hide_details, &details.target_id) {
+                    problem.insert("target_id".into(), json!(target_id));
+                }
+
+                Some((
+                    Bytes::from(JsonValue::Object(problem).to_string()),
+                    HeaderValue::from_static(PROBLEM_JSON_CONTENT_TYPE),
+                ))
+            }
+            ErrorFormat::Template => {
+                let template = self
+                    .template
+                    .as_deref()
+                    .expect("template is validated to be present in `template` format");
+                let ctx = ctx.with(self.vars(status, details));
+                let body: String =
+                    env_with_context_no_errors(template, |v| ctx.get(&v.into())).into();
+                let content_type = self
+                    .content_type
+                    .as_deref()
+                    .unwrap_or(DEFAULT_TEMPLATE_CONTENT_TYPE);
+                Some((
+                    Bytes::from(body),
+                    HeaderValue::from_str(content_type)
+                        .expect("content type is validated to be a valid header value"),
+                ))
+            }
+            ErrorFormat::Empty => None,
+        }
+    }
+}
+
+impl ConfigValidator for ErrorsConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        match (self.format, &self.template) {
+            (ErrorFormat::Template, None) => {
+                return Err(ConfigError::ValidateConfig {
+                    cause: "`errors.template` should be specified for format `template`".into(),
+                })
+            }
+            (format, Some(_)) if format != ErrorFormat::Template => {
+                return Err(ConfigError::ValidateConfig {
+                    cause: "`errors.template` can be used with format `template` only".into(),
+                })
+            }
+            _ => {}
+        }
+        if let Some(content_type) = &self.content_type {
+            if self.format != ErrorFormat::Template {
+                return Err(ConfigError::ValidateConfig {
+                    cause: "`errors.content_type` can be used with format `template` only".into(),
+                });
+            }
+            if HeaderValue::from_str(content_type).is_err() {
+                return Err(ConfigError::ValidateConfig {
+                    cause: format!("invalid `errors.content_type`: `{content_type}`"),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::test_context::get_test_ctx;
+
+    fn render(yaml: &str, details: &ErrorDetails) -> Option<(String, String)> {
+        let cfg: ErrorsConfig = serde_yaml_ng::from_str(yaml).unwrap();
+        assert!(cfg.validate().is_ok());
+        let ctx = get_test_ctx().with(ContextMap::from([(
+            "CTX_REQUEST_ID".into(),
+            "3f1e1b2a-0000-4000-8000-000000000000".into(),
+        )]));
+        cfg.body(StatusCode::GATEWAY_TIMEOUT, details, &ctx)
+            .map(|(body, content_type)| {
+                (
+                    String::from_utf8(body.to_vec()).unwrap(),
+                    content_type.to_str().unwrap().to_string(),
+                )
+            })
+    }
+
+    fn details() -> ErrorDetails {
+        ErrorDetails::new(ErrorKind::Timeout, "target's request timed out").with_target("B")
+    }
+
+    #[test]
+    fn problem_json() {
+        let (body, content_type) = render("{}", &details()).unwrap();
+        assert_eq!(content_type, "application/problem+json");
+        assert_eq!(
+            serde_json::from_str::<JsonValue>(&body).unwrap(),
+            json!({
+                "type": "about:blank",
+                "title": "Gateway Timeout",
+                "status": 504,
+                "detail": "target's request timed out",
+                "instance": "urn:uuid:3f1e1b2a-0000-4000-8000-000000000000",
+                "request_id": "3f1e1b2a-0000-4000-8000-000000000000",
+                "kind": "timeout",
+                "target_id": "B"
+            })
+        );
+
+        let (body, _) = render("hide_details: true", &details()).unwrap();
+        let body: JsonValue = serde_json::from_str(&body).unwrap();
+        assert!(body.get("detail").is_none());
+        assert!(body.get("target_id").is_none());
+        assert_eq!(body["kind"], "timeout");
+    }
+
+    #[test]
+    fn template() {
+        let yaml = "format: template\ncontent_type: text/plain\ntemplate: '${CTX_ERROR_STATUS} ${CTX_ERROR_KIND} ${CTX_ERROR_TARGET_ID:-} ${CTX_ERROR_MESSAGE:-hidden} ${CTX_REQUEST_ID}'";
+        assert_eq!(
+            render(yaml, &details()).unwrap(),
+            (
+                "504 timeout B target's request timed out 3f1e1b2a-0000-4000-8000-000000000000"
+                    .into(),
+                "text/plain".into()
+            )
+        );
+        assert_eq!(
+            render(&format!("{yaml}\nhide_details: true"), &details())
+                .unwrap()
+                .0,
+            "504 timeout  hidden 3f1e1b2a-0000-4000-8000-000000000000"
+        );
+
+        assert!(render("format: empty", &details()).is_none());
+    }
+
+    #[test]
+    fn json_template() {
+        let yaml = r#"format: template
+template: '{"error": ${CTX_ERROR_MESSAGE_JSON:-null}}'"#;
+        let details = ErrorDetails::new(ErrorKind::Upstream, r#"bad "quoted" \ message"#);
+        let (body, content_type) = render(yaml, &details).unwrap();
+        assert_eq!(content_type, "application/json");
+        assert_eq!(
+            serde_json::from_str::<JsonValue>(&body).unwrap(),
+            json!({"error": r#"bad "quoted" \ message"#})
+        );
+
+        let (body, _) = render(&format!("{yaml}\nhide_details: true"), &details).unwrap();
+        assert_eq!(body, r#"{"error": null}"#);
+    }
+
+    #[test]
+    fn wrong_config() {
+        for yaml in [
+            "format: template",
+            "template: text",
+            "content_type: text/plain",
+            "format: template\ntemplate: text\ncontent_type: \"text\\n\"",
+        ] {
+            let cfg: ErrorsConfig = serde_yaml_ng::from_str(yaml).unwrap();
+            assert!(cfg.validate().is_err(), "`{yaml}` should be rejected");
+        }
+    }
+}
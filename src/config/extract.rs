@@ -0,0 +1,164 @@
+use super::{jq::JqFilter, ConfigValidator};
+use crate::{condition::ConditionInput, config::ConfigError, context::ContextMap};
+use jaq_json::Val;
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use std::collections::{BTreeMap, HashSet};
+use tracing::{debug, warn};
+
+const EXTRACT_VAR_PREFIX: &str = "CTX_VAR_";
+
+/// Request variables to extract: name of the variable and `jq` expression to evaluate
+#[derive(Deserialize, Debug)]
+#[serde(transparent)]
+pub struct ExtractConfig(BTreeMap<String, JqFilter>);
+
+impl ExtractConfig {
+    /// Returns name of the context variable
+    fn var_name(prefix: &str, name: &str) -> String {
+        format!("{prefix}{}", name.to_uppercase())
+    }
+
+    /// Evaluates all expressions over the input and returns context variables with the first output of each one.
+    /// Strings are stored as is, other values as JSON; `null`, empty output or errors don't set variable.
+    /// Values with control characters are rejected too, since they can't be used in headers.
+    pub(crate) fn extract_vars(&self, input: &Val, prefix: &str) -> ContextMap {
+        let mut vars = ContextMap::new();
+        for (name, filter) in &self.0 {
+            let value = match filter.first(input.clone()) {
+                Ok(JsonValue::Null) => continue,
+                Ok(JsonValue::String(value)) => value,
+                Ok(value) => value.to_string(),
+                Err(e) => {
+                    warn!("unable to extract variable `{name}`: {e}");
+                    continue;
+                }
+            };
+            if value.chars().any(|c| c.is_control() && c != '\t') {
+                warn!("variable `{name}` isn't extracted: value contains control characters");
+                continue;
+            }
+            let name = Self::var_name(prefix, name);
+            debug!("extracted: {name}=`{value}`");
+            vars.insert(name, value);
+        }
+
+        vars
+    }
+}
+
+impl ConfigValidator for ExtractConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        let mut names = HashSet::new();
+        for name in self.0.keys() {
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(ConfigError::ValidateConfig {
+                    cause: format!(
+                        "invalid `extract` variable name `{name}`, only letters, digits and `_` are allowed"
+                    ),
+                });
+            }
+            if !names.insert(Self::var_name("", name)) {
+                return Err(ConfigError::ValidateConfig {
+                    cause: format!(
+                        "duplicate `extract` variable name `{name}`, names are case insensitive"
+                    ),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+pub trait ExtractBehavior {
+    fn extract(&self, input: &ConditionInput) -> ContextMap;
+}
+
+impl ExtractBehavior for ExtractConfig {
+    /// Evaluates all expressions over the request and returns `CTX_VAR_*` context variables
+    fn extract(&self, input: &ConditionInput) -> ContextMap {
+        self.extract_vars(input.value(), EXTRACT_VAR_PREFIX)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::test_context::get_test_ctx;
+    use hyper::{body::Bytes, Request};
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    #[test]
+    fn extract_variables() {
+        let cfg: ExtractConfig = serde_yaml_ng::from_str(
+            r#"
+            user_id: .body.user.id
+            Name: .body.user.name
+            tags: .body.tags
+            first_tag: .body.tags[]
+            missing: .body.missing
+            broken: .body.user.name | tonumber
+            page: .request.query_params.page[0] // "1"
+            "#,
+        )
+        .unwrap();
+        assert!(cfg.validate().is_ok());
+
+        let (req, _) = Request::builder()
+            .uri("/users?page=2")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 12345);
+        let body =
+            Bytes::from_static(br#"{"user": {"id": 42, "name": "John"}, "tags": ["a", "b"]}"#);
+        let input = ConditionInput::new(get_test_ctx(), &addr, &req, &body);
+
+        let vars = cfg.extract(&input);
+        assert_eq!(
+            vars,
+            ContextMap::from([
+                ("CTX_VAR_USER_ID".into(), "42".into()),
+                ("CTX_VAR_NAME".into(), "John".into()),
+                ("CTX_VAR_TAGS".into(), r#"["a","b"]"#.into()),
+                ("CTX_VAR_FIRST_TAG".into(), "a".into()),
+                ("CTX_VAR_PAGE".into(), "2".into()),
+            ])
+        );
+    }
+
+    #[test]
+    fn control_characters() {
+        let cfg: ExtractConfig =
+            serde_yaml_ng::from_str("name: .body.name\nnested: .body\ntabbed: .body.tabbed")
+                .unwrap();
+
+        let (req, _) = Request::builder().uri("/").body(()).unwrap().into_parts();
+        let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 12345);
+        let body =
+            Bytes::from_static(b"{\"name\": \"John\\r\\nX-Injected: 1\", \"tabbed\": \"a\\tb\"}");
+        let input = ConditionInput::new(get_test_ctx(), &addr, &req, &body);
+
+        let vars = cfg.extract(&input);
+        assert!(!vars.contains_key("CTX_VAR_NAME"));
+        // JSON is escaped
+        assert_eq!(
+            vars.get("CTX_VAR_NESTED").unwrap(),
+            r#"{"name":"John\r\nX-Injected: 1","tabbed":"a\tb"}"#
+        );
+        assert_eq!(vars.get("CTX_VAR_TABBED").unwrap(), "a\tb");
+    }
+
+    #[test]
+    fn wrong_names() {
+        for yaml in [
+            "user-id: .body.id",
+            "\"\": .body.id",
+            "id: .body.id\nID: .body.other_id",
+        ] {
+            let cfg: ExtractConfig = serde_yaml_ng::from_str(yaml).unwrap();
+            assert!(cfg.validate().is_err(), "`{yaml}` should be rejected");
+        }
+        assert!(serde_yaml_ng::from_str::<ExtractConfig>("id: .body[").is_err());
+    }
+}
@@ -1,14 +1,15 @@
-use crate::context::Context;
+use crate::{config::ConfigError, context::Context};
 use hyper::{
-    header::{HeaderName, HeaderValue},
+    header::{HeaderName, HeaderValue, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE},
     HeaderMap,
 };
+use regex::{Regex, RegexBuilder};
 use serde::{
     de::{self, MapAccess, Visitor},
-    Deserialize, Deserializer, Serialize,
+    Deserialize, Deserializer, Serialize, Serializer,
 };
 use shellexpand::env_with_context_no_errors;
-use tracing::debug;
+use tracing::{debug, warn};
 
 pub type HeadersTransformsList = Vec<HeaderTransform>;
 
@@ -26,6 +27,14 @@ impl HeaderTransform {
     fn value(&self) -> Option<&String> {
         self.value.as_ref()
     }
+
+    /// Returns value with expanded context variables, it's always present for actions which require value
+    fn expanded_value(&self, ctx: &Context) -> String {
+        let value = self
+            .value()
+            .expect("value is verified on load, looks like a BUG");
+        env_with_context_no_errors(value, |v| ctx.get(&v.into())).into_owned()
+    }
 }
 
 #[derive(Deserialize, Debug, Clone, PartialEq, Serialize)]
@@ -34,6 +43,60 @@ pub enum HeaderTransformActon {
     Add(String),
     Update(String),
     Drop(String),
+    Set(String),
+    Append(String),
+    Rename {
+        name: String,
+        to: String,
+    },
+    Copy {
+        name: String,
+        from: String,
+    },
+    Replace {
+        name: String,
+        pattern: HeaderPattern,
+    },
+    DropRegex(HeaderPattern),
+}
+
+/// Case-insensitive regex to match header names or values
+#[derive(Debug, Clone)]
+pub struct HeaderPattern(Regex);
+
+impl HeaderPattern {
+    fn new(pattern: &str) -> Result<Self, regex::Error> {
+        RegexBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()
+            .map(HeaderPattern)
+    }
+}
+
+impl PartialEq for HeaderPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Serialize for HeaderPattern {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for HeaderPattern {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let pattern = String::deserialize(deserializer)?;
+        HeaderPattern::new(&pattern)
+            .map_err(|e| de::Error::custom(format!("invalid regex `{pattern}`: {e}")))
+    }
 }
 
 impl<'de> Deserialize<'de> for HeaderTransform {
@@ -41,13 +104,42 @@ impl<'de> Deserialize<'de> for HeaderTransform {
     where
         D: Deserializer<'de>,
     {
-        #[derive(Deserialize, Debug)]
-        #[serde(deny_unknown_fields, rename_all = "lowercase")]
+        #[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+        #[serde(deny_unknown_fields, rename_all = "snake_case")]
         enum Fields {
             Drop,
             Add,
             Update,
+            Set,
+            Append,
+            Rename,
+            Copy,
+            Replace,
+            DropRegex,
             Value,
+            To,
+            From,
+            Pattern,
+        }
+
+        impl Fields {
+            fn name(self) -> &'static str {
+                match self {
+                    Fields::Drop => "drop",
+                    Fields::Add => "add",
+                    Fields::Update => "update",
+                    Fields::Set => "set",
+                    Fields::Append => "append",
+                    Fields::Rename => "rename",
+                    Fields::Copy => "copy",
+                    Fields::Replace => "replace",
+                    Fields::DropRegex => "drop_regex",
+                    Fields::Value => "value",
+                    Fields::To => "to",
+                    Fields::From => "from",
+                    Fields::Pattern => "pattern",
+                }
+            }
         }
 
         struct HeaderTransformVisitor;
@@ -62,68 +154,181 @@ impl<'de> Deserialize<'de> for HeaderTransform {
             where
                 A: MapAccess<'de>,
             {
-                let mut action: Option<HeaderTransformActon> = None;
+                let mut action: Option<(Fields, String)> = None;
                 let mut value: Option<String> = None;
+                let mut to: Option<String> = None;
+                let mut from: Option<String> = None;
+                let mut pattern: Option<String> = None;
 
                 // Extract all fields
-                while let Some(key) = map.next_key()? {
-                    match key {
-                        Fields::Add => {
-                            if action.is_some() {
-                                return Err(de::Error::duplicate_field("add"));
-                            }
-                            action = Some(HeaderTransformActon::Add(map.next_value::<String>()?))
-                        }
-                        Fields::Drop => {
+                while let Some(key) = map.next_key::<Fields>()? {
+                    let field = match key {
+                        Fields::Value => &mut value,
+                        Fields::To => &mut to,
+                        Fields::From => &mut from,
+                        Fields::Pattern => &mut pattern,
+                        _ => {
                             if action.is_some() {
-                                return Err(de::Error::duplicate_field("drop"));
-                            }
-                            action = Some(HeaderTransformActon::Drop(map.next_value::<String>()?))
-                        }
-                        Fields::Update => {
-                            if action.is_some() {
-                                return Err(de::Error::duplicate_field("update"));
-                            }
-                            action = Some(HeaderTransformActon::Update(map.next_value::<String>()?))
-                        }
-                        Fields::Value => {
-                            if value.is_some() {
-                                return Err(de::Error::duplicate_field("value"));
+                                return Err(de::Error::custom(
+                                    "only one action is allowed in a single header transformation",
+                                ));
                             }
-                            value = Some(map.next_value::<String>()?)
+                            action = Some((key, map.next_value::<String>()?));
+                            continue;
                         }
+                    };
+                    if field.is_some() {
+                        return Err(de::Error::duplicate_field(key.name()));
                     }
+                    *field = Some(map.next_value::<String>()?);
                 }
 
-                if let Some(action) = action {
-                    match action {
-                        HeaderTransformActon::Add(_) | HeaderTransformActon::Update(_) => {
-                            if value.is_none() {
-                                return Err(de::Error::missing_field("value"));
-                            }
-                        }
-                        HeaderTransformActon::Drop(_) => {
-                            if value.is_some() {
-                                return Err(de::Error::custom(
-                                    "unknown field `value` in action drop",
-                                ));
-                            }
-                        }
+                let Some((kind, name)) = action else {
+                    return Err(de::Error::missing_field(
+                        "action should be one of add/update/set/append/rename/copy/replace/drop/drop_regex",
+                    ));
+                };
+
+                // Verify which fields are required and which are not allowed
+                let (value_required, to_required, from_required, pattern_required) = match kind {
+                    Fields::Add | Fields::Update | Fields::Set | Fields::Append => {
+                        (true, false, false, false)
+                    }
+                    Fields::Rename => (false, true, false, false),
+                    Fields::Copy => (false, false, true, false),
+                    Fields::Replace => (true, false, false, true),
+                    _ => (false, false, false, false),
+                };
+                for (field, present, required) in [
+                    ("value", value.is_some(), value_required),
+                    ("to", to.is_some(), to_required),
+                    ("from", from.is_some(), from_required),
+                    ("pattern", pattern.is_some(), pattern_required),
+                ] {
+                    if required && !present {
+                        return Err(de::Error::missing_field(field));
+                    }
+                    if !required && present {
+                        return Err(de::Error::custom(format!(
+                            "unknown field `{field}` in action {}",
+                            kind.name()
+                        )));
                     }
-                    Ok(HeaderTransform { action, value })
-                } else {
-                    Err(de::Error::missing_field(
-                        "action should be one of add/drop/update",
-                    ))
                 }
+
+                let header_name = |name: &str| {
+                    HeaderName::from_bytes(name.as_bytes())
+                        .map(|_| name.to_string())
+                        .map_err(|_| de::Error::custom(format!("invalid header name `{name}`")))
+                };
+                let header_pattern = |pattern: &str| {
+                    HeaderPattern::new(pattern)
+                        .map_err(|e| de::Error::custom(format!("invalid regex `{pattern}`: {e}")))
+                };
+
+                let action = match kind {
+                    Fields::Add => HeaderTransformActon::Add(header_name(&name)?),
+                    Fields::Update => HeaderTransformActon::Update(header_name(&name)?),
+                    Fields::Drop if name == "*" => HeaderTransformActon::Drop(name),
+                    Fields::Drop => HeaderTransformActon::Drop(header_name(&name)?),
+                    Fields::Set => HeaderTransformActon::Set(header_name(&name)?),
+                    Fields::Append => HeaderTransformActon::Append(header_name(&name)?),
+                    Fields::Rename => HeaderTransformActon::Rename {
+                        name: header_name(&name)?,
+                        to: header_name(&to.unwrap_or_default())?,
+                    },
+                    Fields::Copy => HeaderTransformActon::Copy {
+                        name: header_name(&name)?,
+                        from: header_name(&from.unwrap_or_default())?,
+                    },
+                    Fields::Replace => HeaderTransformActon::Replace {
+                        name: header_name(&name)?,
+                        pattern: header_pattern(&pattern.unwrap_or_default())?,
+                    },
+                    Fields::DropRegex => HeaderTransformActon::DropRegex(header_pattern(&name)?),
+                    Fields::Value | Fields::To | Fields::From | Fields::Pattern => {
+                        unreachable!("field can't be an action, looks like a BUG")
+                    }
+                };
+
+                Ok(HeaderTransform { action, value })
             }
         }
 
-        const FIELDS: &[&str] = &["add", "drop", "update", "value"];
+        const FIELDS: &[&str] = &[
+            "add",
+            "update",
+            "set",
+            "append",
+            "rename",
+            "copy",
+            "replace",
+            "drop",
+            "drop_regex",
+            "value",
+            "to",
+            "from",
+            "pattern",
+        ];
         deserializer.deserialize_struct("HeaderAction", FIELDS, HeaderTransformVisitor)
     }
 }
 
+/// Converts name of the header transformation, all names are validated on load
+fn valid_header_name(name: &str) -> HeaderName {
+    HeaderName::from_bytes(name.as_bytes())
+        .expect("header name is validated on load, looks like a BUG")
+}
+
+/// Converts header value produced by the template, invalid values are skipped
+fn header_value(name: &str, value: &str) -> Option<HeaderValue> {
+    match HeaderValue::from_str(value) {
+        Ok(value) => Some(value),
+        Err(_) => {
+            warn!("skip invalid value of header `{name}`: {value:?}");
+            None
+        }
+    }
+}
+
+/// Updates headers of the rewritten body: `Content-Length` is recomputed,
+/// `Content-Encoding` is dropped since the new body isn't encoded,
+/// `Content-Type` is replaced if it's defined
+pub(crate) fn update_body_headers(
+    headers: &mut HeaderMap,
+    body_len: usize,
+    content_type: Option<&str>,
+) {
+    headers.insert(CONTENT_LENGTH, HeaderValue::from(body_len));
+    headers.remove(CONTENT_ENCODING);
+    if let Some(value) = content_type.and_then(|v| header_value(CONTENT_TYPE.as_str(), v)) {
+        headers.insert(CONTENT_TYPE, value);
+    }
+}
+
+/// Makes sure that `content_type` of the body is a valid header value and it's used along with the body
+pub(crate) fn validate_content_type(
+    content_type: Option<&str>,
+    has_body: bool,
+    section: &str,
+) -> Result<(), ConfigError> {
+    let Some(content_type) = content_type else {
+        return Ok(());
+    };
+    if !has_body {
+        return Err(ConfigError::ValidateConfig {
+            cause: format!("`content_type` of {section} can be used along with `body` only"),
+        });
+    }
+    if HeaderValue::from_str(content_type).is_err() {
+        return Err(ConfigError::ValidateConfig {
+            cause: format!("invalid `content_type` of {section}: `{content_type}`"),
+        });
+    }
+
+    Ok(())
+}
+
 pub trait HeadersTransformator<'a> {
     fn transform(&'a self, headers: &'a mut HeaderMap, ctx: &Context);
 }
@@ -134,29 +339,19 @@ impl<'a> HeadersTransformator<'a> for HeadersTransformsList {
             match transform.action().clone() {
                 HeaderTransformActon::Add(key) => {
                     if !headers.contains_key(key.clone()) {
-                        let value = transform.value().as_ref().unwrap().as_str();
-                        let value = env_with_context_no_errors(value, |v| ctx.get(&v.into()));
-                        let key = HeaderName::from_bytes(key.as_bytes()).unwrap();
-                        debug!("add: name={key}, value={value}");
-                        headers.insert(&key, HeaderValue::from_str(&value).unwrap());
+                        let value = transform.expanded_value(ctx);
+                        if let Some(value) = header_value(&key, &value) {
+                            debug!("add: name={key}, value={value:?}");
+                            headers.insert(valid_header_name(&key), value);
+                        }
                     }
                 }
                 HeaderTransformActon::Update(key) => {
                     if headers.contains_key(&key) {
-                        let value = transform.value().as_ref().unwrap().as_str();
-                        let value = env_with_context_no_errors(value, |v| ctx.get(&v.into()));
-                        let debug_key = key.clone();
-                        let key = HeaderName::from_bytes(key.as_bytes()).unwrap();
-                        let old = headers.insert(key, HeaderValue::from_str(&value).unwrap());
-                        if let Some(old) = old {
-                            debug!(
-                                "update: name={}, old={}, new={}",
-                                debug_key,
-                                old.to_str().unwrap(),
-                                value
-                            );
-                        } else {
-                            debug!("update: name={}, old=, new={}", debug_key, value);
+                        let value = transform.expanded_value(ctx);
+                        if let Some(value) = header_value(&key, &value) {
+                            let old = headers.insert(valid_header_name(&key), value.clone());
+                            debug!("update: name={key}, old={old:?}, new={value:?}");
                         }
                     }
                 }
@@ -165,15 +360,79 @@ impl<'a> HeadersTransformator<'a> for HeadersTransformsList {
                         debug!("drop: all headers");
                         headers.clear();
                     } else {
-                        let debug_key = key.clone();
-                        let old = headers.remove(key);
-                        if let Some(old) = old {
-                            debug!("drop: name={}, old={}", debug_key, old.to_str().unwrap());
-                        } else {
-                            debug!("drop: name={}, old=", debug_key);
+                        let old = headers.remove(&key);
+                        debug!("drop: name={key}, old={old:?}");
+                    }
+                }
+                HeaderTransformActon::Set(key) => {
+                    let value = transform.expanded_value(ctx);
+                    if let Some(value) = header_value(&key, &value) {
+                        debug!("set: name={key}, value={value:?}");
+                        headers.insert(valid_header_name(&key), value);
+                    }
+                }
+                HeaderTransformActon::Append(key) => {
+                    let value = transform.expanded_value(ctx);
+                    if let Some(value) = header_value(&key, &value) {
+                        debug!("append: name={key}, value={value:?}");
+                        headers.append(valid_header_name(&key), value);
+                    }
+                }
+                HeaderTransformActon::Rename { name, to } => {
+                    let values: Vec<HeaderValue> = headers.get_all(&name).iter().cloned().collect();
+                    if !values.is_empty() {
+                        debug!("rename: name={name}, to={to}, values={values:?}");
+                        let to = valid_header_name(&to);
+                        headers.remove(&name);
+                        headers.remove(&to);
+                        for value in values {
+                            headers.append(&to, value);
+                        }
+                    }
+                }
+                HeaderTransformActon::Copy { name, from } => {
+                    let values: Vec<HeaderValue> = headers.get_all(&from).iter().cloned().collect();
+                    if !values.is_empty() {
+                        debug!("copy: name={name}, from={from}, values={values:?}");
+                        let name = valid_header_name(&name);
+                        headers.remove(&name);
+                        for value in values {
+                            headers.append(&name, value);
                         }
                     }
                 }
+                HeaderTransformActon::Replace { name, pattern } => {
+                    let replacement = transform.expanded_value(ctx);
+                    let values: Vec<HeaderValue> = headers.get_all(&name).iter().cloned().collect();
+                    if values.is_empty() {
+                        continue;
+                    }
+                    let name = valid_header_name(&name);
+                    headers.remove(&name);
+                    for value in values {
+                        let value = match value.to_str() {
+                            Ok(old) => {
+                                let new = pattern.0.replace_all(old, replacement.as_str());
+                                debug!("replace: name={name}, old={old}, new={new}");
+                                header_value(name.as_str(), &new).unwrap_or(value)
+                            }
+                            // Non-visible ASCII values are left as is
+                            Err(_) => value,
+                        };
+                        headers.append(&name, value);
+                    }
+                }
+                HeaderTransformActon::DropRegex(pattern) => {
+                    let names: Vec<HeaderName> = headers
+                        .keys()
+                        .filter(|name| pattern.0.is_match(name.as_str()))
+                        .cloned()
+                        .collect();
+                    for name in names {
+                        debug!("drop_regex: name={name}");
+                        headers.remove(name);
+                    }
+                }
             };
         }
     }
@@ -183,7 +442,7 @@ impl<'a> HeadersTransformator<'a> for HeadersTransformsList {
 mod tests {
     use insta::{assert_debug_snapshot, assert_ron_snapshot};
 
-    use crate::context::test_context::get_test_ctx;
+    use crate::context::{test_context::get_test_ctx, ContextMap};
 
     use super::*;
 
@@ -216,6 +475,21 @@ mod tests {
             r#"{"drop": "old_header", "wrong_value": "old_value"}"#,
             r#"{"drop": "old_header", "value": "old_value"}"#,
             r#"{"drop": "old_header", "value": 1}"#,
+            r#"{"add": "new_header", "drop": "old_header", "value": "new_value"}"#,
+            r#"{"set": "new_header"}"#,
+            r#"{"set": "bad header", "value": "new_value"}"#,
+            r#"{"add": "bad header", "value": "new_value"}"#,
+            r#"{"update": "bad:header", "value": "new_value"}"#,
+            r#"{"drop": "bad header"}"#,
+            r#"{"append": "new_header", "to": "new_value"}"#,
+            r#"{"rename": "old_header"}"#,
+            r#"{"rename": "old_header", "to": "new_header", "value": "new_value"}"#,
+            r#"{"copy": "new_header", "to": "old_header"}"#,
+            r#"{"copy": "new_header", "from": "bad:header"}"#,
+            r#"{"replace": "header", "value": "new_value"}"#,
+            r#"{"replace": "header", "pattern": "(unclosed", "value": "new_value"}"#,
+            r#"{"drop_regex": "[wrong"}"#,
+            r#"{"drop_regex": "^x-", "value": "new_value"}"#,
         ];
 
         for test_item in wrong_json {
@@ -261,4 +535,115 @@ mod tests {
         // After transformation
         assert_debug_snapshot!(headers);
     }
+
+    #[test]
+    fn extended_transforms() {
+        let ctx = get_test_ctx();
+
+        let transforms: HeadersTransformsList = serde_yaml_ng::from_str(
+            r#"
+            - set: X-Set-Existing
+              value: good
+            - set: X-Set-New
+              value: ${TEST_ENV_HEADER_TO_ADD}
+            - append: X-Multi
+              value: second
+            - rename: X-Old-Name
+              to: X-New-Name
+            - rename: X-Non-Existing
+              to: X-Set-New
+            - copy: X-Copy
+              from: X-Multi
+            - copy: X-Set-Existing
+              from: X-Non-Existing
+            - replace: Authorization
+              pattern: "^Token (.+)$"
+              value: "Bearer $1"
+            - drop_regex: "^x-internal-"
+            "#,
+        )
+        .unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Set-Existing", "wrong".parse().unwrap());
+        headers.insert("X-Multi", "first".parse().unwrap());
+        headers.insert("X-Old-Name", "renamed".parse().unwrap());
+        headers.insert("X-New-Name", "wrong".parse().unwrap());
+        headers.insert("Authorization", "Token abc".parse().unwrap());
+        headers.insert("X-Internal-Id", "wrong".parse().unwrap());
+        headers.insert("X-Internal-Secret", "wrong".parse().unwrap());
+        headers.insert("X-Not-Internal", "good".parse().unwrap());
+
+        transforms.transform(&mut headers, ctx);
+
+        let values = |name: &str| -> Vec<&str> {
+            headers
+                .get_all(name)
+                .iter()
+                .map(|v| v.to_str().unwrap())
+                .collect()
+        };
+        assert_eq!(values("x-set-existing"), ["good"]);
+        assert_eq!(values("x-set-new"), ["TEST_ENV_HEADER_VALUE"]);
+        assert_eq!(values("x-multi"), ["first", "second"]);
+        assert_eq!(values("x-old-name"), Vec::<&str>::new());
+        assert_eq!(values("x-new-name"), ["renamed"]);
+        assert_eq!(values("x-copy"), ["first", "second"]);
+        assert_eq!(values("authorization"), ["Bearer abc"]);
+        assert_eq!(values("x-internal-id"), Vec::<&str>::new());
+        assert_eq!(values("x-internal-secret"), Vec::<&str>::new());
+        assert_eq!(values("x-not-internal"), ["good"]);
+        assert_eq!(headers.len(), 9);
+    }
+
+    #[test]
+    fn invalid_values_are_skipped() {
+        let ctx = get_test_ctx().with(ContextMap::from([(
+            "CTX_VAR_INJECTED".into(),
+            "value\r\nX-Injected: 1".into(),
+        )]));
+        let transforms: HeadersTransformsList = serde_yaml_ng::from_str(
+            r#"
+            - add: X-Added
+              value: ${CTX_VAR_INJECTED}
+            - update: X-Updated
+              value: ${CTX_VAR_INJECTED}
+            - set: X-Set
+              value: ${CTX_VAR_INJECTED}
+            - drop: X-Dropped
+            "#,
+        )
+        .unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Updated", "good".parse().unwrap());
+        headers.insert("X-Dropped", HeaderValue::from_bytes(b"caf\xe9").unwrap());
+
+        transforms.transform(&mut headers, &ctx);
+
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers["x-updated"], "good");
+    }
+
+    #[test]
+    fn body_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_LENGTH, HeaderValue::from_static("100"));
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/html"));
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+
+        update_body_headers(&mut headers, 5, None);
+        assert_eq!(headers[CONTENT_LENGTH], "5");
+        assert!(!headers.contains_key(CONTENT_ENCODING));
+        assert_eq!(headers[CONTENT_TYPE], "text/html");
+
+        update_body_headers(&mut headers, 7, Some("application/json"));
+        assert_eq!(headers[CONTENT_LENGTH], "7");
+        assert_eq!(headers[CONTENT_TYPE], "application/json");
+
+        assert!(validate_content_type(Some("text/plain"), true, "test").is_ok());
+        assert!(validate_content_type(None, false, "test").is_ok());
+        assert!(validate_content_type(Some("text/plain"), false, "test").is_err());
+        assert!(validate_content_type(Some("text\n"), true, "test").is_err());
+    }
 }
@@ -0,0 +1,182 @@
+use super::{ConfigValidator, SecretString};
+use crate::config::ConfigError;
+use hyper::header::{HeaderName, HeaderValue};
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct HmacConfig {
+    header: String,
+    #[serde(default)]
+    algorithm: HmacAlgorithm,
+    secret: SecretString,
+    #[serde(default)]
+    encoding: HmacEncoding,
+    prefix: Option<String>,
+    timestamp_header: Option<String>,
+    payload_template: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields, rename_all = "lowercase")]
+pub enum HmacAlgorithm {
+    Sha1,
+    #[default]
+    Sha256,
+    Sha512,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields, rename_all = "lowercase")]
+pub enum HmacEncoding {
+    #[default]
+    Hex,
+    Base64,
+}
+
+impl HmacConfig {
+    pub fn header(&self) -> &str {
+        &self.header
+    }
+
+    pub fn algorithm(&self) -> HmacAlgorithm {
+        self.algorithm
+    }
+
+    pub fn secret(&self) -> &SecretString {
+        &self.secret
+    }
+
+    pub fn encoding(&self) -> HmacEncoding {
+        self.encoding
+    }
+
+    pub fn prefix(&self) -> Option<&str> {
+        self.prefix.as_deref()
+    }
+
+    pub fn timestamp_header(&self) -> Option<&str> {
+        self.timestamp_header.as_deref()
+    }
+
+    pub fn payload_template(&self) -> Option<&str> {
+        self.payload_template.as_deref()
+    }
+}
+
+impl ConfigValidator for HmacConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.secret.expose().is_empty() {
+            return Err(ConfigError::ValidateConfig {
+                cause: "`hmac.secret` must not be empty".into(),
+            });
+        }
+        if HeaderName::from_bytes(self.header.as_bytes()).is_err() {
+            return Err(ConfigError::ValidateConfig {
+                cause: format!(
+                    "`hmac.header` is not a valid header name: `{}`",
+                    self.header
+                ),
+            });
+        }
+        if let Some(prefix) = &self.prefix {
+            if HeaderValue::from_str(prefix).is_err() {
+                return Err(ConfigError::ValidateConfig {
+                    cause: format!("`hmac.prefix` is not a valid header value: {prefix:?}"),
+                });
+            }
+        }
+        if let Some(timestamp_header) = &self.timestamp_header {
+            if HeaderName::from_bytes(timestamp_header.as_bytes()).is_err() {
+                return Err(ConfigError::ValidateConfig {
+                    cause: format!(
+                        "`hmac.timestamp_header` is not a valid header name: `{timestamp_header}`"
+                    ),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_minimal() {
+        let cfg: HmacConfig =
+            serde_yaml_ng::from_str("header: X-Signature\nsecret: top-secret").unwrap();
+        assert_eq!(cfg.header(), "X-Signature");
+        assert_eq!(cfg.algorithm(), HmacAlgorithm::Sha256);
+        assert_eq!(cfg.encoding(), HmacEncoding::Hex);
+        assert_eq!(cfg.secret().expose(), "top-secret");
+        assert_eq!(cfg.prefix(), None);
+        assert_eq!(cfg.timestamp_header(), None);
+        assert_eq!(cfg.payload_template(), None);
+        assert!(cfg.validate().is_ok());
+    }
+
+    #[test]
+    fn parses_full() {
+        let cfg: HmacConfig = serde_yaml_ng::from_str(
+            "header: X-Signature\nalgorithm: sha512\nsecret: s\nencoding: base64\nprefix: v1=\ntimestamp_header: X-Timestamp\npayload_template: \"${CTX_HMAC_TIMESTAMP}.${CTX_HMAC_BODY}\"",
+        )
+        .unwrap();
+        assert_eq!(cfg.algorithm(), HmacAlgorithm::Sha512);
+        assert_eq!(cfg.encoding(), HmacEncoding::Base64);
+        assert_eq!(cfg.prefix(), Some("v1="));
+        assert_eq!(cfg.timestamp_header(), Some("X-Timestamp"));
+        assert_eq!(
+            cfg.payload_template(),
+            Some("${CTX_HMAC_TIMESTAMP}.${CTX_HMAC_BODY}")
+        );
+        assert!(cfg.validate().is_ok());
+    }
+
+    #[test]
+    fn hides_secret_in_debug_output() {
+        let cfg: HmacConfig =
+            serde_yaml_ng::from_str("header: X-Signature\nsecret: top-secret").unwrap();
+        assert!(!format!("{cfg:?}").contains("top-secret"));
+    }
+
+    #[test]
+    fn rejects_empty_secret() {
+        let cfg: HmacConfig = serde_yaml_ng::from_str("header: X-Signature\nsecret: \"\"").unwrap();
+        assert!(cfg.validate().is_err());
+    }
+
+    #[test]
+    fn rejects_bad_header_names() {
+        let cfg: HmacConfig =
+            serde_yaml_ng::from_str("header: \"X Signature\"\nsecret: s").unwrap();
+        assert!(cfg.validate().is_err());
+
+        let cfg: HmacConfig = serde_yaml_ng::from_str(
+            "header: X-Signature\nsecret: s\ntimestamp_header: \"X:Timestamp\"",
+        )
+        .unwrap();
+        assert!(cfg.validate().is_err());
+    }
+
+    #[test]
+    fn rejects_bad_prefix() {
+        let cfg: HmacConfig =
+            serde_yaml_ng::from_str("header: X-Signature\nsecret: s\nprefix: \"v1\\r\\n=\"")
+                .unwrap();
+        assert!(cfg.validate().is_err());
+
+        let cfg: HmacConfig =
+            serde_yaml_ng::from_str("header: X-Signature\nsecret: s\nprefix: \"t=1, v1=\"")
+                .unwrap();
+        assert!(cfg.validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_algorithm() {
+        let result: Result<HmacConfig, _> =
+            serde_yaml_ng::from_str("header: X-Signature\nsecret: s\nalgorithm: md5");
+        assert!(result.is_err());
+    }
+}
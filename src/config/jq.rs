@@ -0,0 +1,188 @@
+use crate::config::ConfigError;
+use core::fmt;
+use jaq_core::{load, Compiler, Ctx, Filter, Native, RcIter};
+use jaq_json::Val;
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_json::Value as JsonValue;
+use std::str::FromStr;
+use tracing::debug;
+
+#[derive(thiserror::Error, Debug)]
+#[error("unable to evaluate expression `{filter}`: {cause}")]
+pub struct JqError {
+    filter: String,
+    cause: String,
+}
+
+/// Compiled expression in [`jq`](https://jqlang.github.io/jq/manual/) syntax
+pub struct JqFilter {
+    filter: Filter<Native<Val>>,
+    filter_str: String,
+}
+
+impl fmt::Debug for JqFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JqFilter")
+            .field("filter", &self.filter_str)
+            .finish()
+    }
+}
+
+impl FromStr for JqFilter {
+    type Err = ConfigError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        debug!("filter=`{value}`");
+
+        let program = load::File {
+            code: value,
+            path: (),
+        };
+        let arena = load::Arena::default();
+        let loader = load::Loader::new(jaq_std::defs().chain(jaq_json::defs()));
+
+        // parse the filter
+        let modules = loader
+            .load(&arena, program)
+            .map_err(|_err| ConfigError::ValidateConfig {
+                cause: format!("invalid jq expression: `{value}`"),
+            })?;
+
+        // compile the filter
+        let filter = Compiler::default()
+            .with_funs(jaq_std::funs().chain(jaq_json::funs()))
+            .compile(modules)
+            .map_err(|_err| ConfigError::ValidateConfig {
+                cause: format!("invalid jq expression: `{value}`"),
+            })?;
+
+        Ok(Self {
+            filter,
+            filter_str: value.to_string(),
+        })
+    }
+}
+
+impl JqFilter {
+    /// Returns source of the expression
+    pub fn as_str(&self) -> &str {
+        &self.filter_str
+    }
+
+    /// Evaluates expression and returns all its outputs, or the first error
+    pub(crate) fn run(&self, input: Val) -> Result<Vec<Val>, JqError> {
+        let inputs = RcIter::new(core::iter::empty());
+        self.filter
+            .run((Ctx::new([], &inputs), input))
+            .collect::<Result<Vec<Val>, _>>()
+            .map_err(|e| JqError {
+                filter: self.filter_str.clone(),
+                cause: e.to_string(),
+            })
+    }
+
+    /// Evaluates expression and returns its first output as JSON value, `null` if there is no output.
+    /// Further outputs aren't evaluated at all, so infinite generators are fine here.
+    pub(crate) fn first(&self, input: Val) -> Result<JsonValue, JqError> {
+        let inputs = RcIter::new(core::iter::empty());
+        let first = self.filter.run((Ctx::new([], &inputs), input)).next();
+        match first {
+            Some(Ok(value)) => Ok(JsonValue::from(value)),
+            Some(Err(e)) => Err(JqError {
+                filter: self.filter_str.clone(),
+                cause: e.to_string(),
+            }),
+            None => Ok(JsonValue::Null),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for JqFilter {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct JqFilterVisitor;
+        impl Visitor<'_> for JqFilterVisitor {
+            type Value = JqFilter;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("expression in JQ-like style")
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                JqFilter::from_str(v).map_err(|e| E::custom(e))
+            }
+        }
+
+        deserializer.deserialize_string(JqFilterVisitor)
+    }
+}
+
+impl Serialize for JqFilter {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.filter_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn run_expression() {
+        let filter = JqFilter::from_str(".items[] | .id").unwrap();
+        let input = Val::from(json!({"items": [{"id": 1}, {"id": "two"}]}));
+
+        let out: Vec<JsonValue> = filter
+            .run(input.clone())
+            .unwrap()
+            .into_iter()
+            .map(JsonValue::from)
+            .collect();
+        assert_eq!(out, vec![json!(1), json!("two")]);
+        assert_eq!(filter.first(input).unwrap(), json!(1));
+        assert_eq!(filter.as_str(), ".items[] | .id");
+
+        let filter = JqFilter::from_str("empty").unwrap();
+        assert_eq!(filter.first(Val::from(json!({}))).unwrap(), JsonValue::Null);
+    }
+
+    #[test]
+    fn first_output_only() {
+        let filter = JqFilter::from_str("repeat(.)").unwrap();
+        assert_eq!(filter.first(Val::from(json!(1))).unwrap(), json!(1));
+
+        let filter = JqFilter::from_str("range(infinite)").unwrap();
+        assert_eq!(filter.first(Val::from(json!(null))).unwrap(), json!(0));
+
+        // Errors of the following outputs don't matter
+        let filter = JqFilter::from_str(".[] | tonumber").unwrap();
+        assert_eq!(
+            filter.first(Val::from(json!(["1", "abc"]))).unwrap(),
+            json!(1)
+        );
+    }
+
+    #[test]
+    fn wrong_expression() {
+        assert!(JqFilter::from_str(".items[").is_err());
+        assert!(JqFilter::from_str("unknown_function(1)").is_err());
+
+        let filter = JqFilter::from_str(".value | tonumber").unwrap();
+        let err = filter
+            .first(Val::from(json!({"value": "abc"})))
+            .unwrap_err();
+        assert!(err.to_string().starts_with("unable to evaluate expression"));
+    }
+}
@@ -1,5 +1,12 @@
 use super::{
+    cache::CacheConfig,
+    compare::CompareConfig,
+    compression::CompressionConfig,
+    cors::CorsConfig,
+    extract::ExtractConfig,
     headers::HeaderTransform,
+    proxy_headers::ProxyHeadersConfig,
+    quorum::QuorumMinOk,
     response::{ResponseBehavior, ResponseConfig},
     target::{TargetConfig, TargetConfigList},
     ConfigValidator,
@@ -45,6 +52,13 @@ pub struct ListenerConfig {
     response: ResponseConfig,
     #[serde(default)]
     tls: TlsConfig,
+    cors: Option<CorsConfig>,
+    cache: Option<CacheConfig>,
+    compression: Option<CompressionConfig>,
+    extract: Option<ExtractConfig>,
+    compare: Option<CompareConfig>,
+    #[serde(default)]
+    proxy_headers: ProxyHeadersConfig,
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
@@ -138,6 +152,36 @@ impl ListenerConfig {
         &self.tls
     }
 
+    /// Returns CORS config of this [`ListenerConfig`], if it's defined.
+    pub fn cors(&self) -> Option<&CorsConfig> {
+        self.cors.as_ref()
+    }
+
+    /// Returns response cache config of this [`ListenerConfig`], if it's defined.
+    pub fn cache(&self) -> Option<&CacheConfig> {
+        self.cache.as_ref()
+    }
+
+    /// Returns compression config of this [`ListenerConfig`], if it's defined.
+    pub fn compression(&self) -> Option<&CompressionConfig> {
+        self.compression.as_ref()
+    }
+
+    /// Returns request variables to extract, if they're defined.
+    pub fn extract(&self) -> Option<&ExtractConfig> {
+        self.extract.as_ref()
+    }
+
+    /// Returns config of the baseline and candidate responses comparison, if it's defined
+    pub fn compare(&self) -> Option<&CompareConfig> {
+        self.compare.as_ref()
+    }
+
+    /// Returns config of the hop-by-hop and forwarding headers
+    pub fn proxy_headers(&self) -> &ProxyHeadersConfig {
+        &self.proxy_headers
+    }
+
     fn validate_strategy(&self) -> Result<(), ConfigError> {
         // Validate strategy requirements
         match self.strategy() {
@@ -169,6 +213,38 @@ impl ListenerConfig {
                     });
                 }
             }
+            ResponseStrategy::SelectByExpression
+                if self.response().selector_expression().is_none() =>
+            {
+                return Err(ConfigError::ValidateConfig {
+                    cause: format!(
+                        "`selector_expression` should be specified for strategy `{}`",
+                        self.strategy()
+                    ),
+                });
+            }
+            ResponseStrategy::Quorum => match self.response().quorum() {
+                None => {
+                    return Err(ConfigError::ValidateConfig {
+                        cause: format!(
+                            "`quorum` should be specified for strategy `{}`",
+                            self.strategy()
+                        ),
+                    });
+                }
+                Some(quorum) => {
+                    if let QuorumMinOk::Count(count) = quorum.min_ok() {
+                        if count > self.targets().len() {
+                            return Err(ConfigError::ValidateConfig {
+                                cause: format!(
+                                    "`quorum.min_ok` is {count} but only {} targets are defined",
+                                    self.targets().len()
+                                ),
+                            });
+                        }
+                    }
+                }
+            },
             ResponseStrategy::AlwaysTargetId
             | ResponseStrategy::FailedThenTargetId
             | ResponseStrategy::OkThenTargetId => {
@@ -193,6 +269,59 @@ impl ListenerConfig {
             }
             _ => {}
         };
+        if self.response().selector_expression().is_some()
+            && !matches!(self.strategy(), ResponseStrategy::SelectByExpression)
+        {
+            return Err(ConfigError::ValidateConfig {
+                cause: format!(
+                    "`selector_expression` can be used with strategy `{}` only",
+                    ResponseStrategy::SelectByExpression
+                ),
+            });
+        }
+        if self.response().aggregate().is_some()
+            && !matches!(self.strategy(), ResponseStrategy::Aggregate)
+        {
+            return Err(ConfigError::ValidateConfig {
+                cause: format!(
+                    "`aggregate` can be used with strategy `{}` only",
+                    ResponseStrategy::Aggregate
+                ),
+            });
+        }
+        if self.targets().iter().any(|t| !t.depends_on().is_empty())
+            && matches!(
+                self.strategy(),
+                ResponseStrategy::ConditionalRouting | ResponseStrategy::Failover
+            )
+        {
+            return Err(ConfigError::ValidateConfig {
+                cause: format!(
+                    "`depends_on` can't be used with strategy `{}`",
+                    self.strategy()
+                ),
+            });
+        }
+        if self.response().quorum().is_some()
+            && !matches!(self.strategy(), ResponseStrategy::Quorum)
+        {
+            return Err(ConfigError::ValidateConfig {
+                cause: format!(
+                    "`quorum` can be used with strategy `{}` only",
+                    ResponseStrategy::Quorum
+                ),
+            });
+        }
+        if self.response().failover_timeout().is_some()
+            && !matches!(self.strategy(), ResponseStrategy::Failover)
+        {
+            return Err(ConfigError::ValidateConfig {
+                cause: format!(
+                    "`failover_timeout` can be used with strategy `{}` only",
+                    ResponseStrategy::Failover
+                ),
+            });
+        }
 
         Ok(())
     }
@@ -212,6 +341,10 @@ pub enum ResponseStrategy {
     #[default]
     FailedThenOverride,
     ConditionalRouting,
+    SelectByExpression,
+    Aggregate,
+    Failover,
+    Quorum,
 }
 
 #[derive(Deserialize, Debug, EnumString, PartialEq, Eq, Hash, Serialize)]
@@ -327,6 +460,22 @@ impl ConfigValidator for ListenerConfig {
         self.targets().validate()?;
         self.response().validate()?;
         self.validate_strategy()?;
+        if let Some(cors) = self.cors() {
+            cors.validate()?;
+        }
+        if let Some(cache) = self.cache() {
+            cache.validate()?;
+        }
+        if let Some(compression) = self.compression() {
+            compression.validate()?;
+        }
+        if let Some(extract) = self.extract() {
+            extract.validate()?;
+        }
+        if let Some(compare) = self.compare() {
+            compare.validate()?;
+            compare.validate_targets(self.targets())?;
+        }
 
         Ok(())
     }
@@ -0,0 +1,128 @@
+use super::{ConfigValidator, SecretString};
+use crate::config::ConfigError;
+use hyper::Uri;
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct OAuth2Config {
+    token_url: String,
+    client_id: String,
+    client_secret: SecretString,
+    #[serde(default)]
+    scopes: Vec<String>,
+    audience: Option<String>,
+    #[serde(default)]
+    client_auth: OAuth2ClientAuth,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields, rename_all = "lowercase")]
+pub enum OAuth2ClientAuth {
+    /// Client credentials in `Authorization: Basic ...` header
+    #[default]
+    Basic,
+    /// Client credentials as `client_id` and `client_secret` form parameters
+    Body,
+}
+
+impl OAuth2Config {
+    pub fn token_url(&self) -> &str {
+        &self.token_url
+    }
+
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    pub fn client_secret(&self) -> &SecretString {
+        &self.client_secret
+    }
+
+    pub fn scopes(&self) -> &[String] {
+        &self.scopes
+    }
+
+    pub fn audience(&self) -> Option<&str> {
+        self.audience.as_deref()
+    }
+
+    pub fn client_auth(&self) -> OAuth2ClientAuth {
+        self.client_auth
+    }
+}
+
+impl ConfigValidator for OAuth2Config {
+    fn validate(&self) -> Result<(), ConfigError> {
+        let uri: Uri = self
+            .token_url
+            .parse()
+            .map_err(|e| ConfigError::ValidateConfig {
+                cause: format!("invalid `oauth2.token_url` `{}`: {e}", self.token_url),
+            })?;
+        if !matches!(uri.scheme_str(), Some("http") | Some("https")) || uri.host().is_none() {
+            return Err(ConfigError::ValidateConfig {
+                cause: format!(
+                    "`oauth2.token_url` should be absolute http(s) URL: `{}`",
+                    self.token_url
+                ),
+            });
+        }
+        if self.client_id.trim().is_empty() {
+            return Err(ConfigError::ValidateConfig {
+                cause: "`oauth2.client_id` must not be empty".into(),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_minimal() {
+        let cfg: OAuth2Config = serde_yaml_ng::from_str(
+            "token_url: https://auth.example.com/oauth/token\nclient_id: id\nclient_secret: secret",
+        )
+        .unwrap();
+        assert_eq!(cfg.token_url(), "https://auth.example.com/oauth/token");
+        assert_eq!(cfg.client_id(), "id");
+        assert_eq!(cfg.client_secret().expose(), "secret");
+        assert!(cfg.scopes().is_empty());
+        assert_eq!(cfg.audience(), None);
+        assert_eq!(cfg.client_auth(), OAuth2ClientAuth::Basic);
+        assert!(cfg.validate().is_ok());
+    }
+
+    #[test]
+    fn parses_full() {
+        let cfg: OAuth2Config = serde_yaml_ng::from_str(
+            "token_url: https://auth.example.com/oauth/token\nclient_id: id\nclient_secret: secret\nscopes: [read, write]\naudience: https://api.example.com\nclient_auth: body",
+        )
+        .unwrap();
+        assert_eq!(cfg.scopes(), &["read".to_string(), "write".to_string()]);
+        assert_eq!(cfg.audience(), Some("https://api.example.com"));
+        assert_eq!(cfg.client_auth(), OAuth2ClientAuth::Body);
+        assert!(cfg.validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_relative_token_url() {
+        let cfg: OAuth2Config = serde_yaml_ng::from_str(
+            "token_url: /oauth/token\nclient_id: id\nclient_secret: secret",
+        )
+        .unwrap();
+        assert!(cfg.validate().is_err());
+    }
+
+    #[test]
+    fn rejects_empty_client_id() {
+        let cfg: OAuth2Config = serde_yaml_ng::from_str(
+            "token_url: https://auth.example.com/oauth/token\nclient_id: \"\"\nclient_secret: secret",
+        )
+        .unwrap();
+        assert!(cfg.validate().is_err());
+    }
+}
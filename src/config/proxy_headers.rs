@@ -0,0 +1,284 @@
+use hyper::{
+    header::{
+        HeaderName, HeaderValue, CONNECTION, FORWARDED, HOST, TE, TRAILER, TRANSFER_ENCODING,
+        UPGRADE, VIA,
+    },
+    http::request::Parts,
+    HeaderMap, Version,
+};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use tracing::debug;
+
+const PSEUDONYM: &str = env!("CARGO_PKG_NAME");
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+const X_FORWARDED_HOST: &str = "x-forwarded-host";
+
+/// Hop-by-hop headers which are meaningful for a single connection only, RFC 9110, section 7.6.1,
+/// plus obsolete and proxy-specific ones
+const HOP_BY_HOP_HEADERS: [&str; 5] = [
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-authentication-info",
+];
+
+/// Headers to pass information about the client to targets
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub enum ForwardedHeaders {
+    /// `X-Forwarded-For`, `X-Forwarded-Proto` and `X-Forwarded-Host`
+    #[default]
+    XForwarded,
+    /// RFC 7239 `Forwarded`
+    Forwarded,
+    /// Don't add any
+    #[serde(rename = "none")]
+    Disabled,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields, default)]
+pub struct ProxyHeadersConfig {
+    strip_hop_by_hop: bool,
+    via: bool,
+    forwarded: ForwardedHeaders,
+}
+
+impl Default for ProxyHeadersConfig {
+    fn default() -> Self {
+        Self {
+            strip_hop_by_hop: true,
+            via: true,
+            forwarded: Default::default(),
+        }
+    }
+}
+
+impl ProxyHeadersConfig {
+    pub fn strip_hop_by_hop(&self) -> bool {
+        self.strip_hop_by_hop
+    }
+
+    pub fn via(&self) -> bool {
+        self.via
+    }
+
+    pub fn forwarded(&self) -> ForwardedHeaders {
+        self.forwarded
+    }
+
+    /// Updates headers of the request to pass to targets, `headers` should be a copy of the original request's ones
+    pub(crate) fn update_request_headers(
+        &self,
+        headers: &mut HeaderMap,
+        addr: &SocketAddr,
+        req: &Parts,
+    ) {
+        if self.strip_hop_by_hop {
+            strip_hop_by_hop_headers(headers);
+        }
+        if self.via {
+            append_via(headers, req.version);
+        }
+
+        let proto = req.uri.scheme_str().unwrap_or("http");
+        let host = req
+            .headers
+            .get(HOST)
+            .and_then(|v| v.to_str().ok())
+            .or_else(|| req.uri.authority().map(|a| a.as_str()));
+        match self.forwarded {
+            ForwardedHeaders::XForwarded => {
+                append_value(headers, X_FORWARDED_FOR, &addr.ip().to_string());
+                // Values of the previous proxy are more accurate
+                set_if_absent(headers, X_FORWARDED_PROTO, proto);
+                if let Some(host) = host {
+                    set_if_absent(headers, X_FORWARDED_HOST, host);
+                }
+            }
+            ForwardedHeaders::Forwarded => {
+                let mut element = format!("for={};proto={proto}", forwarded_node(addr.ip()));
+                if let Some(host) = host {
+                    element.push_str(&format!(";host={}", forwarded_value(host)));
+                }
+                append_value(headers, FORWARDED.as_str(), &element);
+            }
+            ForwardedHeaders::Disabled => {}
+        }
+    }
+
+    /// Updates headers of the target's response to pass back to the client
+    pub(crate) fn update_response_headers(&self, headers: &mut HeaderMap, version: Version) {
+        if self.strip_hop_by_hop {
+            strip_hop_by_hop_headers(headers);
+        }
+        if self.via {
+            append_via(headers, version);
+        }
+    }
+}
+
+/// Removes hop-by-hop headers including ones which are listed in `Connection` header
+fn strip_hop_by_hop_headers(headers: &mut HeaderMap) {
+    let listed: Vec<HeaderName> = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect();
+    for name in listed
+        .iter()
+        .chain([CONNECTION, TE, TRAILER, TRANSFER_ENCODING, UPGRADE].iter())
+    {
+        if headers.remove(name).is_some() {
+            debug!("hop-by-hop header is stripped: {name}");
+        }
+    }
+    for name in HOP_BY_HOP_HEADERS {
+        if headers.remove(name).is_some() {
+            debug!("hop-by-hop header is stripped: {name}");
+        }
+    }
+}
+
+/// Appends this proxy to `Via` header, RFC 9110, section 7.6.3
+fn append_via(headers: &mut HeaderMap, version: Version) {
+    let protocol = match version {
+        Version::HTTP_09 => "0.9",
+        Version::HTTP_10 => "1.0",
+        Version::HTTP_2 => "2",
+        Version::HTTP_3 => "3",
+        _ => "1.1",
+    };
+    append_value(headers, VIA.as_str(), &format!("{protocol} {PSEUDONYM}"));
+}
+
+/// Appends value to the comma-separated list of the header's values
+fn append_value(headers: &mut HeaderMap, name: &str, value: &str) {
+    let existing: Vec<&str> = headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .collect();
+    let value = if existing.is_empty() {
+        value.to_string()
+    } else {
+        format!("{}, {value}", existing.join(", "))
+    };
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        headers.insert(
+            HeaderName::from_bytes(name.as_bytes()).expect("header name is a valid constant"),
+            value,
+        );
+    }
+}
+
+fn set_if_absent(headers: &mut HeaderMap, name: &'static str, value: &str) {
+    if !headers.contains_key(name) {
+        if let Ok(value) = HeaderValue::from_str(value) {
+            headers.insert(name, value);
+        }
+    }
+}
+
+/// Formats node of `Forwarded` header, IPv6 addresses are bracketed and quoted, RFC 7239, section 6
+fn forwarded_node(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("\"[{ip}]\""),
+    }
+}
+
+/// Quotes value of `Forwarded` header if it isn't a token, like host with port
+fn forwarded_value(value: &str) -> String {
+    if value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+    {
+        value.to_string()
+    } else {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::Request;
+
+    fn request_headers(yaml: &str, addr: &str, headers: &[(&str, &str)]) -> HeaderMap {
+        let cfg: ProxyHeadersConfig = serde_yaml_ng::from_str(yaml).unwrap();
+        let mut req = Request::builder().uri("/path");
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        let (parts, _) = req.body(()).unwrap().into_parts();
+        let mut headers = parts.headers.clone();
+        cfg.update_request_headers(&mut headers, &addr.parse().unwrap(), &parts);
+        headers
+    }
+
+    #[test]
+    fn hop_by_hop() {
+        let headers = request_headers(
+            "forwarded: none\nvia: false",
+            "127.0.0.1:1000",
+            &[
+                ("connection", "keep-alive, x-custom-hop"),
+                ("keep-alive", "timeout=5"),
+                ("x-custom-hop", "1"),
+                ("te", "trailers"),
+                ("upgrade", "websocket"),
+                ("proxy-authorization", "Basic xxx"),
+                ("x-end-to-end", "1"),
+            ],
+        );
+        assert_eq!(
+            headers.keys().map(HeaderName::as_str).collect::<Vec<_>>(),
+            ["x-end-to-end"]
+        );
+
+        let headers = request_headers(
+            "strip_hop_by_hop: false\nforwarded: none\nvia: false",
+            "127.0.0.1:1000",
+            &[("connection", "close")],
+        );
+        assert_eq!(headers[CONNECTION], "close");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(TRANSFER_ENCODING, HeaderValue::from_static("chunked"));
+        headers.insert(VIA, HeaderValue::from_static("1.1 upstream"));
+        ProxyHeadersConfig::default().update_response_headers(&mut headers, Version::HTTP_2);
+        assert!(!headers.contains_key(TRANSFER_ENCODING));
+        assert_eq!(headers[VIA], "1.1 upstream, 2 http-dragonfly");
+    }
+
+    #[test]
+    fn forwarded() {
+        let headers = request_headers(
+            "{}",
+            "10.0.0.2:1000",
+            &[("host", "example.com"), ("x-forwarded-for", "192.0.2.1")],
+        );
+        assert_eq!(headers[VIA], "1.1 http-dragonfly");
+        assert_eq!(headers[X_FORWARDED_FOR], "192.0.2.1, 10.0.0.2");
+        assert_eq!(headers[X_FORWARDED_PROTO], "http");
+        assert_eq!(headers[X_FORWARDED_HOST], "example.com");
+        assert!(!headers.contains_key(FORWARDED));
+
+        let headers = request_headers(
+            "forwarded: forwarded",
+            "[2001:db8::1]:1000",
+            &[("host", "example.com:8080"), ("forwarded", "for=192.0.2.1")],
+        );
+        assert_eq!(
+            headers[FORWARDED],
+            r#"for=192.0.2.1, for="[2001:db8::1]";proto=http;host="example.com:8080""#
+        );
+        assert!(!headers.contains_key(X_FORWARDED_FOR));
+    }
+}
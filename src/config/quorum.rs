@@ -0,0 +1,181 @@
+use super::ConfigValidator;
+use crate::config::ConfigError;
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+/// Minimal number of successful responses: absolute count or percentage of the queried targets
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuorumMinOk {
+    Count(usize),
+    Percent(u8),
+}
+
+impl<'de> Deserialize<'de> for QuorumMinOk {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct QuorumMinOkVisitor;
+        impl Visitor<'_> for QuorumMinOkVisitor {
+            type Value = QuorumMinOk;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("number of responses or percentage like `50%`")
+            }
+
+            fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                Ok(QuorumMinOk::Count(v as usize))
+            }
+
+            fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                usize::try_from(v)
+                    .map(QuorumMinOk::Count)
+                    .map_err(|_| E::invalid_value(de::Unexpected::Signed(v), &self))
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                let value = match v.strip_suffix('%') {
+                    Some(percent) => percent.trim().parse().map(QuorumMinOk::Percent),
+                    None => v.trim().parse().map(QuorumMinOk::Count),
+                };
+                value.map_err(|_| E::invalid_value(de::Unexpected::Str(v), &self))
+            }
+        }
+
+        deserializer.deserialize_any(QuorumMinOkVisitor)
+    }
+}
+
+impl Serialize for QuorumMinOk {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            QuorumMinOk::Count(count) => serializer.serialize_u64(*count as u64),
+            QuorumMinOk::Percent(percent) => serializer.serialize_str(&format!("{percent}%")),
+        }
+    }
+}
+
+/// What to respond with: one of the targets' responses or the override
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub enum QuorumRespondWith {
+    #[default]
+    Target,
+    Override,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct QuorumConfig {
+    min_ok: QuorumMinOk,
+    #[serde(default)]
+    early_return: bool,
+    #[serde(default)]
+    respond_with: QuorumRespondWith,
+}
+
+impl QuorumConfig {
+    pub fn min_ok(&self) -> QuorumMinOk {
+        self.min_ok
+    }
+
+    /// Returns `true` if response should be sent as soon as quorum is reached,
+    /// without waiting for the rest of targets
+    pub fn early_return(&self) -> bool {
+        self.early_return
+    }
+
+    pub fn respond_with(&self) -> QuorumRespondWith {
+        self.respond_with
+    }
+
+    /// Returns number of successful responses required for quorum of `total` queried targets,
+    /// at least one response is always required
+    pub fn required(&self, total: usize) -> usize {
+        let required = match self.min_ok {
+            QuorumMinOk::Count(count) => count,
+            QuorumMinOk::Percent(percent) => (total * percent as usize).div_ceil(100),
+        };
+        required.max(1)
+    }
+}
+
+impl ConfigValidator for QuorumConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        match self.min_ok {
+            QuorumMinOk::Count(0) | QuorumMinOk::Percent(0) => Err(ConfigError::ValidateConfig {
+                cause: "`quorum.min_ok` should be greater than zero".into(),
+            }),
+            QuorumMinOk::Percent(percent) if percent > 100 => Err(ConfigError::ValidateConfig {
+                cause: format!("`quorum.min_ok` can't be greater than 100%, but it's {percent}%"),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn min_ok_values() {
+        for (yaml, expected) in [
+            ("min_ok: 2", QuorumMinOk::Count(2)),
+            ("min_ok: '3'", QuorumMinOk::Count(3)),
+            ("min_ok: 50%", QuorumMinOk::Percent(50)),
+            ("min_ok: '100%'", QuorumMinOk::Percent(100)),
+        ] {
+            let cfg: QuorumConfig = serde_yaml_ng::from_str(yaml).unwrap();
+            assert_eq!(cfg.min_ok(), expected, "{yaml}");
+            assert!(cfg.validate().is_ok(), "{yaml}");
+        }
+
+        for yaml in [
+            "min_ok: -1",
+            "min_ok: half",
+            "min_ok: 1.5",
+            "min_ok: 50 %%",
+            "{}",
+        ] {
+            assert!(
+                serde_yaml_ng::from_str::<QuorumConfig>(yaml).is_err(),
+                "`{yaml}` should be rejected"
+            );
+        }
+        for yaml in ["min_ok: 0", "min_ok: 0%", "min_ok: 101%"] {
+            let cfg: QuorumConfig = serde_yaml_ng::from_str(yaml).unwrap();
+            assert!(cfg.validate().is_err(), "`{yaml}` should be rejected");
+        }
+    }
+
+    #[test]
+    fn required_responses() {
+        let required = |yaml: &str, total| {
+            serde_yaml_ng::from_str::<QuorumConfig>(yaml)
+                .unwrap()
+                .required(total)
+        };
+        assert_eq!(required("min_ok: 2", 3), 2);
+        assert_eq!(required("min_ok: 2", 1), 2);
+        assert_eq!(required("min_ok: 50%", 3), 2);
+        assert_eq!(required("min_ok: 50%", 4), 2);
+        assert_eq!(required("min_ok: 100%", 3), 3);
+        assert_eq!(required("min_ok: 34%", 3), 2);
+        assert_eq!(required("min_ok: 50%", 0), 1);
+    }
+}
@@ -1,18 +1,37 @@
 use super::{
-    headers::{HeaderTransform, HeadersTransformator},
+    aggregate::{AggregateConfig, AggregateItem},
+    errors::{ErrorDetails, ErrorKind, ErrorsConfig},
+    headers::{update_body_headers, validate_content_type, HeaderTransform, HeadersTransformator},
+    jq::JqFilter,
+    quorum::{QuorumConfig, QuorumRespondWith},
+    target::{TargetConfig, TargetOnAggregateError},
     ConfigValidator,
 };
 use crate::{
+    body_parser::parse_body,
     config,
     context::Context,
     handler::{ResponseResult, ResponsesMap},
 };
-use http_body_util::Full;
-use hyper::{body::Bytes, header::CONTENT_LENGTH, http::Error, Response, StatusCode};
+use futures_util::FutureExt;
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    body::Bytes,
+    header::{HeaderValue, CONTENT_TYPE},
+    http::Error,
+    Response, StatusCode,
+};
+use jaq_json::Val;
 use regex::Regex;
-use serde::{Deserialize, Serialize};
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_json::{json, Value as JsonValue};
 use shellexpand::env_with_context_no_errors;
-use tracing::debug;
+use std::{cmp::Reverse, collections::HashMap, time::Duration};
+use strum_macros::Display;
+use tracing::{debug, warn};
 
 pub type ResponseStatus = u16;
 
@@ -22,80 +41,388 @@ const UNABLE_TO_CREATE_RESPONSE_ERROR: &str = "unable to create response, looks
 #[serde(deny_unknown_fields, default)]
 pub struct ResponseConfig {
     target_selector: Option<String>,
-    failed_status_regex: String,
+    selector_expression: Option<JqFilter>,
+    aggregate: Option<AggregateConfig>,
+    prefer: ResponsePreference,
+    #[serde(with = "humantime_serde")]
+    failover_timeout: Option<Duration>,
+    quorum: Option<QuorumConfig>,
+    failed_status_regex: StatusRegex,
     no_targets_status: ResponseStatus,
     #[serde(rename = "override")]
-    override_config: Option<OverrideConfig>,
+    override_config: Option<OverrideRules>,
+    errors: ErrorsConfig,
 }
 
 impl Default for ResponseConfig {
     fn default() -> Self {
         Self {
             target_selector: Default::default(),
-            failed_status_regex: "4\\d{2}|5\\d{2}".into(),
+            selector_expression: None,
+            aggregate: None,
+            prefer: Default::default(),
+            failover_timeout: None,
+            quorum: None,
+            failed_status_regex: StatusRegex::new("4\\d{2}|5\\d{2}")
+                .expect("default `failed_status_regex` is a valid regex"),
             no_targets_status: 500,
             override_config: None,
+            errors: Default::default(),
         }
     }
 }
 
+/// Regex to match response statuses, it's compiled once at load time
+#[derive(Clone)]
+pub struct StatusRegex(Regex);
+
+impl StatusRegex {
+    fn new(pattern: &str) -> Result<Self, regex::Error> {
+        Regex::new(pattern).map(StatusRegex)
+    }
+
+    /// Matches full status line, e.g. `404 Not Found`, so reason phrase can be used in the pattern
+    pub fn is_match(&self, status: StatusCode) -> bool {
+        self.0.is_match(&status.to_string())
+    }
+}
+
+impl std::fmt::Debug for StatusRegex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.as_str().fmt(f)
+    }
+}
+
+impl Serialize for StatusRegex {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for StatusRegex {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let pattern = String::deserialize(deserializer)?;
+        StatusRegex::new(&pattern).map_err(|e| {
+            de::Error::custom(format!("invalid `failed_status_regex` `{pattern}`: {e}"))
+        })
+    }
+}
+
 #[derive(Deserialize, Debug, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct OverrideConfig {
+    when: Option<JqFilter>,
     status: Option<ResponseStatus>,
     body: Option<String>,
+    content_type: Option<String>,
     headers: Option<Vec<HeaderTransform>>,
 }
 
+/// Ordered list of override rules, the first matching one is applied.
+/// Single rule may be defined as an object instead of a list.
+#[derive(Debug)]
+pub struct OverrideRules(Vec<OverrideConfig>);
+
+impl<'de> Deserialize<'de> for OverrideRules {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct OverrideRulesVisitor;
+        impl<'de> Visitor<'de> for OverrideRulesVisitor {
+            type Value = OverrideRules;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("override config or list of override rules")
+            }
+
+            fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+            where
+                A: de::MapAccess<'de>,
+            {
+                OverrideConfig::deserialize(de::value::MapAccessDeserializer::new(map))
+                    .map(|rule| OverrideRules(vec![rule]))
+            }
+
+            fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: de::SeqAccess<'de>,
+            {
+                Vec::deserialize(de::value::SeqAccessDeserializer::new(seq)).map(OverrideRules)
+            }
+        }
+
+        deserializer.deserialize_any(OverrideRulesVisitor)
+    }
+}
+
+impl Serialize for OverrideRules {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.0.serialize(serializer)
+    }
+}
+
+/// How the response to override has been created
+#[derive(Debug, Clone, Copy, PartialEq, Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum OverrideBranch {
+    /// Target's response is selected
+    Target,
+    /// Empty response is created instead of target's one
+    Override,
+    /// There is no suitable target's response
+    NoTarget,
+    /// Responses of targets are aggregated
+    Aggregate,
+}
+
+/// Builds input object of the override rules' `when` expressions
+fn override_input(
+    resp: &Response<Full<Bytes>>,
+    ctx: &Context,
+    branch: OverrideBranch,
+) -> JsonValue {
+    // Input content
+    // .branch
+    // .target_id
+    // .status
+    // .headers{}
+    // .body
+    // .body_text
+    // .env{}
+    let body = parse_body(resp.headers(), &response_body(resp));
+    let env = ctx.iter().collect::<HashMap<&String, &String>>();
+    json!({
+        "branch": branch.to_string(),
+        "target_id": ctx.get(&"CTX_TARGET_ID".into()),
+        "status": resp.status().as_u16(),
+        "headers": response_headers(resp),
+        "body": body.body,
+        "body_text": body.text,
+        "env": env,
+    })
+}
+
+impl OverrideRules {
+    /// Returns the first rule without `when` or with `when` evaluated to `true`
+    fn find(
+        &self,
+        resp: &Response<Full<Bytes>>,
+        ctx: &Context,
+        branch: OverrideBranch,
+    ) -> Option<&OverrideConfig> {
+        let mut input = None;
+        self.0.iter().find(|rule| match &rule.when {
+            None => true,
+            Some(when) => {
+                let input = input
+                    .get_or_insert_with(|| Val::from(override_input(resp, ctx, branch)))
+                    .clone();
+                match when.first(input) {
+                    Ok(value) => value == JsonValue::Bool(true),
+                    Err(e) => {
+                        warn!("override rule isn't applied: {e}");
+                        false
+                    }
+                }
+            }
+        })
+    }
+}
+
+impl ConfigValidator for OverrideRules {
+    fn validate(&self) -> Result<(), config::ConfigError> {
+        if let Some(pos) = self.0.iter().position(|rule| rule.when.is_none()) {
+            if pos + 1 < self.0.len() {
+                return Err(config::ConfigError::ValidateConfig {
+                    cause: "override rule without `when` should be the last one".into(),
+                });
+            }
+        }
+        for rule in &self.0 {
+            if let Some(status) = rule.status {
+                if StatusCode::from_u16(status).is_err() {
+                    return Err(config::ConfigError::ValidateConfig {
+                        cause: format!("invalid `override.status` status `{status}`"),
+                    });
+                }
+            }
+            validate_content_type(
+                rule.content_type.as_deref(),
+                rule.body.is_some(),
+                "response override",
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Order of responses to prefer if several of them are suitable for the strategy
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub enum ResponsePreference {
+    /// Order of targets in config
+    #[default]
+    ConfigOrder,
+    /// Higher target's `priority` first, order of targets in config for equal priorities
+    Priority,
+    /// Faster target's response first
+    Fastest,
+}
+
 impl ConfigValidator for ResponseConfig {
     fn validate(&self) -> Result<(), config::ConfigError> {
+        if StatusCode::from_u16(self.no_targets_status).is_err() {
+            return Err(config::ConfigError::ValidateConfig {
+                cause: format!(
+                    "invalid `no_targets_status` status `{}`",
+                    self.no_targets_status
+                ),
+            });
+        }
+        if let Some(aggregate) = &self.aggregate {
+            aggregate.validate()?;
+        }
+        if let Some(quorum) = &self.quorum {
+            quorum.validate()?;
+        }
+        if let Some(override_config) = &self.override_config {
+            override_config.validate()?;
+        }
+        self.errors.validate()?;
         Ok(())
     }
 }
 
+impl ResponseConfig {
+    /// Checks status against target's `failed_status_regex` or the listener's one
+    pub(crate) fn is_failed_status(&self, target: &TargetConfig, status: StatusCode) -> bool {
+        target
+            .failed_status_regex()
+            .unwrap_or(&self.failed_status_regex)
+            .is_match(status)
+    }
+}
+
+/// Error details of the response when there is no suitable target's response
+pub(crate) fn no_targets_error() -> ErrorDetails {
+    ErrorDetails::new(ErrorKind::NoTargets, "no suitable target's response")
+}
+
+/// Returns headers of the response as strings
+fn response_headers(resp: &Response<Full<Bytes>>) -> HashMap<String, String> {
+    resp.headers()
+        .iter()
+        .map(|(k, v)| (k.to_string(), String::from_utf8_lossy(v.as_bytes()).into()))
+        .collect()
+}
+
+/// Returns body of the response, in-memory body is always ready
+pub(crate) fn response_body(resp: &Response<Full<Bytes>>) -> Bytes {
+    resp.body()
+        .clone()
+        .collect()
+        .now_or_never()
+        .expect("in-memory body should be ready")
+        .expect("collecting of in-memory body can't fail")
+        .to_bytes()
+}
+
 pub trait ResponseBehavior {
     fn target_selector(&self) -> &Option<String>;
+    fn selector_expression(&self) -> Option<&JqFilter>;
+    fn aggregate(&self) -> Option<&AggregateConfig>;
+    fn prefer(&self) -> ResponsePreference;
+    fn failover_timeout(&self) -> Option<Duration>;
+    fn quorum(&self) -> Option<&QuorumConfig>;
+    fn count_ok_responses(&self, targets: &[&TargetConfig], responses: &ResponsesMap) -> usize;
+    fn preferred_targets<'a>(
+        &self,
+        targets: &[&'a TargetConfig],
+        durations: &[Duration],
+    ) -> Vec<&'a TargetConfig>;
+    fn find_response_by_expression(
+        &self,
+        target_ids: &[String],
+        responses: &ResponsesMap,
+    ) -> Result<Option<String>, String>;
+    fn aggregate_response(
+        &'static self,
+        targets: &[&TargetConfig],
+        responses: &ResponsesMap,
+        ctx: &Context,
+    ) -> Result<Response<Full<Bytes>>, String>;
     fn override_response(
         &'static self,
         resp: Response<Full<Bytes>>,
         ctx: &Context,
+        branch: OverrideBranch,
     ) -> Response<Full<Bytes>>;
     fn find_first_response(
         &self,
+        targets: &[&TargetConfig],
         responses: &ResponsesMap,
         response_kind: ResponseKind,
     ) -> Option<String>;
+    fn errors(&self) -> &ErrorsConfig;
     fn error_response(
         &self,
         e: ResponseResult,
         status: &Option<ResponseStatus>,
-    ) -> Response<Full<Bytes>>;
+        target_id: &str,
+        ctx: &Context,
+    ) -> Result<Response<Full<Bytes>>, Error>;
     fn empty_response(&self, status: ResponseStatus) -> Result<Response<Full<Bytes>>, Error>;
+    fn failure_response(
+        &self,
+        status: ResponseStatus,
+        details: ErrorDetails,
+        ctx: &Context,
+    ) -> Result<Response<Full<Bytes>>, Error>;
     fn override_empty_response(
         &'static self,
         status: ResponseStatus,
         ctx: &Context,
     ) -> Result<Response<Full<Bytes>>, Error>;
-    fn no_target_response(&'static self, ctx: &Context) -> Result<Response<Full<Bytes>>, Error>;
+    fn no_target_response(
+        &'static self,
+        details: ErrorDetails,
+        ctx: &Context,
+    ) -> Result<Response<Full<Bytes>>, Error>;
     fn select_from_two_targets_response(
         &'static self,
         first_target_id: Option<String>,
         second_target_id: Option<String>,
         responses: &mut ResponsesMap,
         ctx: &Context,
-    ) -> Response<Full<Bytes>>;
+    ) -> Result<Response<Full<Bytes>>, Error>;
     fn select_target_or_override_response(
         &'static self,
         target_id: Option<String>,
         responses: &mut ResponsesMap,
         ctx: &Context,
-    ) -> Response<Full<Bytes>>;
+    ) -> Result<Response<Full<Bytes>>, Error>;
     fn select_target_or_error_response(
         &'static self,
         target_id: Option<String>,
         responses: &mut ResponsesMap,
         ctx: &Context,
-    ) -> Response<Full<Bytes>>;
+    ) -> Result<Response<Full<Bytes>>, Error>;
+    fn quorum_response(
+        &'static self,
+        targets: &[&TargetConfig],
+        responses: &mut ResponsesMap,
+        ctx: &Context,
+    ) -> Result<Response<Full<Bytes>>, Error>;
 }
 
 #[derive(Debug)]
@@ -109,79 +436,225 @@ impl ResponseBehavior for ResponseConfig {
         &self.target_selector
     }
 
+    fn selector_expression(&self) -> Option<&JqFilter> {
+        self.selector_expression.as_ref()
+    }
+
+    fn aggregate(&self) -> Option<&AggregateConfig> {
+        self.aggregate.as_ref()
+    }
+
+    fn prefer(&self) -> ResponsePreference {
+        self.prefer
+    }
+
+    /// Returns total time limit of querying targets one by one in case of `failover` strategy
+    fn failover_timeout(&self) -> Option<Duration> {
+        self.failover_timeout
+    }
+
+    fn quorum(&self) -> Option<&QuorumConfig> {
+        self.quorum.as_ref()
+    }
+
+    /// Returns number of successful responses of the targets
+    fn count_ok_responses(&self, targets: &[&TargetConfig], responses: &ResponsesMap) -> usize {
+        targets
+            .iter()
+            .filter(|target| match responses.get(&target.id()) {
+                Some((Some(resp), _)) => !self.is_failed_status(target, resp.status()),
+                _ => false,
+            })
+            .count()
+    }
+
+    /// Returns targets in order of preference of their responses,
+    /// `durations` are times of targets' responses in the same order as targets
+    fn preferred_targets<'a>(
+        &self,
+        targets: &[&'a TargetConfig],
+        durations: &[Duration],
+    ) -> Vec<&'a TargetConfig> {
+        let mut ordered: Vec<(&TargetConfig, &Duration)> =
+            targets.iter().copied().zip(durations).collect();
+        // Sort is stable, so order of targets in config is kept for equal values
+        match self.prefer() {
+            ResponsePreference::ConfigOrder => {}
+            ResponsePreference::Priority => ordered.sort_by_key(|(t, _)| Reverse(t.priority())),
+            ResponsePreference::Fastest => ordered.sort_by_key(|(_, d)| **d),
+        }
+
+        ordered.into_iter().map(|(t, _)| t).collect()
+    }
+
+    /// Evaluates `selector_expression` over all available responses, in order of targets,
+    /// and returns target id of the selected response or `None` if the override should be used
+    fn find_response_by_expression(
+        &self,
+        target_ids: &[String],
+        responses: &ResponsesMap,
+    ) -> Result<Option<String>, String> {
+        let Some(filter) = self.selector_expression() else {
+            return Ok(None);
+        };
+
+        // Input content
+        // .[].target_id
+        // .[].status
+        // .[].headers{}
+        // .[].body
+        let input: Vec<JsonValue> = target_ids
+            .iter()
+            .filter_map(|id| match responses.get(id) {
+                Some((Some(resp), _)) => Some((id, resp)),
+                _ => None,
+            })
+            .map(|(id, resp)| {
+                let body = parse_body(resp.headers(), &response_body(resp));
+                json!({
+                    "target_id": id,
+                    "status": resp.status().as_u16(),
+                    "headers": response_headers(resp),
+                    "body": body.text.map_or(body.body, JsonValue::String),
+                })
+            })
+            .collect();
+        debug!("selector input=`{:#?}`", input);
+
+        match filter.first(Val::from(JsonValue::Array(input))) {
+            Ok(JsonValue::Null) => Ok(None),
+            Ok(JsonValue::String(id)) if responses.get(&id).is_some_and(|(r, _)| r.is_some()) => {
+                debug!("selected target id={id}");
+                Ok(Some(id))
+            }
+            Ok(value) => Err(format!(
+                "`selector_expression` returned unknown target id: {value}"
+            )),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// Merges JSON bodies of all available responses, in order of targets, into a single JSON response.
+    /// Failed and non-JSON responses are handled according to `on_aggregate_error` of the target,
+    /// error is returned if such response should fail the whole request.
+    fn aggregate_response(
+        &'static self,
+        targets: &[&TargetConfig],
+        responses: &ResponsesMap,
+        ctx: &Context,
+    ) -> Result<Response<Full<Bytes>>, String> {
+        let mut items = vec![];
+        for target in targets {
+            let target_id = target.id();
+            let Some((Some(resp), _)) = responses.get(&target_id) else {
+                continue;
+            };
+            let body = if self.is_failed_status(target, resp.status()) {
+                Err(format!("failed status {}", resp.status().as_u16()))
+            } else {
+                serde_json::from_slice::<JsonValue>(&response_body(resp))
+                    .map_err(|e| format!("body isn't valid JSON: {e}"))
+            };
+            if let Err(e) = &body {
+                match target.on_aggregate_error() {
+                    TargetOnAggregateError::Skip => {
+                        debug!("target `{target_id}` response is skipped: {e}");
+                        continue;
+                    }
+                    TargetOnAggregateError::Include => {}
+                    TargetOnAggregateError::Fail => {
+                        return Err(format!(
+                            "target `{target_id}` response can't be aggregated: {e}"
+                        ))
+                    }
+                }
+            }
+            items.push(AggregateItem {
+                target_id,
+                status: resp.status().as_u16(),
+                headers: response_headers(resp),
+                body,
+            });
+        }
+
+        let default_cfg = AggregateConfig::default();
+        let cfg = self.aggregate().unwrap_or(&default_cfg);
+        let body = cfg.aggregate(items).map_err(|e| e.to_string())?;
+        debug!("aggregated body=`{body}`");
+
+        let resp = Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, HeaderValue::from_static("application/json"))
+            .body(Full::from(body.to_string()))
+            .expect(UNABLE_TO_CREATE_RESPONSE_ERROR);
+        Ok(self.override_response(resp, ctx, OverrideBranch::Aggregate))
+    }
+
     fn override_response(
         &'static self,
         resp: Response<Full<Bytes>>,
         ctx: &Context,
+        branch: OverrideBranch,
     ) -> Response<Full<Bytes>> {
-        if let Some(cfg) = &self.override_config {
-            let (resp_parts, resp_body) = resp.into_parts();
-            let mut new_resp = Response::builder();
+        let cfg = self
+            .override_config
+            .as_ref()
+            .and_then(|rules| rules.find(&resp, ctx, branch));
+        if let Some(cfg) = cfg {
+            let (mut resp_parts, resp_body) = resp.into_parts();
 
-            // Set status
-            new_resp = if let Some(status) = cfg.status {
-                new_resp.status(status)
-            } else {
-                new_resp.status(resp_parts.status)
-            };
+            // Set status, it's validated to be a valid one
+            if let Some(status) = cfg.status.and_then(|s| StatusCode::from_u16(s).ok()) {
+                resp_parts.status = status;
+            }
 
-            // Prepare headers
-            let mut headers = resp_parts.headers;
+            // Prepare body and headers, body related ones are updated after transformations
             if let Some(transforms) = &cfg.headers {
-                transforms.transform(&mut headers, ctx)
+                transforms.transform(&mut resp_parts.headers, ctx)
             }
-            for (k, v) in &headers {
-                new_resp = new_resp.header(k, v);
-            }
-
-            // Prepare body
             let body: Full<Bytes> = if let Some(body) = &cfg.body {
-                // Remove Content-length header since it's incorrect now
-                headers.remove(CONTENT_LENGTH);
                 let body: String = env_with_context_no_errors(&body, |v| ctx.get(&v.into())).into();
+                update_body_headers(
+                    &mut resp_parts.headers,
+                    body.len(),
+                    cfg.content_type.as_deref(),
+                );
                 Full::from(body)
             } else {
                 resp_body
             };
 
             // Final response
-            new_resp.body(body).expect(UNABLE_TO_CREATE_RESPONSE_ERROR)
+            Response::from_parts(resp_parts, body)
         } else {
             resp
         }
     }
 
+    /// Returns target id of the first response of the required kind, targets are checked in the given order
     fn find_first_response(
         &self,
+        targets: &[&TargetConfig],
         responses: &ResponsesMap,
         response_kind: ResponseKind,
     ) -> Option<String> {
         debug!("looking for {:?}", response_kind);
 
-        let re = Regex::new(&self.failed_status_regex).unwrap_or_else(|_| {
-            panic!(
-                "unable parse regex expression: {}",
-                self.failed_status_regex
-            )
-        });
-        for key in responses.keys() {
-            let (resp, _) = responses
-                .get(key)
-                .expect("unable to get header value by key, looks like a BUG");
-            if let Some(resp) = resp {
-                let status: String = resp.status().to_string();
-                let is_failed = re.is_match(&status);
+        for target in targets {
+            let key = target.id();
+            if let Some((Some(resp), _)) = responses.get(&key) {
+                let is_failed = self.is_failed_status(target, resp.status());
                 match response_kind {
                     ResponseKind::Ok => {
                         if !is_failed {
                             debug!("found target id={}", key);
-                            return Some(key.into());
+                            return Some(key);
                         }
                     }
                     ResponseKind::Failed => {
                         if is_failed {
                             debug!("found target id={}", key);
-                            return Some(key.into());
+                            return Some(key);
                         }
                     }
                 }
@@ -192,33 +665,52 @@ impl ResponseBehavior for ResponseConfig {
         None
     }
 
+    fn errors(&self) -> &ErrorsConfig {
+        &self.errors
+    }
+
     fn error_response(
         &self,
         e: ResponseResult,
         status: &Option<ResponseStatus>,
-    ) -> Response<Full<Bytes>> {
-        let resp = Response::builder();
-        let resp = if let Some(status) = status.to_owned() {
-            resp.status(status)
-        } else {
-            match e {
-                ResponseResult::HyperError(e) => {
-                    if e.is_connect() {
-                        resp.status(StatusCode::BAD_GATEWAY)
-                    } else {
-                        resp.status(StatusCode::INTERNAL_SERVER_ERROR)
-                    }
-                }
-                ResponseResult::Timeout => resp.status(StatusCode::GATEWAY_TIMEOUT),
-                ResponseResult::SigningError(_) => resp.status(StatusCode::INTERNAL_SERVER_ERROR),
-                _ => {
-                    panic!("Looks like a BUG!")
+        target_id: &str,
+        ctx: &Context,
+    ) -> Result<Response<Full<Bytes>>, Error> {
+        let (default_status, details) = match e {
+            ResponseResult::HyperError(e) => {
+                let message = match std::error::Error::source(&e) {
+                    Some(source) => format!("{e}: {source}"),
+                    None => e.to_string(),
+                };
+                if e.is_connect() {
+                    (
+                        StatusCode::BAD_GATEWAY,
+                        ErrorDetails::new(ErrorKind::Connect, message),
+                    )
+                } else {
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        ErrorDetails::new(ErrorKind::Upstream, message),
+                    )
                 }
             }
+            ResponseResult::Timeout => (
+                StatusCode::GATEWAY_TIMEOUT,
+                ErrorDetails::new(ErrorKind::Timeout, "target's request timed out"),
+            ),
+            ResponseResult::SigningError(cause) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorDetails::new(ErrorKind::Signing, cause),
+            ),
+            ResponseResult::TargetError(cause) => (
+                StatusCode::BAD_GATEWAY,
+                ErrorDetails::new(ErrorKind::Upstream, cause),
+            ),
+            ResponseResult::Ok(resp) => return Ok(resp),
         };
+        let status = status.unwrap_or(default_status.as_u16());
 
-        resp.body(Full::from(Bytes::new()))
-            .expect(UNABLE_TO_CREATE_RESPONSE_ERROR)
+        self.failure_response(status, details.with_target(target_id), ctx)
     }
 
     fn empty_response(&self, status: ResponseStatus) -> Result<Response<Full<Bytes>>, Error> {
@@ -227,18 +719,38 @@ impl ResponseBehavior for ResponseConfig {
             .body(Full::from(Bytes::new()))
     }
 
+    fn failure_response(
+        &self,
+        status: ResponseStatus,
+        details: ErrorDetails,
+        ctx: &Context,
+    ) -> Result<Response<Full<Bytes>>, Error> {
+        let status = StatusCode::from_u16(status)?;
+        match self.errors.body(status, &details, ctx) {
+            Some((body, content_type)) => Response::builder()
+                .status(status)
+                .header(CONTENT_TYPE, content_type)
+                .body(Full::from(body)),
+            None => self.empty_response(status.as_u16()),
+        }
+    }
+
     fn override_empty_response(
         &'static self,
         status: ResponseStatus,
         ctx: &Context,
     ) -> Result<Response<Full<Bytes>>, Error> {
         let empty = self.empty_response(status)?;
-        Ok(self.override_response(empty, ctx))
+        Ok(self.override_response(empty, ctx, OverrideBranch::Override))
     }
 
-    fn no_target_response(&'static self, ctx: &Context) -> Result<Response<Full<Bytes>>, Error> {
-        let empty: Response<Full<Bytes>> = self.empty_response(self.no_targets_status)?;
-        Ok(self.override_response(empty, ctx))
+    fn no_target_response(
+        &'static self,
+        details: ErrorDetails,
+        ctx: &Context,
+    ) -> Result<Response<Full<Bytes>>, Error> {
+        let resp = self.failure_response(self.no_targets_status, details, ctx)?;
+        Ok(self.override_response(resp, ctx, OverrideBranch::NoTarget))
     }
 
     fn select_from_two_targets_response(
@@ -247,12 +759,12 @@ impl ResponseBehavior for ResponseConfig {
         second_target_id: Option<String>,
         responses: &mut ResponsesMap,
         ctx: &Context,
-    ) -> Response<Full<Bytes>> {
+    ) -> Result<Response<Full<Bytes>>, Error> {
         if let Some(target_id) = first_target_id {
             if let Some((resp, ctx)) = responses.remove(&target_id) {
                 let resp = resp.expect(UNABLE_TO_CREATE_RESPONSE_ERROR);
                 let ctx = ctx.with_response(&resp);
-                self.override_response(resp, &ctx)
+                Ok(self.override_response(resp, &ctx, OverrideBranch::Target))
             } else {
                 self.select_from_two_targets_response(None, second_target_id, responses, ctx)
             }
@@ -260,18 +772,15 @@ impl ResponseBehavior for ResponseConfig {
             if let Some((resp, ctx)) = responses.remove(&target_id) {
                 if let Some(resp) = resp {
                     let ctx = ctx.with_response(&resp);
-                    self.override_response(resp, &ctx)
+                    Ok(self.override_response(resp, &ctx, OverrideBranch::Target))
                 } else {
-                    self.no_target_response(ctx)
-                        .expect(UNABLE_TO_CREATE_RESPONSE_ERROR)
+                    self.no_target_response(no_targets_error(), ctx)
                 }
             } else {
-                self.no_target_response(ctx)
-                    .expect(UNABLE_TO_CREATE_RESPONSE_ERROR)
+                self.no_target_response(no_targets_error(), ctx)
             }
         } else {
-            self.no_target_response(ctx)
-                .expect(UNABLE_TO_CREATE_RESPONSE_ERROR)
+            self.no_target_response(no_targets_error(), ctx)
         }
     }
 
@@ -280,19 +789,17 @@ impl ResponseBehavior for ResponseConfig {
         target_id: Option<String>,
         responses: &mut ResponsesMap,
         ctx: &Context,
-    ) -> Response<Full<Bytes>> {
+    ) -> Result<Response<Full<Bytes>>, Error> {
         if let Some(target_id) = target_id {
             if let Some((resp, ctx)) = responses.remove(&target_id) {
                 let resp = resp.expect(UNABLE_TO_CREATE_RESPONSE_ERROR);
                 let ctx = ctx.with_response(&resp);
-                self.override_response(resp, &ctx)
+                Ok(self.override_response(resp, &ctx, OverrideBranch::Target))
             } else {
                 self.override_empty_response(StatusCode::OK.into(), ctx)
-                    .expect(UNABLE_TO_CREATE_RESPONSE_ERROR)
             }
         } else {
             self.override_empty_response(StatusCode::OK.into(), ctx)
-                .expect(UNABLE_TO_CREATE_RESPONSE_ERROR)
         }
     }
 
@@ -301,23 +808,428 @@ impl ResponseBehavior for ResponseConfig {
         target_id: Option<String>,
         responses: &mut ResponsesMap,
         ctx: &Context,
-    ) -> Response<Full<Bytes>> {
+    ) -> Result<Response<Full<Bytes>>, Error> {
         if let Some(target_id) = target_id {
             if let Some((resp, ctx)) = responses.remove(&target_id) {
                 if let Some(resp) = resp {
                     let ctx = ctx.with_response(&resp);
-                    self.override_response(resp, &ctx)
+                    Ok(self.override_response(resp, &ctx, OverrideBranch::Target))
                 } else {
-                    self.no_target_response(ctx)
-                        .expect(UNABLE_TO_CREATE_RESPONSE_ERROR)
+                    self.no_target_response(no_targets_error(), ctx)
                 }
             } else {
-                self.no_target_response(ctx)
-                    .expect(UNABLE_TO_CREATE_RESPONSE_ERROR)
+                self.no_target_response(no_targets_error(), ctx)
             }
         } else {
-            self.no_target_response(ctx)
-                .expect(UNABLE_TO_CREATE_RESPONSE_ERROR)
+            self.no_target_response(no_targets_error(), ctx)
+        }
+    }
+
+    /// Responds with the preferred successful response (or override) if quorum is reached,
+    /// otherwise with the preferred failed response (or `no_targets_status` response)
+    fn quorum_response(
+        &'static self,
+        targets: &[&TargetConfig],
+        responses: &mut ResponsesMap,
+        ctx: &Context,
+    ) -> Result<Response<Full<Bytes>>, Error> {
+        let quorum = self
+            .quorum()
+            .expect("quorum is validated to be present for `quorum` strategy");
+        let ok_count = self.count_ok_responses(targets, responses);
+        let required = quorum.required(targets.len());
+        let reached = ok_count >= required;
+        debug!("quorum: {ok_count} of {required} required responses are successful");
+
+        let resp = match (reached, quorum.respond_with()) {
+            (true, QuorumRespondWith::Target) => {
+                let target_id = self.find_first_response(targets, responses, ResponseKind::Ok);
+                self.select_target_or_override_response(target_id, responses, ctx)?
+            }
+            (true, QuorumRespondWith::Override) => {
+                self.override_empty_response(StatusCode::OK.into(), ctx)?
+            }
+            (false, QuorumRespondWith::Target) => {
+                let target_id = self.find_first_response(targets, responses, ResponseKind::Failed);
+                self.select_target_or_error_response(target_id, responses, ctx)?
+            }
+            (false, QuorumRespondWith::Override) => {
+                self.no_target_response(no_targets_error(), ctx)?
+            }
+        };
+
+        Ok(resp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::compression::ContentEncoding,
+        context::{test_context::get_test_ctx, ContextMap},
+    };
+    use hyper::header::CONTENT_ENCODING;
+
+    fn response(status: u16, content_type: &str, body: &'static str) -> Response<Full<Bytes>> {
+        Response::builder()
+            .status(status)
+            .header("content-type", content_type)
+            .body(Full::from(body))
+            .unwrap()
+    }
+
+    fn select(expression: &str) -> Result<Option<String>, String> {
+        let cfg: ResponseConfig =
+            serde_yaml_ng::from_str(&format!("selector_expression: '{expression}'")).unwrap();
+        let ctx = get_test_ctx();
+        let mut responses = ResponsesMap::new();
+        responses.insert(
+            "A".into(),
+            (
+                Some(response(200, "application/json", r#"{"version": 2}"#)),
+                ctx,
+            ),
+        );
+        responses.insert(
+            "B".into(),
+            (
+                Some(response(200, "application/json", r#"{"version": 5}"#)),
+                ctx,
+            ),
+        );
+        responses.insert("C".into(), (Some(response(503, "text/plain", "busy")), ctx));
+        responses.insert("DROPPED".into(), (None, ctx));
+        let target_ids = ["A", "B", "C", "DROPPED"].map(String::from);
+
+        cfg.find_response_by_expression(&target_ids, &responses)
+    }
+
+    #[test]
+    fn select_by_expression() {
+        assert_eq!(
+            select("max_by(.body.version? // 0).target_id"),
+            Ok(Some("B".into()))
+        );
+        assert_eq!(select(".[0].target_id"), Ok(Some("A".into())));
+        assert_eq!(
+            select(r#"first(.[] | select(.status >= 500 and .body == "busy")).target_id"#),
+            Ok(Some("C".into()))
+        );
+        assert_eq!(
+            select("length"),
+            Err("`selector_expression` returned unknown target id: 3".into())
+        );
+        assert_eq!(select("null"), Ok(None));
+        assert_eq!(select("empty"), Ok(None));
+        assert!(select(r#""DROPPED""#).is_err());
+        assert!(select(r#""UNKNOWN""#).is_err());
+        assert!(select(".[0].body | tonumber").is_err());
+    }
+
+    fn aggregate(on_aggregate_error: &str) -> Result<JsonValue, String> {
+        let cfg: &'static ResponseConfig =
+            Box::leak(Box::new(serde_yaml_ng::from_str("aggregate: {}").unwrap()));
+        let target = |id: &str| -> TargetConfig {
+            serde_yaml_ng::from_str(&format!(
+                "{{id: {id}, url: 'http://localhost', on_aggregate_error: {on_aggregate_error}}}"
+            ))
+            .unwrap()
+        };
+        let targets = [target("A"), target("B"), target("C"), target("DROPPED")];
+        let targets: Vec<&TargetConfig> = targets.iter().collect();
+        let ctx = get_test_ctx();
+        let mut responses = ResponsesMap::new();
+        responses.insert(
+            "A".into(),
+            (Some(response(200, "application/json", r#"{"id": 1}"#)), ctx),
+        );
+        responses.insert("B".into(), (Some(response(200, "text/plain", "text")), ctx));
+        responses.insert(
+            "C".into(),
+            (Some(response(503, "application/json", "{}")), ctx),
+        );
+        responses.insert("DROPPED".into(), (None, ctx));
+
+        let resp = cfg.aggregate_response(&targets, &responses, ctx)?;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[CONTENT_TYPE], "application/json");
+        Ok(serde_json::from_slice(&response_body(&resp)).unwrap())
+    }
+
+    #[test]
+    fn aggregate_errors() {
+        assert_eq!(aggregate("skip"), Ok(json!({"A": {"id": 1}})));
+
+        let aggregated = aggregate("include").unwrap();
+        assert_eq!(aggregated["A"], json!({"id": 1}));
+        assert_eq!(aggregated["B"]["status"], 200);
+        assert!(aggregated["B"]["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("body isn't valid JSON"));
+        assert_eq!(
+            aggregated["C"],
+            json!({"status": 503, "error": "failed status 503"})
+        );
+        assert!(aggregated.get("DROPPED").is_none());
+
+        assert_eq!(
+            aggregate("fail").unwrap_err(),
+            "target `B` response can't be aggregated: body isn't valid JSON: expected ident at line 1 column 2"
+        );
+    }
+
+    #[test]
+    fn preferred_targets() {
+        let targets: Vec<TargetConfig> = [
+            "{id: A, url: 'http://localhost'}",
+            "{id: B, url: 'http://localhost', priority: 10}",
+            "{id: C, url: 'http://localhost', priority: -1}",
+            "{id: D, url: 'http://localhost', priority: 10}",
+        ]
+        .iter()
+        .map(|yaml| serde_yaml_ng::from_str(yaml).unwrap())
+        .collect();
+        let targets: Vec<&TargetConfig> = targets.iter().collect();
+        let durations = [300, 200, 100, 200].map(Duration::from_millis);
+
+        let order = |prefer: &str| -> Vec<String> {
+            let cfg: ResponseConfig =
+                serde_yaml_ng::from_str(&format!("prefer: {prefer}")).unwrap();
+            cfg.preferred_targets(&targets, &durations)
+                .iter()
+                .map(|t| t.id())
+                .collect()
+        };
+        assert_eq!(order("config_order"), ["A", "B", "C", "D"]);
+        assert_eq!(order("priority"), ["B", "D", "A", "C"]);
+        assert_eq!(order("fastest"), ["C", "B", "D", "A"]);
+        assert!(serde_yaml_ng::from_str::<ResponseConfig>("prefer: random").is_err());
+    }
+
+    #[test]
+    fn failed_status_regex() {
+        let target: TargetConfig = serde_yaml_ng::from_str(
+            "{id: A, url: 'http://localhost', failed_status_regex: 'Not Found|Gateway'}",
+        )
+        .unwrap();
+        let cfg = ResponseConfig::default();
+        assert!(cfg.is_failed_status(&target, StatusCode::NOT_FOUND));
+        assert!(cfg.is_failed_status(&target, StatusCode::BAD_GATEWAY));
+        assert!(cfg.is_failed_status(&target, StatusCode::GATEWAY_TIMEOUT));
+        assert!(!cfg.is_failed_status(&target, StatusCode::INTERNAL_SERVER_ERROR));
+
+        let target: TargetConfig =
+            serde_yaml_ng::from_str("{id: B, url: 'http://localhost'}").unwrap();
+        assert!(cfg.is_failed_status(&target, StatusCode::NOT_FOUND));
+        assert!(cfg.is_failed_status(&target, StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!cfg.is_failed_status(&target, StatusCode::OK));
+        assert!(!cfg.is_failed_status(&target, StatusCode::PERMANENT_REDIRECT));
+    }
+
+    #[test]
+    fn find_first_response() {
+        let targets: Vec<TargetConfig> = [
+            "{id: A, url: 'http://localhost'}",
+            "{id: B, url: 'http://localhost', failed_status_regex: '404'}",
+            "{id: C, url: 'http://localhost'}",
+            "{id: D, url: 'http://localhost'}",
+        ]
+        .iter()
+        .map(|yaml| serde_yaml_ng::from_str(yaml).unwrap())
+        .collect();
+        let targets: Vec<&TargetConfig> = targets.iter().collect();
+        let ctx = get_test_ctx();
+        let mut responses = ResponsesMap::new();
+        responses.insert("A".into(), (None, ctx));
+        responses.insert("B".into(), (Some(response(503, "text/plain", "")), ctx));
+        responses.insert("C".into(), (Some(response(500, "text/plain", "")), ctx));
+        responses.insert("D".into(), (Some(response(200, "text/plain", "")), ctx));
+
+        let cfg = ResponseConfig::default();
+        assert_eq!(
+            cfg.find_first_response(&targets, &responses, ResponseKind::Ok),
+            Some("B".into())
+        );
+        assert_eq!(
+            cfg.find_first_response(&targets, &responses, ResponseKind::Failed),
+            Some("C".into())
+        );
+        let reversed: Vec<&TargetConfig> = targets.iter().rev().copied().collect();
+        assert_eq!(
+            cfg.find_first_response(&reversed, &responses, ResponseKind::Ok),
+            Some("D".into())
+        );
+        assert_eq!(
+            cfg.find_first_response(&targets[..1], &responses, ResponseKind::Ok),
+            None
+        );
+    }
+
+    #[test]
+    fn quorum_response() {
+        let targets: Vec<TargetConfig> = ["A", "B", "C"]
+            .iter()
+            .map(|id| {
+                serde_yaml_ng::from_str(&format!("{{id: {id}, url: 'http://localhost'}}")).unwrap()
+            })
+            .collect();
+        let targets: Vec<&TargetConfig> = targets.iter().collect();
+        let ctx = get_test_ctx();
+
+        let respond = |quorum: &str, statuses: [u16; 3]| {
+            let cfg: &'static ResponseConfig = Box::leak(Box::new(
+                serde_yaml_ng::from_str(&format!("{{quorum: {quorum}, no_targets_status: 503}}"))
+                    .unwrap(),
+            ));
+            let mut responses = ResponsesMap::new();
+            for (target, status) in targets.iter().zip(statuses) {
+                let resp = response(status, "text/plain", "");
+                responses.insert(target.id(), (Some(resp), ctx));
+            }
+            assert_eq!(
+                cfg.count_ok_responses(&targets, &responses),
+                statuses.iter().filter(|s| **s < 400).count()
+            );
+            cfg.quorum_response(&targets, &mut responses, ctx)
+                .unwrap()
+                .status()
+                .as_u16()
+        };
+
+        assert_eq!(respond("{min_ok: 2}", [500, 201, 202]), 201);
+        assert_eq!(respond("{min_ok: 2}", [200, 502, 501]), 502);
+        assert_eq!(respond("{min_ok: 67%}", [200, 200, 500]), 500);
+        assert_eq!(respond("{min_ok: 66%}", [200, 200, 500]), 200);
+        assert_eq!(
+            respond("{min_ok: 1, respond_with: override}", [500, 201, 500]),
+            200
+        );
+        assert_eq!(
+            respond("{min_ok: 3, respond_with: override}", [200, 201, 500]),
+            503
+        );
+    }
+
+    #[test]
+    fn error_response() {
+        let cfg = ResponseConfig::default();
+        let ctx = &get_test_ctx();
+        let body = |resp: &Response<Full<Bytes>>| -> JsonValue {
+            serde_json::from_slice(&response_body(resp)).unwrap()
+        };
+
+        let resp = cfg
+            .error_response(
+                ResponseResult::TargetError("request has been aborted".into()),
+                &None,
+                "A",
+                ctx,
+            )
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(body(&resp)["kind"], "upstream");
+        assert_eq!(body(&resp)["target_id"], "A");
+
+        let resp = cfg
+            .error_response(ResponseResult::Timeout, &Some(503), "A", ctx)
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body(&resp)["kind"], "timeout");
+
+        // Successful response is passed as is
+        let resp = cfg
+            .error_response(
+                ResponseResult::Ok(response(201, "text/plain", "created")),
+                &None,
+                "A",
+                ctx,
+            )
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert_eq!(response_body(&resp), "created");
+    }
+
+    #[test]
+    fn override_rules() {
+        let cfg: &'static ResponseConfig = Box::leak(Box::new(
+            serde_yaml_ng::from_str(
+                r#"
+override:
+  - when: .status >= 500 and .branch == "target"
+    status: 503
+    body: '{"error": "try later"}'
+  - when: .target_id == "A"
+    headers:
+      - add: x-target
+        value: ${CTX_TARGET_ID}
+"#,
+            )
+            .unwrap(),
+        ));
+        assert!(cfg.validate().is_ok());
+        let ctx = get_test_ctx();
+        let target_ctx = ctx.with(ContextMap::from([("CTX_TARGET_ID".into(), "A".into())]));
+
+        let resp = cfg.override_response(
+            response(502, "text/plain", "bad gateway"),
+            &target_ctx,
+            OverrideBranch::Target,
+        );
+        assert_eq!(resp.status(), 503);
+        assert_eq!(response_body(&resp), r#"{"error": "try later"}"#);
+        assert!(!resp.headers().contains_key("x-target"));
+
+        let resp = cfg.override_response(
+            response(200, "text/plain", "ok"),
+            &target_ctx,
+            OverrideBranch::Target,
+        );
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["x-target"], "A");
+
+        // No matching rules
+        let resp = cfg.override_response(
+            response(502, "text/plain", ""),
+            ctx,
+            OverrideBranch::NoTarget,
+        );
+        assert_eq!(resp.status(), 502);
+        assert!(!resp.headers().contains_key("x-target"));
+
+        // Single rule and wrong order of rules
+        let cfg: ResponseConfig = serde_yaml_ng::from_str("override: {status: 201}").unwrap();
+        assert!(cfg.validate().is_ok());
+        let cfg: ResponseConfig =
+            serde_yaml_ng::from_str("override: [{status: 201}, {when: .status == 200}]").unwrap();
+        assert!(cfg.validate().is_err());
+        assert!(serde_yaml_ng::from_str::<ResponseConfig>("override: {state: 201}").is_err());
+
+        // Statuses out of range
+        for yaml in ["override: {status: 1000}", "no_targets_status: 99"] {
+            let cfg: ResponseConfig = serde_yaml_ng::from_str(yaml).unwrap();
+            assert!(cfg.validate().is_err(), "`{yaml}` should be rejected");
         }
     }
+
+    #[test]
+    fn override_body_of_encoded_response() {
+        let cfg: &'static ResponseConfig = Box::leak(Box::new(
+            serde_yaml_ng::from_str("override: {body: replaced, content_type: text/plain}")
+                .unwrap(),
+        ));
+        let gzipped = crate::compression::encode(ContentEncoding::Gzip, b"original body").unwrap();
+        let resp = Response::builder()
+            .header(CONTENT_ENCODING, "gzip")
+            .header(CONTENT_TYPE, "text/html")
+            .body(Full::from(gzipped))
+            .unwrap();
+
+        let resp = cfg.override_response(resp, get_test_ctx(), OverrideBranch::Target);
+
+        assert!(!resp.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(resp.headers()[CONTENT_TYPE], "text/plain");
+        assert_eq!(response_body(&resp), "replaced");
+    }
 }
@@ -1,20 +1,31 @@
 use super::{
     aws_sigv4::AwsSigV4Config,
-    headers::HeaderTransform,
+    compression::ContentEncoding,
+    extract::ExtractConfig,
+    headers::{validate_content_type, HeaderTransform},
+    hmac::HmacConfig,
+    jq::{JqError, JqFilter},
     listener::{TlsConfig, TlsVerifyConfig},
-    response::ResponseStatus,
+    oauth2::OAuth2Config,
+    response::{response_body, ResponseStatus, StatusRegex},
+    target_response::TargetResponseConfig,
+    uri::UriTransform,
     ConfigValidator,
 };
-use crate::{config::ConfigError, context::Context};
+use crate::{
+    body_parser::parse_body,
+    condition::ConditionInput,
+    config::ConfigError,
+    context::{step_prefix, targets_prefix, Context, ContextMap},
+};
 use core::fmt;
 use http_body_util::Full;
-use hyper::{body::Bytes, http::request::Parts, Request, Uri};
+use hyper::{body::Bytes, Request, Response, StatusCode, Uri};
 use hyper_rustls::HttpsConnectorBuilder;
 use hyper_util::{
     client::legacy::{connect::HttpConnector, Client},
     rt::TokioExecutor,
 };
-use jaq_core::{load, Compiler, Ctx, Filter, Native, RcIter};
 use jaq_json::Val;
 use rustls::{
     client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
@@ -26,28 +37,31 @@ use serde::{
     de::{self, Visitor},
     Deserialize, Deserializer,
 };
-use serde_json::{json, value::Value as JsonValue, Value};
+use serde_json::Value as JsonValue;
 use std::{
     collections::{HashMap, HashSet},
     fs::File,
     io::BufReader,
+    str::FromStr,
     sync::{Arc, LazyLock, RwLock},
     time::Duration,
 };
-use tracing::debug;
+use tracing::{debug, warn};
 
 const DEFAULT_TARGET_TIMEOUT_SEC: u64 = 60;
 
 pub type TargetConfigList = Vec<TargetConfig>;
-type HttpsClient = Client<hyper_rustls::HttpsConnector<HttpConnector>, Full<Bytes>>;
+pub(crate) type HttpsClient = Client<hyper_rustls::HttpsConnector<HttpConnector>, Full<Bytes>>;
 
 #[derive(Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct TargetConfig {
     id: Option<String>,
     url: String,
+    uri: Option<Vec<UriTransform>>,
     headers: Option<Vec<HeaderTransform>>,
     body: Option<String>,
+    content_type: Option<String>,
     #[serde(
         with = "humantime_serde",
         default = "TargetConfig::default_target_timeout"
@@ -58,8 +72,21 @@ pub struct TargetConfig {
     error_status: Option<ResponseStatus>,
     condition: Option<TargetConditionConfig>,
     #[serde(default)]
+    on_condition_error: TargetOnJqError,
+    #[serde(default)]
+    on_aggregate_error: TargetOnAggregateError,
+    #[serde(default)]
+    priority: i32,
+    failed_status_regex: Option<StatusRegex>,
+    depends_on: Option<Vec<String>>,
+    extract: Option<ExtractConfig>,
+    response: Option<TargetResponseConfig>,
+    #[serde(default)]
     tls: Option<TlsConfig>,
     aws_sigv4: Option<AwsSigV4Config>,
+    hmac: Option<HmacConfig>,
+    oauth2: Option<OAuth2Config>,
+    compress_request: Option<ContentEncoding>,
 }
 
 impl TargetConfig {
@@ -93,6 +120,11 @@ impl TargetConfig {
         self.url.as_ref()
     }
 
+    /// Returns transformations of the target's URI, if they're defined.
+    pub fn uri_transforms(&self) -> Option<&Vec<UriTransform>> {
+        self.uri.as_ref()
+    }
+
     pub fn headers(&self) -> &Option<Vec<HeaderTransform>> {
         &self.headers
     }
@@ -101,6 +133,11 @@ impl TargetConfig {
         &self.body
     }
 
+    /// Returns content type of the request `body`, if it's defined.
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
     pub fn timeout(&self) -> &Duration {
         &self.timeout
     }
@@ -117,29 +154,110 @@ impl TargetConfig {
         &self.condition
     }
 
+    /// Returns how to treat the condition if its evaluation fails
+    pub fn on_condition_error(&self) -> TargetOnJqError {
+        self.on_condition_error
+    }
+
+    pub fn on_aggregate_error(&self) -> TargetOnAggregateError {
+        self.on_aggregate_error
+    }
+
+    /// Returns priority of the target's response, higher value is preferred
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    /// Returns target's own regex of failed statuses, if it's defined
+    pub fn failed_status_regex(&self) -> Option<&StatusRegex> {
+        self.failed_status_regex.as_ref()
+    }
+
+    /// Returns ids of the targets which should respond successfully before this one is queried
+    pub fn depends_on(&self) -> &[String] {
+        self.depends_on.as_deref().unwrap_or_default()
+    }
+
+    /// Returns pipeline stage of the target among the listener's targets, see `depends_on`
+    pub fn pipeline_stage(&self, targets: &[TargetConfig]) -> usize {
+        pipeline_stage(targets, self, &mut vec![]).expect("`depends_on` is validated to be acyclic")
+    }
+
+    /// Orders targets by their pipeline stages among the listener's targets.
+    /// Sort is stable, so order of targets in config is kept within the same stage,
+    /// and targets aren't touched at all if none of them has dependencies.
+    pub fn sort_by_pipeline_stage(targets: &mut [&TargetConfig], all: &[TargetConfig]) {
+        if targets.iter().any(|t| !t.depends_on().is_empty()) {
+            targets.sort_by_key(|t| t.pipeline_stage(all));
+        }
+    }
+
+    /// Returns response body variables to extract for the next pipeline steps, if they're defined
+    pub fn extract(&self) -> Option<&ExtractConfig> {
+        self.extract.as_ref()
+    }
+
+    /// Returns transformations of the target's response, if they're defined
+    pub fn response(&self) -> Option<&TargetResponseConfig> {
+        self.response.as_ref()
+    }
+
     pub fn aws_sigv4(&self) -> Option<&AwsSigV4Config> {
         self.aws_sigv4.as_ref()
     }
 
-    /// Signs the request with AWS SigV4 if this target has `aws_sigv4` configured.
-    /// No-op if it doesn't.
+    pub fn hmac(&self) -> Option<&HmacConfig> {
+        self.hmac.as_ref()
+    }
+
+    pub fn oauth2(&self) -> Option<&OAuth2Config> {
+        self.oauth2.as_ref()
+    }
+
+    /// Returns encoding to compress request body with, if it's defined.
+    pub fn compress_request(&self) -> Option<ContentEncoding> {
+        self.compress_request
+    }
+
+    /// Authorizes the request with OAuth2 bearer token and signs it with HMAC and/or AWS SigV4,
+    /// according to `oauth2`, `hmac` and `aws_sigv4` configs of this target.
+    /// HMAC signature goes before SigV4, so it's covered by SigV4 signature if both are set.
+    /// No-op if nothing is configured.
     pub(crate) async fn sign_request(
         &self,
         request: &mut Request<Full<Bytes>>,
         body: &Bytes,
-    ) -> Result<(), crate::aws_auth::AwsAuthError> {
+        ctx: &Context<'_>,
+        client: &HttpsClient,
+        tls_config: &TlsConfig,
+    ) -> Result<(), TargetAuthError> {
+        if let Some(oauth2_cfg) = self.oauth2() {
+            crate::oauth2_auth::authorize_request(
+                oauth2_cfg,
+                request,
+                client,
+                self.timeout,
+                tls_config,
+            )
+            .await?;
+        }
+        if let Some(hmac_cfg) = self.hmac() {
+            crate::hmac_auth::sign_request(hmac_cfg, request, body, ctx)?;
+        }
         if let Some(aws_sigv4_cfg) = self.aws_sigv4() {
             crate::aws_auth::sign_request(aws_sigv4_cfg, &self.id(), request, body).await?;
         }
         Ok(())
     }
 
+    /// Returns configured tls config, or the default one
+    pub fn tls_config(&'static self, default_tls_config: &'static TlsConfig) -> &'static TlsConfig {
+        self.tls.as_ref().unwrap_or(default_tls_config)
+    }
+
     /// Returns http client with configured (or default) tls config and timeout
     pub fn https_client(&'static self, default_tls_config: &'static TlsConfig) -> HttpsClient {
-        Self::get_https_client(
-            self.timeout(),
-            self.tls.as_ref().unwrap_or(default_tls_config),
-        )
+        Self::get_https_client(self.timeout(), self.tls_config(default_tls_config))
     }
 
     /// Check if client with specified timeout and tls config is present in the cache
@@ -261,6 +379,16 @@ impl TargetConfig {
     }
 }
 
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum TargetAuthError {
+    #[error(transparent)]
+    AwsSigV4(#[from] crate::aws_auth::AwsAuthError),
+    #[error(transparent)]
+    OAuth2(#[from] crate::oauth2_auth::OAuth2Error),
+    #[error(transparent)]
+    Hmac(#[from] crate::hmac_auth::HmacAuthError),
+}
+
 #[derive(Debug)]
 struct NoCertificateVerification {}
 
@@ -322,6 +450,66 @@ pub enum TargetOnErrorAction {
     Drop,
 }
 
+/// Action in case of failed or non-JSON response of `aggregate` strategy
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub enum TargetOnAggregateError {
+    #[default]
+    Skip,
+    Include,
+    Fail,
+}
+
+/// Action in case of condition evaluation error: treat condition as `false`, `true` or reject the request
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum TargetOnJqError {
+    #[default]
+    False,
+    True,
+    Reject,
+}
+
+impl<'de> Deserialize<'de> for TargetOnJqError {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct TargetOnJqErrorVisitor;
+        impl Visitor<'_> for TargetOnJqErrorVisitor {
+            type Value = TargetOnJqError;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("`false`, `true` or `reject`")
+            }
+
+            fn visit_bool<E>(self, v: bool) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                Ok(if v {
+                    TargetOnJqError::True
+                } else {
+                    TargetOnJqError::False
+                })
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                match v {
+                    "false" => Ok(TargetOnJqError::False),
+                    "true" => Ok(TargetOnJqError::True),
+                    "reject" => Ok(TargetOnJqError::Reject),
+                    _ => Err(E::invalid_value(de::Unexpected::Str(v), &self)),
+                }
+            }
+        }
+
+        deserializer.deserialize_any(TargetOnJqErrorVisitor)
+    }
+}
+
 #[derive(Debug)]
 pub enum TargetConditionConfig {
     Default,
@@ -372,15 +560,12 @@ impl<'de> Deserialize<'de> for TargetConditionConfig {
     }
 }
 
-pub struct ConditionFilter {
-    filter: Filter<Native<Val>>,
-    filter_str: String,
-}
+pub struct ConditionFilter(JqFilter);
 
 impl fmt::Debug for ConditionFilter {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("ConditionFilter")
-            .field("filter", &self.filter_str)
+            .field("filter", &self.0.as_str())
             .finish()
     }
 }
@@ -391,56 +576,37 @@ impl From<&str> for ConditionFilter {
     }
 }
 impl ConditionFilter {
-    fn run(&self, input: JsonValue) -> bool {
-        debug!("input=`{:#?}`", input);
-        let inputs = RcIter::new(core::iter::empty());
-        let out = self.filter.run((Ctx::new([], &inputs), Val::from(input)));
+    fn run(&self, input: Val) -> Result<bool, JqError> {
+        let out = self.0.run(input)?;
 
-        let out: Vec<String> = out
-            .map(|v| format!("{}", v.unwrap_or(Val::Bool(false))))
-            .collect();
-
-        let result = out.len() == 1 && out[0] == "true";
+        let result = out.len() == 1 && out[0] == Val::Bool(true);
         debug!("result=`{result}`");
 
-        result
+        Ok(result)
     }
 
     fn from_str(value: &str) -> Result<Self, ConfigError> {
-        debug!("filter=`{value}`");
-
-        let program = load::File {
-            code: value,
-            path: (),
-        };
-        let arena = load::Arena::default();
-        let loader = load::Loader::new(jaq_std::defs().chain(jaq_json::defs()));
-
-        // parse the filter
-        let modules = loader
-            .load(&arena, program)
+        JqFilter::from_str(value)
+            .map(ConditionFilter)
             .map_err(|_err| ConfigError::ValidateConfig {
                 cause: format!("invalid conditional expression: `{value}`"),
-            })?;
-
-        // compile the filter
-        let filter = Compiler::default()
-            .with_funs(jaq_std::funs().chain(jaq_json::funs()))
-            .compile(modules)
-            .map_err(|_err| ConfigError::ValidateConfig {
-                cause: format!("invalid conditional expression: `{value}`"),
-            })?;
-        Ok(ConditionFilter {
-            filter,
-            filter_str: value.to_string(),
-        })
+            })
     }
 }
 
 impl ConfigValidator for TargetConfig {
     fn validate(&self) -> Result<(), ConfigError> {
-        // Validate URIs
-        self.uri()?;
+        // Validate URIs, URL without variables should be absolute since it can't be fixed at request time
+        let uri = self.uri()?;
+        if !self.url.contains('$') && uri.host().is_none() {
+            return Err(ConfigError::ValidateConfig {
+                cause: format!(
+                    "url `{}` of target `{}` should be absolute, with scheme and host",
+                    self.url,
+                    self.id()
+                ),
+            });
+        }
 
         // Validate target's error response override
         match self.on_error() {
@@ -454,8 +620,8 @@ impl ConfigValidator for TargetConfig {
                     });
                 }
             }
-            TargetOnErrorAction::Status => {
-                if self.error_status().is_none() {
+            TargetOnErrorAction::Status => match self.error_status() {
+                None => {
                     return Err(ConfigError::ValidateConfig {
                         cause: format!(
                             "`error_status` should be set if `on_error` is `status`, target `{}`",
@@ -463,13 +629,52 @@ impl ConfigValidator for TargetConfig {
                         ),
                     });
                 }
-            }
+                Some(status) if StatusCode::from_u16(status).is_err() => {
+                    return Err(ConfigError::ValidateConfig {
+                        cause: format!(
+                            "invalid `error_status` status `{status}` of target `{}`",
+                            self.id()
+                        ),
+                    });
+                }
+                Some(_) => {}
+            },
+        }
+
+        validate_content_type(
+            self.content_type(),
+            self.body.is_some(),
+            &format!("target `{}`", self.id()),
+        )?;
+
+        if let Some(extract) = self.extract() {
+            extract.validate()?;
+        }
+
+        if let Some(response) = self.response() {
+            response.validate()?;
         }
 
         if let Some(aws_sigv4) = self.aws_sigv4() {
             aws_sigv4.validate()?;
         }
 
+        if let Some(hmac) = self.hmac() {
+            hmac.validate()?;
+        }
+
+        if let Some(oauth2) = self.oauth2() {
+            oauth2.validate()?;
+            if self.aws_sigv4().is_some() {
+                return Err(ConfigError::ValidateConfig {
+                    cause: format!(
+                        "`oauth2` and `aws_sigv4` can't be used together since both set `Authorization` header, target `{}`",
+                        self.id()
+                    ),
+                });
+            }
+        }
+
         Ok(())
     }
 }
@@ -500,49 +705,134 @@ impl ConfigValidator for [TargetConfig] {
             });
         }
 
+        // Make sure context variables of different targets don't collide, e.g. `a-b` and `a_b`
+        for (pos, target) in self.iter().enumerate() {
+            if let Some(other) = self[..pos]
+                .iter()
+                .find(|t| targets_prefix(&t.id()) == targets_prefix(&target.id()))
+            {
+                return Err(ConfigError::ValidateConfig {
+                    cause: format!(
+                        "target IDs `{}` and `{}` have the same context variables prefix `{}`",
+                        other.id(),
+                        target.id(),
+                        targets_prefix(&target.id())
+                    ),
+                });
+            }
+        }
+
+        // Make sure that pipeline steps are known targets without cyclic dependencies
+        for target in self {
+            if let Some(id) = target
+                .depends_on()
+                .iter()
+                .find(|id| !self.iter().any(|t| t.id() == **id))
+            {
+                return Err(ConfigError::ValidateConfig {
+                    cause: format!(
+                        "`depends_on` points to unknown target_id `{id}`, target `{}`",
+                        target.id()
+                    ),
+                });
+            }
+            if pipeline_stage(self, target, &mut vec![]).is_none() {
+                return Err(ConfigError::ValidateConfig {
+                    cause: format!("cyclic `depends_on` of target `{}`", target.id()),
+                });
+            }
+        }
+
         Ok(())
     }
 }
 
+/// Returns pipeline stage of the target: `0` if it doesn't depend on other targets,
+/// otherwise the next one after the latest stage of its dependencies; `None` in case of cyclic dependencies
+fn pipeline_stage(
+    targets: &[TargetConfig],
+    target: &TargetConfig,
+    path: &mut Vec<String>,
+) -> Option<usize> {
+    let id = target.id();
+    if path.contains(&id) {
+        return None;
+    }
+    path.push(id);
+    let mut stage = 0;
+    for dependency in targets
+        .iter()
+        .filter(|t| target.depends_on().contains(&t.id()))
+    {
+        stage = stage.max(pipeline_stage(targets, dependency, path)? + 1);
+    }
+    path.pop();
+
+    Some(stage)
+}
+
 pub trait TargetBehavior {
-    fn check_condition(&self, ctx: &Context, req: &Parts, body: &Bytes) -> bool;
+    fn check_condition(&self, input: &ConditionInput) -> Result<bool, JqError>;
+    fn step_vars(&self, ctx: &Context, resp: &Response<Full<Bytes>>) -> ContextMap;
+    fn transform_response(
+        &self,
+        resp: Response<Full<Bytes>>,
+        ctx: &Context,
+    ) -> Response<Full<Bytes>>;
 }
 
 impl TargetBehavior for TargetConfig {
-    fn check_condition(&self, ctx: &Context, req: &Parts, body: &Bytes) -> bool {
-        // Input content
-        // .body
-        // .env{}
-        // .request.headers{}
-        // .request.uri.full
-        // .request.uri.host
-        // .request.uri.path
-        // .request.uri.query
-        let body: Value = serde_json::from_slice(body).unwrap_or(json!({}));
-        let headers: HashMap<String, String> = req
-            .headers
-            .iter()
-            .map(|(k, v)| (k.to_string(), v.to_str().unwrap().to_string()))
-            .collect();
-        let env = ctx.iter().collect::<HashMap<&String, &String>>();
-        let input = json!({
-            "body": body,
-            "env": env,
-            "request": {
-                "headers": headers,
-                "uri": {
-                    "full": req.uri.to_string(),
-                    "host": req.uri.host(),
-                    "path": req.uri.path(),
-                    "query": req.uri.query()
-                }
+    /// Evaluates target's condition, evaluation error is handled according to `on_condition_error`:
+    /// it's returned in case of `reject` action only
+    fn check_condition(&self, input: &ConditionInput) -> Result<bool, JqError> {
+        let Some(TargetConditionConfig::Filter(filter)) = self.condition() else {
+            return Ok(false);
+        };
+
+        filter.run(input.value().clone()).or_else(|e| {
+            warn!("target `{}`: {e}", self.id());
+            match self.on_condition_error() {
+                TargetOnJqError::False => Ok(false),
+                TargetOnJqError::True => Ok(true),
+                TargetOnJqError::Reject => Err(e),
             }
-        });
+        })
+    }
 
-        if let TargetConditionConfig::Filter(filter) = self.condition().as_ref().unwrap() {
-            filter.run(input)
-        } else {
-            false
+    /// Returns variables of the target's response for the next pipeline steps:
+    /// response variables and body with `CTX_STEP_<ID>_` prefix, and variables extracted from the body
+    fn step_vars(&self, ctx: &Context, resp: &Response<Full<Bytes>>) -> ContextMap {
+        // CTX_STEP_<UPPERCASE_TARGET_ID>_BODY
+        // CTX_STEP_<UPPERCASE_TARGET_ID>_BODY_<UPPERCASE_EXTRACT_NAME>
+        let id = self.id();
+        let prefix = step_prefix(&id);
+        let mut vars = ctx.with_response(resp).step_vars(&id);
+        let body = response_body(resp);
+        vars.insert(
+            format!("{prefix}BODY"),
+            String::from_utf8_lossy(&body).into_owned(),
+        );
+        if let Some(extract) = self.extract() {
+            let body = parse_body(resp.headers(), &body);
+            let input = Val::from(body.text.map_or(body.body, JsonValue::String));
+            vars.extend(extract.extract_vars(&input, &format!("{prefix}BODY_")));
+        }
+
+        vars
+    }
+
+    /// Applies target's response transformations if they're defined, context is the target's one
+    fn transform_response(
+        &self,
+        resp: Response<Full<Bytes>>,
+        ctx: &Context,
+    ) -> Response<Full<Bytes>> {
+        match self.response() {
+            Some(cfg) => {
+                let ctx = ctx.with_response(&resp);
+                cfg.transform(resp, &ctx, &self.id())
+            }
+            None => resp,
         }
     }
 }
@@ -555,14 +845,155 @@ pub mod test_target {
         TargetConfig {
             id: Some("TEST-TARGET-ID".into()),
             url: "https://www.google.com/test-path?query=some-query".into(),
+            uri: None,
             headers: None,
             body: None,
+            content_type: None,
             timeout: Duration::from_secs(DEFAULT_TARGET_TIMEOUT_SEC),
             on_error: TargetOnErrorAction::Propagate,
             error_status: None,
             condition: Some(TargetConditionConfig::Default),
+            on_condition_error: Default::default(),
+            on_aggregate_error: Default::default(),
+            priority: 0,
+            failed_status_regex: None,
+            depends_on: None,
+            extract: None,
+            response: None,
             tls: Default::default(),
             aws_sigv4: None,
+            hmac: None,
+            oauth2: None,
+            compress_request: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::test_context::get_test_ctx;
+    use hyper::header::CONTENT_TYPE;
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    fn check(target_yaml: &str, body: &'static str) -> Result<bool, JqError> {
+        let target: TargetConfig = serde_yaml_ng::from_str(target_yaml).unwrap();
+        let (req, _) = Request::builder().uri("/").body(()).unwrap().into_parts();
+        let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 12345);
+        let body = Bytes::from_static(body.as_bytes());
+        let input = ConditionInput::new(get_test_ctx(), &addr, &req, &body);
+
+        target.check_condition(&input)
+    }
+
+    #[test]
+    fn on_condition_error_values() {
+        for (yaml, expected) in [
+            ("false", TargetOnJqError::False),
+            ("true", TargetOnJqError::True),
+            ("reject", TargetOnJqError::Reject),
+            ("\"true\"", TargetOnJqError::True),
+        ] {
+            let action: TargetOnJqError = serde_yaml_ng::from_str(yaml).unwrap();
+            assert_eq!(action, expected, "{yaml}");
         }
+        assert!(serde_yaml_ng::from_str::<TargetOnJqError>("drop").is_err());
+    }
+
+    #[test]
+    fn condition_errors() {
+        let condition = "url: http://localhost/\ncondition: .body.value | tonumber > 10\n";
+        let on_error = |action: &str| format!("{condition}on_condition_error: {action}");
+
+        assert!(check(condition, r#"{"value": "11"}"#).unwrap());
+        assert!(!check(condition, r#"{"value": "9"}"#).unwrap());
+
+        assert!(!check(condition, r#"{"value": "NaN?"}"#).unwrap());
+        assert!(!check(&on_error("false"), r#"{"value": "NaN?"}"#).unwrap());
+        assert!(check(&on_error("true"), r#"{"value": "NaN?"}"#).unwrap());
+        assert!(check(&on_error("reject"), r#"{"value": "NaN?"}"#).is_err());
+        assert!(check(&on_error("reject"), r#"{"value": "11"}"#).unwrap());
+    }
+
+    fn parse_targets(yaml: &str) -> Vec<TargetConfig> {
+        serde_yaml_ng::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn pipeline_stages() {
+        let targets = parse_targets(
+            r#"
+- {id: A, url: "http://localhost/"}
+- {id: B, url: "http://localhost/", depends_on: [A]}
+- {id: C, url: "http://localhost/", depends_on: [A, B]}
+- {id: D, url: "http://localhost/"}
+"#,
+        );
+        assert!(targets.validate().is_ok());
+        let stages: Vec<usize> = targets.iter().map(|t| t.pipeline_stage(&targets)).collect();
+        assert_eq!(stages, vec![0, 1, 2, 0]);
+
+        let mut ordered: Vec<&TargetConfig> = targets.iter().rev().collect();
+        TargetConfig::sort_by_pipeline_stage(&mut ordered, &targets);
+        let ids: Vec<String> = ordered.iter().map(|t| t.id()).collect();
+        assert_eq!(ids, ["D", "A", "B", "C"]);
+
+        let independent = parse_targets(
+            r#"
+- {id: B, url: "http://localhost/"}
+- {id: A, url: "http://localhost/"}
+"#,
+        );
+        let mut ordered: Vec<&TargetConfig> = independent.iter().collect();
+        TargetConfig::sort_by_pipeline_stage(&mut ordered, &independent);
+        let ids: Vec<String> = ordered.iter().map(|t| t.id()).collect();
+        assert_eq!(ids, ["B", "A"]);
+
+        for yaml in [
+            r#"[{id: A, url: "http://localhost/", depends_on: [B]}]"#,
+            r#"[{id: A, url: "http://localhost/", depends_on: [A]}]"#,
+            r#"[{id: A, url: "http://localhost/", depends_on: [B]}, {id: B, url: "http://localhost/", depends_on: [A]}]"#,
+        ] {
+            assert!(
+                parse_targets(yaml).validate().is_err(),
+                "`{yaml}` should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn step_vars() {
+        let target: TargetConfig = serde_yaml_ng::from_str(
+            "id: auth
+url: http://localhost/
+extract:
+  token: .token
+  user: .user.name",
+        )
+        .unwrap();
+        let resp = Response::builder()
+            .status(201)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Full::from(r#"{"token": "abc", "user": {"name": "John"}}"#))
+            .unwrap();
+
+        let vars = target.step_vars(get_test_ctx(), &resp);
+        assert_eq!(vars.get("CTX_STEP_AUTH_STATUS").unwrap(), "201 Created");
+        assert_eq!(
+            vars.get("CTX_STEP_AUTH_BODY").unwrap(),
+            r#"{"token": "abc", "user": {"name": "John"}}"#
+        );
+        assert_eq!(vars.get("CTX_STEP_AUTH_BODY_TOKEN").unwrap(), "abc");
+        assert_eq!(vars.get("CTX_STEP_AUTH_BODY_USER").unwrap(), "John");
+        // Values with control characters aren't extracted, the whole body is kept as is
+        let body = "{\"token\": \"abc\\r\\nX-Injected: 1\", \"user\": {\"name\": \"John\"}}";
+        let resp = Response::builder()
+            .header(CONTENT_TYPE, "application/json")
+            .body(Full::from(body))
+            .unwrap();
+        let vars = target.step_vars(get_test_ctx(), &resp);
+        assert!(!vars.contains_key("CTX_STEP_AUTH_BODY_TOKEN"));
+        assert_eq!(vars.get("CTX_STEP_AUTH_BODY_USER").unwrap(), "John");
+        assert_eq!(vars.get("CTX_STEP_AUTH_BODY").unwrap(), body);
     }
 }
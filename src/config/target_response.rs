@@ -0,0 +1,244 @@
+use super::{
+    headers::{update_body_headers, validate_content_type, HeaderTransform, HeadersTransformator},
+    jq::JqFilter,
+    response::{response_body, ResponseStatus},
+    ConfigValidator,
+};
+use crate::{body_parser::parse_body, config::ConfigError, context::Context};
+use http_body_util::Full;
+use hyper::{
+    body::Bytes,
+    header::{HeaderValue, CONTENT_TYPE},
+    Response, StatusCode,
+};
+use jaq_json::Val;
+use regex::Regex;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value as JsonValue;
+use shellexpand::env_with_context_no_errors;
+use tracing::warn;
+
+/// Regex which should match the whole status code, it's compiled once at load time
+pub struct StatusPattern {
+    pattern: String,
+    regex: Regex,
+}
+
+impl StatusPattern {
+    fn new(pattern: &str) -> Result<Self, regex::Error> {
+        // Pattern is checked alone to report errors without anchors
+        Regex::new(pattern)?;
+        Ok(Self {
+            pattern: pattern.into(),
+            regex: Regex::new(&format!("^(?:{pattern})$"))?,
+        })
+    }
+
+    fn is_match(&self, status: StatusCode) -> bool {
+        self.regex.is_match(status.as_str())
+    }
+}
+
+impl std::fmt::Debug for StatusPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.pattern.fmt(f)
+    }
+}
+
+impl Serialize for StatusPattern {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.pattern)
+    }
+}
+
+impl<'de> Deserialize<'de> for StatusPattern {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let pattern = String::deserialize(deserializer)?;
+        StatusPattern::new(&pattern).map_err(|e| {
+            de::Error::custom(format!("invalid `status_map.from` regex `{pattern}`: {e}"))
+        })
+    }
+}
+
+/// Replaces status of the response if it matches `from` regex
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct StatusMapping {
+    from: StatusPattern,
+    to: ResponseStatus,
+}
+
+/// Transformations of the target's response, which are applied before any strategy-related processing
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct TargetResponseConfig {
+    status_map: Option<Vec<StatusMapping>>,
+    headers: Option<Vec<HeaderTransform>>,
+    body: Option<String>,
+    body_filter: Option<JqFilter>,
+    content_type: Option<String>,
+}
+
+impl TargetResponseConfig {
+    /// Returns the first mapped status of the matching rule, if any
+    fn map_status(&self, status: StatusCode) -> Option<StatusCode> {
+        self.status_map
+            .iter()
+            .flatten()
+            .find(|m| m.from.is_match(status))
+            .and_then(|m| StatusCode::from_u16(m.to).ok())
+    }
+
+    /// Applies status mapping, headers transformations and body template or filter to the response.
+    /// Context should include the original response's variables.
+    /// If body filter fails, original body is preserved.
+    pub(crate) fn transform(
+        &self,
+        resp: Response<Full<Bytes>>,
+        ctx: &Context,
+        target_id: &str,
+    ) -> Response<Full<Bytes>> {
+        let body = if let Some(body) = &self.body {
+            let body: String = env_with_context_no_errors(body, |v| ctx.get(&v.into())).into();
+            Some((Bytes::from(body), None))
+        } else if let Some(filter) = &self.body_filter {
+            let parsed = parse_body(resp.headers(), &response_body(&resp));
+            let input = Val::from(parsed.text.map_or(parsed.body, JsonValue::String));
+            match filter.first(input) {
+                Ok(value) => Some((
+                    Bytes::from(value.to_string()),
+                    Some(HeaderValue::from_static("application/json")),
+                )),
+                Err(e) => {
+                    warn!("target `{target_id}`: response body isn't transformed: {e}");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let (mut parts, mut resp_body) = resp.into_parts();
+        if let Some(status) = self.map_status(parts.status) {
+            parts.status = status;
+        }
+        if let Some(transforms) = &self.headers {
+            transforms.transform(&mut parts.headers, ctx);
+        }
+        if let Some((body, content_type)) = body {
+            if let Some(content_type) = content_type {
+                parts.headers.insert(CONTENT_TYPE, content_type);
+            }
+            update_body_headers(&mut parts.headers, body.len(), self.content_type.as_deref());
+            resp_body = Full::from(body);
+        }
+
+        Response::from_parts(parts, resp_body)
+    }
+}
+
+impl ConfigValidator for TargetResponseConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        for mapping in self.status_map.iter().flatten() {
+            if StatusCode::from_u16(mapping.to).is_err() {
+                return Err(ConfigError::ValidateConfig {
+                    cause: format!("invalid `status_map.to` status `{}`", mapping.to),
+                });
+            }
+        }
+        if self.body.is_some() && self.body_filter.is_some() {
+            return Err(ConfigError::ValidateConfig {
+                cause: "`body` and `body_filter` of target's response can't be used together"
+                    .into(),
+            });
+        }
+        validate_content_type(
+            self.content_type.as_deref(),
+            self.body.is_some() || self.body_filter.is_some(),
+            "target's response",
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::test_context::get_test_ctx;
+    use hyper::header::CONTENT_LENGTH;
+
+    fn transform(yaml: &str, status: u16, body: &'static str) -> Response<Full<Bytes>> {
+        let cfg: TargetResponseConfig = serde_yaml_ng::from_str(yaml).unwrap();
+        assert!(cfg.validate().is_ok());
+        let resp = Response::builder()
+            .status(status)
+            .header(CONTENT_LENGTH, body.len())
+            .header("x-original", "yes")
+            .body(Full::from(body))
+            .unwrap();
+        let ctx = get_test_ctx().with_response(&resp);
+        cfg.transform(resp, &ctx, "TEST")
+    }
+
+    #[test]
+    fn status_mapping() {
+        let yaml = "status_map:\n  - from: '404'\n    to: 200\n  - from: '5\\d{2}'\n    to: 503";
+        assert_eq!(transform(yaml, 404, "").status(), 200);
+        assert_eq!(transform(yaml, 500, "").status(), 503);
+        assert_eq!(transform(yaml, 504, "").status(), 503);
+        assert_eq!(transform(yaml, 401, "").status(), 401);
+        // Whole status should match
+        assert_eq!(
+            transform("status_map: [{from: '4', to: 200}]", 404, "").status(),
+            404
+        );
+    }
+
+    #[test]
+    fn headers_and_body() {
+        let resp = transform(
+            "headers:\n  - drop: x-original\n  - add: x-status\n    value: ${CTX_RESPONSE_STATUS}\nbody: 'status ${CTX_RESPONSE_STATUS}'",
+            404,
+            "not found",
+        );
+        assert!(!resp.headers().contains_key("x-original"));
+        assert_eq!(resp.headers()["x-status"], "404 Not Found");
+        assert_eq!(resp.headers()[CONTENT_LENGTH], "20");
+        assert_eq!(response_body(&resp), "status 404 Not Found");
+
+        let resp = transform(
+            "body_filter: '{total: (.items | length)}'",
+            200,
+            r#"{"items": [1, 2, 3]}"#,
+        );
+        assert_eq!(resp.headers()[CONTENT_TYPE], "application/json");
+        assert_eq!(response_body(&resp), r#"{"total":3}"#);
+
+        // Original body is preserved if filter fails
+        let resp = transform("body_filter: '.items | length'", 200, "plain text");
+        assert_eq!(response_body(&resp), "plain text");
+        assert!(resp.headers().contains_key(CONTENT_LENGTH));
+    }
+
+    #[test]
+    fn wrong_config() {
+        let result: Result<TargetResponseConfig, _> =
+            serde_yaml_ng::from_str("status_map: [{from: '5\\d{2', to: 503}]");
+        assert!(result.is_err());
+
+        for yaml in [
+            "status_map: [{from: '500', to: 1000}]",
+            "body: text\nbody_filter: .",
+        ] {
+            let cfg: TargetResponseConfig = serde_yaml_ng::from_str(yaml).unwrap();
+            assert!(cfg.validate().is_err(), "`{yaml}` should be rejected");
+        }
+    }
+}
@@ -0,0 +1,457 @@
+use crate::context::Context;
+use hyper::{
+    http::{self, uri::PathAndQuery},
+    Uri,
+};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use regex::Regex;
+use serde::Deserialize;
+use shellexpand::env_with_context_no_errors;
+use tracing::debug;
+
+pub type UriTransformsList = Vec<UriTransform>;
+
+/// Characters which aren't allowed in the path, `%` is kept as is to preserve already encoded characters
+const PATH_ENCODE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub enum PreserveUriPart {
+    Path,
+    Query,
+    PathAndQuery,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(try_from = "UriTransformFields")]
+pub enum UriTransform {
+    Preserve(PreserveUriPart),
+    StripPrefix(String),
+    AddPrefix(String),
+    RewritePath { pattern: Regex, value: String },
+    AddQuery { name: String, value: String },
+    SetQuery { name: String, value: String },
+    DropQuery(String),
+    RenameQuery { name: String, to: String },
+}
+
+/// Raw representation of the single transformation as it's defined in the config
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct UriTransformFields {
+    preserve: Option<PreserveUriPart>,
+    strip_prefix: Option<String>,
+    add_prefix: Option<String>,
+    rewrite_path: Option<String>,
+    add_query: Option<String>,
+    set_query: Option<String>,
+    drop_query: Option<String>,
+    rename_query: Option<String>,
+    value: Option<String>,
+    to: Option<String>,
+}
+
+impl TryFrom<UriTransformFields> for UriTransform {
+    type Error = String;
+
+    fn try_from(fields: UriTransformFields) -> Result<Self, Self::Error> {
+        let actions = [
+            ("preserve", fields.preserve.is_some()),
+            ("strip_prefix", fields.strip_prefix.is_some()),
+            ("add_prefix", fields.add_prefix.is_some()),
+            ("rewrite_path", fields.rewrite_path.is_some()),
+            ("add_query", fields.add_query.is_some()),
+            ("set_query", fields.set_query.is_some()),
+            ("drop_query", fields.drop_query.is_some()),
+            ("rename_query", fields.rename_query.is_some()),
+        ];
+        let defined: Vec<&str> = actions
+            .iter()
+            .filter_map(|(name, present)| present.then_some(*name))
+            .collect();
+        let action = match defined.as_slice() {
+            [action] => *action,
+            [] => return Err("action should be one of preserve/strip_prefix/add_prefix/rewrite_path/add_query/set_query/drop_query/rename_query".into()),
+            _ => return Err(format!("only one action is allowed in a single uri transformation, but found: {}", defined.join(", "))),
+        };
+
+        // Verify which fields are required and which are not allowed
+        let (value_required, to_required) = match action {
+            "rewrite_path" | "add_query" | "set_query" => (true, false),
+            "rename_query" => (false, true),
+            _ => (false, false),
+        };
+        for (field, present, required) in [
+            ("value", fields.value.is_some(), value_required),
+            ("to", fields.to.is_some(), to_required),
+        ] {
+            if required && !present {
+                return Err(format!("missing field `{field}` in action {action}"));
+            }
+            if !required && present {
+                return Err(format!("unknown field `{field}` in action {action}"));
+            }
+        }
+
+        let not_empty = |name: Option<String>| {
+            let name = name.unwrap_or_default();
+            if name.is_empty() {
+                Err(format!(
+                    "query parameter name in action {action} must not be empty"
+                ))
+            } else {
+                Ok(name)
+            }
+        };
+        let prefix = |prefix: Option<String>| {
+            let prefix = prefix.unwrap_or_default();
+            if prefix.starts_with('/') {
+                Ok(prefix)
+            } else {
+                Err(format!("path prefix `{prefix}` should start with `/`"))
+            }
+        };
+
+        let transform = match action {
+            "preserve" => {
+                UriTransform::Preserve(fields.preserve.unwrap_or(PreserveUriPart::PathAndQuery))
+            }
+            "strip_prefix" => UriTransform::StripPrefix(prefix(fields.strip_prefix)?),
+            "add_prefix" => UriTransform::AddPrefix(prefix(fields.add_prefix)?),
+            "rewrite_path" => {
+                let pattern = fields.rewrite_path.unwrap_or_default();
+                UriTransform::RewritePath {
+                    pattern: Regex::new(&pattern)
+                        .map_err(|e| format!("invalid regex `{pattern}`: {e}"))?,
+                    value: fields.value.unwrap_or_default(),
+                }
+            }
+            "add_query" => UriTransform::AddQuery {
+                name: not_empty(fields.add_query)?,
+                value: fields.value.unwrap_or_default(),
+            },
+            "set_query" => UriTransform::SetQuery {
+                name: not_empty(fields.set_query)?,
+                value: fields.value.unwrap_or_default(),
+            },
+            "drop_query" => UriTransform::DropQuery(not_empty(fields.drop_query)?),
+            "rename_query" => UriTransform::RenameQuery {
+                name: not_empty(fields.rename_query)?,
+                to: not_empty(fields.to)?,
+            },
+            _ => unreachable!("unknown uri transform action `{action}`, looks like a BUG"),
+        };
+
+        Ok(transform)
+    }
+}
+
+/// Query parameter which keeps its raw form, so parameters which aren't touched by transformations
+/// are passed to the target as is, without re-encoding
+#[derive(Debug)]
+struct QueryParam {
+    name: String,
+    raw: String,
+}
+
+impl QueryParam {
+    fn new(name: &str, value: &str) -> Self {
+        Self {
+            name: name.into(),
+            raw: form_urlencoded::Serializer::new(String::new())
+                .append_pair(name, value)
+                .finish(),
+        }
+    }
+
+    /// Replaces name of the parameter, value is kept as is
+    fn rename(&mut self, to: &str) {
+        let name: String = form_urlencoded::byte_serialize(to.as_bytes()).collect();
+        self.raw = match self.raw.split_once('=') {
+            Some((_, value)) => format!("{name}={value}"),
+            None => name,
+        };
+        self.name = to.into();
+    }
+}
+
+fn parse_query(query: Option<&str>) -> Vec<QueryParam> {
+    query
+        .unwrap_or_default()
+        .split('&')
+        .filter(|raw| !raw.is_empty())
+        .map(|raw| QueryParam {
+            name: form_urlencoded::parse(raw.as_bytes())
+                .next()
+                .map(|(name, _)| name.into_owned())
+                .unwrap_or_default(),
+            raw: raw.into(),
+        })
+        .collect()
+}
+
+fn serialize_query(params: &[QueryParam]) -> Option<String> {
+    if params.is_empty() {
+        None
+    } else {
+        Some(
+            params
+                .iter()
+                .map(|p| p.raw.as_str())
+                .collect::<Vec<_>>()
+                .join("&"),
+        )
+    }
+}
+
+/// Removes prefix from the path if the path starts with it on the segments boundary
+fn strip_path_prefix(path: &str, prefix: &str) -> Option<String> {
+    let prefix = prefix.trim_end_matches('/');
+    let rest = path.strip_prefix(prefix)?;
+    if rest.is_empty() {
+        Some("/".into())
+    } else if rest.starts_with('/') {
+        Some(rest.into())
+    } else {
+        None
+    }
+}
+
+fn join_paths(base: &str, path: &str) -> String {
+    format!(
+        "{}/{}",
+        base.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    )
+}
+
+pub trait UriTransformator {
+    fn transform(&self, uri: &Uri, original: &Uri, ctx: &Context) -> Result<Uri, http::Error>;
+}
+
+impl UriTransformator for UriTransformsList {
+    /// Applies all transformations to the target's URI,
+    /// original request's URI is used as a source of path and query to preserve.
+    fn transform(&self, uri: &Uri, original: &Uri, ctx: &Context) -> Result<Uri, http::Error> {
+        let expand = |value: &str| -> String {
+            env_with_context_no_errors(value, |v| ctx.get(&v.into())).into_owned()
+        };
+        let mut path = uri.path().to_string();
+        let mut query = uri.query().map(String::from);
+
+        for transform in self {
+            match transform {
+                UriTransform::Preserve(part) => {
+                    if matches!(part, PreserveUriPart::Path | PreserveUriPart::PathAndQuery) {
+                        path = join_paths(&path, original.path());
+                    }
+                    if matches!(part, PreserveUriPart::Query | PreserveUriPart::PathAndQuery) {
+                        if let Some(original) = original.query().filter(|q| !q.is_empty()) {
+                            query = Some(match query.filter(|q| !q.is_empty()) {
+                                Some(query) => format!("{query}&{original}"),
+                                None => original.into(),
+                            });
+                        }
+                    }
+                }
+                UriTransform::StripPrefix(prefix) => {
+                    if let Some(stripped) = strip_path_prefix(&path, &expand(prefix)) {
+                        path = stripped;
+                    }
+                }
+                UriTransform::AddPrefix(prefix) => {
+                    path = join_paths(&expand(prefix), &path);
+                }
+                UriTransform::RewritePath { pattern, value } => {
+                    path = pattern
+                        .replace_all(&path, expand(value).as_str())
+                        .into_owned();
+                }
+                UriTransform::AddQuery { name, value } => {
+                    let mut params = parse_query(query.as_deref());
+                    if !params.iter().any(|p| p.name == *name) {
+                        params.push(QueryParam::new(name, &expand(value)));
+                    }
+                    query = serialize_query(&params);
+                }
+                UriTransform::SetQuery { name, value } => {
+                    let mut params = parse_query(query.as_deref());
+                    params.retain(|p| p.name != *name);
+                    params.push(QueryParam::new(name, &expand(value)));
+                    query = serialize_query(&params);
+                }
+                UriTransform::DropQuery(name) => {
+                    let mut params = parse_query(query.as_deref());
+                    params.retain(|p| p.name != *name);
+                    query = serialize_query(&params);
+                }
+                UriTransform::RenameQuery { name, to } => {
+                    let mut params = parse_query(query.as_deref());
+                    if params.iter().any(|p| p.name == *name) {
+                        params.retain(|p| p.name != *to);
+                        params
+                            .iter_mut()
+                            .filter(|p| p.name == *name)
+                            .for_each(|p| p.rename(to));
+                    }
+                    query = serialize_query(&params);
+                }
+            }
+        }
+
+        if !path.starts_with('/') {
+            path.insert(0, '/');
+        }
+        let path = utf8_percent_encode(&path, PATH_ENCODE_SET).to_string();
+        let path_and_query = match query.filter(|q| !q.is_empty()) {
+            Some(query) => format!("{path}?{query}"),
+            None => path,
+        };
+        debug!("uri transform: {uri} -> {path_and_query}");
+
+        let mut parts = uri.clone().into_parts();
+        parts.path_and_query = Some(PathAndQuery::try_from(path_and_query)?);
+        Ok(Uri::from_parts(parts)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::test_context::get_test_ctx;
+
+    fn transform(transforms: &str, uri: &str, original: &str) -> String {
+        let transforms: UriTransformsList = serde_yaml_ng::from_str(transforms).unwrap();
+        transforms
+            .transform(
+                &uri.parse().unwrap(),
+                &original.parse().unwrap(),
+                get_test_ctx(),
+            )
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn preserve_original_path_and_query() {
+        let preserve = "- preserve: path_and_query";
+        assert_eq!(
+            transform(preserve, "https://example.com/", "/Users/1?Name=A%20B"),
+            "https://example.com/Users/1?Name=A%20B"
+        );
+        assert_eq!(
+            transform(preserve, "https://example.com/api/?key=1", "/users?a=b"),
+            "https://example.com/api/users?key=1&a=b"
+        );
+        assert_eq!(
+            transform("- preserve: path", "https://example.com/api", "/users?a=b"),
+            "https://example.com/api/users"
+        );
+        assert_eq!(
+            transform("- preserve: query", "https://example.com/api", "/users?a=b"),
+            "https://example.com/api?a=b"
+        );
+    }
+
+    #[test]
+    fn path_transforms() {
+        let transforms = r#"
+            - preserve: path
+            - strip_prefix: /public
+            - add_prefix: /v2
+            - rewrite_path: "^/v2/users/(\\d+)$"
+              value: "/v2/people/$1/${TEST_ENV_HEADER_TO_ADD} name"
+        "#;
+        assert_eq!(
+            transform(transforms, "http://example.com/", "/public/users/42"),
+            "http://example.com/v2/people/42/TEST_ENV_HEADER_VALUE%20name"
+        );
+        // prefix should match whole segments only
+        assert_eq!(
+            transform(transforms, "http://example.com/", "/publicity/users/42"),
+            "http://example.com/v2/publicity/users/42"
+        );
+        assert_eq!(
+            transform("- strip_prefix: /public/", "http://example.com/public", "/"),
+            "http://example.com/"
+        );
+    }
+
+    #[test]
+    fn query_transforms() {
+        let transforms = r#"
+            - preserve: query
+            - add_query: page
+              value: "1"
+            - add_query: existing
+              value: wrong
+            - set_query: token
+              value: a&b=c d
+            - drop_query: secret
+            - rename_query: q
+              to: search
+        "#;
+        assert_eq!(
+            transform(
+                transforms,
+                "http://example.com/?existing=good&token=old",
+                "/?Q=Case&q=Hello%20World&secret=1&token=older"
+            ),
+            "http://example.com/?existing=good&Q=Case&search=Hello%20World&page=1&token=a%26b%3Dc+d"
+        );
+    }
+
+    #[test]
+    fn untouched_query_params_are_kept_raw() {
+        let transforms = r#"
+            - preserve: query
+            - drop_query: secret
+            - rename_query: q
+              to: search term
+        "#;
+        assert_eq!(
+            transform(
+                transforms,
+                "http://example.com/",
+                "/?flag&name=A%20B&list=a,b;c&&q&secret=1&q=x%2By"
+            ),
+            "http://example.com/?flag&name=A%20B&list=a,b;c&search+term&search+term=x%2By"
+        );
+        assert_eq!(
+            transform("- drop_query: a", "http://example.com/?a=1", "/"),
+            "http://example.com/"
+        );
+    }
+
+    #[test]
+    fn wrong_transforms() {
+        let wrong_yaml = [
+            "- {}",
+            "- preserve: everything",
+            "- strip_prefix: api",
+            "- add_prefix: /api\n  value: /v2",
+            "- rewrite_path: \"(unclosed\"\n  value: /",
+            "- rewrite_path: ^/api",
+            "- add_query: page",
+            "- add_query: \"\"\n  value: \"1\"",
+            "- set_query: page\n  to: \"1\"",
+            "- rename_query: q",
+            "- drop_query: q\n  add_query: page\n  value: \"1\"",
+            "- unknown: q",
+        ];
+
+        for yaml in wrong_yaml {
+            let result: Result<UriTransformsList, _> = serde_yaml_ng::from_str(yaml);
+            assert!(result.is_err(), "unexpected deserialization of `{yaml}`");
+        }
+    }
+}
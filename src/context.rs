@@ -92,7 +92,7 @@ impl<'a> Context<'a> {
             own.insert("CTX_REQUEST_HOST".into(), host.to_lowercase());
         }
         if let Some(query) = req.uri.query() {
-            own.insert("CTX_REQUEST_QUERY".into(), query.to_lowercase());
+            own.insert("CTX_REQUEST_QUERY".into(), query.into());
         }
 
         // CTX_REQUEST_HEADERS_<UPPERCASE_HEADER_NAME>
@@ -131,6 +131,27 @@ impl<'a> Context<'a> {
         self.with(own)
     }
 
+    /// Adds variables to the own context
+    pub fn with_vars(mut self, vars: ContextMap) -> Self {
+        self.own.extend(vars);
+        self
+    }
+
+    /// Returns own response variables of this context with `CTX_STEP_<TARGET_ID>_` prefix
+    /// instead of `CTX_RESPONSE_`, context should be created by `with_response`
+    pub fn step_vars(&self, target_id: &str) -> ContextMap {
+        // CTX_STEP_<UPPERCASE_TARGET_ID>_HEADERS_<UPPERCASE_HEADER_NAME>
+        // CTX_STEP_<UPPERCASE_TARGET_ID>_STATUS
+        let prefix = step_prefix(target_id);
+        self.own
+            .iter()
+            .filter_map(|(k, v)| {
+                k.strip_prefix("CTX_RESPONSE_")
+                    .map(|name| (format!("{prefix}{name}"), v.clone()))
+            })
+            .collect()
+    }
+
     pub fn iter(&self) -> ContextIterator<'_> {
         let iter = Box::new(self.own.iter());
         ContextIterator {
@@ -141,6 +162,31 @@ impl<'a> Context<'a> {
     }
 }
 
+/// Returns target ID as a part of variable names:
+/// uppercase, any character except letters and digits is replaced by `_`
+fn var_id(target_id: &str) -> String {
+    target_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Returns prefix of the pipeline step variables: `CTX_STEP_<UPPERCASE_TARGET_ID>_`
+pub fn step_prefix(target_id: &str) -> String {
+    format!("CTX_STEP_{}_", var_id(target_id))
+}
+
+/// Returns prefix of the target's result variables: `CTX_TARGETS_<UPPERCASE_TARGET_ID>_`
+pub fn targets_prefix(target_id: &str) -> String {
+    format!("CTX_TARGETS_{}_", var_id(target_id))
+}
+
 pub struct ContextIterator<'a> {
     ctx: &'a Context<'a>,
     iter: Box<dyn Iterator<Item = (&'a String, &'a String)> + 'a>,
@@ -255,7 +301,7 @@ pub mod test_context {
 
     fn get_test_request() -> Request<Full<Bytes>> {
         Request::builder()
-            .uri("https://www.google.com/test-path?query=some-query")
+            .uri("https://www.google.com/test-path?query=Some-Query")
             .header("X-Some-Header", "some header value")
             .method("POST")
             .body(Full::from(Bytes::new()))
@@ -314,6 +360,12 @@ pub mod test_context {
         let (req, _) = get_test_request().into_parts();
         let request_ctx = parent.with_request(&addr, &req, "TEST-LISTENER-1.2.3.4:1234".into());
 
+        // Query values are case-sensitive, so the query is kept as is
+        assert_eq!(
+            request_ctx.get(&"CTX_REQUEST_QUERY".into()),
+            Some(&"query=Some-Query".into())
+        );
+
         assert_ron_snapshot!(request_ctx, {".own" => insta::sorted_redaction(), ".parent.own" => insta::sorted_redaction()});
     }
 
@@ -334,6 +386,32 @@ pub mod test_context {
 
         assert_ron_snapshot!(response_ctx, {".own" => insta::sorted_redaction(), ".parent.own" => insta::sorted_redaction()});
     }
+
+    #[test]
+    fn step_context() {
+        let parent = get_test_ctx();
+        let response = get_test_response();
+        let response_ctx = parent.with_response(&response);
+
+        let vars = response_ctx.step_vars("auth-service.v1");
+        assert_eq!(
+            vars.get("CTX_STEP_AUTH_SERVICE_V1_STATUS"),
+            response_ctx.get(&"CTX_RESPONSE_STATUS".into())
+        );
+        assert_eq!(vars.len(), response.headers().len() + 1);
+        assert!(vars
+            .keys()
+            .all(|k| k.starts_with("CTX_STEP_AUTH_SERVICE_V1_")));
+
+        let ctx = parent.with(ContextMap::new()).with_vars(vars);
+        assert!(ctx.get(&"CTX_STEP_AUTH_SERVICE_V1_STATUS".into()).is_some());
+    }
+
+    #[test]
+    fn target_prefixes() {
+        assert_eq!(step_prefix("auth"), "CTX_STEP_AUTH_");
+        assert_eq!(targets_prefix("Target-1.v2"), "CTX_TARGETS_TARGET_1_V2_");
+    }
 }
 
 #[cfg(test)]
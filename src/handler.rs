@@ -1,33 +1,57 @@
 use crate::{
+    compression::{self, CompressionError},
+    condition::ConditionInput,
     config::{
-        headers::HeadersTransformator,
+        compression::CompressionConfig,
+        cors::CorsBehavior,
+        errors::{ErrorDetails, ErrorKind},
+        extract::ExtractBehavior,
+        headers::{update_body_headers, HeadersTransformator},
         listener::{ListenerConfig, ResponseStrategy},
-        response::{ResponseBehavior, ResponseKind},
+        response::{no_targets_error, ResponseBehavior, ResponseKind},
         target::{TargetBehavior, TargetConditionConfig, TargetConfig, TargetOnErrorAction},
+        uri::UriTransformator,
     },
-    context::Context,
+    context::{targets_prefix, Context, ContextMap},
+    oauth2_auth,
+    response_cache::{self, CacheLookup},
+    response_compare,
 };
+use futures_util::{stream::FuturesUnordered, StreamExt};
 use http::HeaderValue;
 use http_body_util::{BodyExt, Full};
 use hyper::{
     body::{Bytes, Incoming},
-    header::HOST,
-    http, Request, Response, StatusCode, Uri,
+    header::{AUTHORIZATION, CONTENT_ENCODING, CONTENT_LENGTH, HOST},
+    http, HeaderMap, Request, Response, StatusCode, Uri,
 };
 use shellexpand::env_with_context_no_errors;
-use std::{collections::HashMap, net::SocketAddr};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    time::{Duration, Instant},
+};
+use strum_macros::Display;
+use tokio::task::JoinError;
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
 pub type ResponsesMap<'a> = HashMap<String, (Option<Response<Full<Bytes>>>, &'a Context<'a>)>;
 pub type HyperError = hyper_util::client::legacy::Error;
+type TargetResult = Result<Result<Response<Incoming>, HyperError>, tokio::time::error::Elapsed>;
+type TargetRequest = Pin<Box<dyn Future<Output = (TargetResult, Duration)> + Send>>;
 
 enum TargetDispatch {
-    Spawned(
-        tokio::task::JoinHandle<
-            Result<Result<Response<Incoming>, HyperError>, tokio::time::error::Elapsed>,
-        >,
-    ),
+    Spawned(tokio::task::JoinHandle<(TargetResult, Duration)>),
+    /// Request which is built, signed and sent only when its result is awaited
+    Deferred,
+    /// Pipeline step which has been already awaited
+    Completed(ResponseResult, Duration),
+    /// Pipeline step which isn't queried since some of its dependencies failed
+    Skipped,
     SigningFailed(String),
 }
 
@@ -50,8 +74,26 @@ impl RequestHandler {
         self,
         addr: SocketAddr,
         req: Request<Incoming>,
-    ) -> Result<Response<Full<Bytes>>, http::Error> {
+    ) -> Result<Response<Full<Bytes>>, Infallible> {
         let req_id = Uuid::new_v4();
+        match self.process(req_id, addr, req).await {
+            Ok(resp) => Ok(resp),
+            Err(e) => {
+                error!(
+                    "{req_id}: failed, listener: {}: {e}",
+                    self.listener_cfg.id()
+                );
+                Ok(self.failure_response(req_id, e))
+            }
+        }
+    }
+
+    async fn process(
+        self,
+        req_id: Uuid,
+        addr: SocketAddr,
+        req: Request<Incoming>,
+    ) -> Result<Response<Full<Bytes>>, HandlerError> {
         info!(
             "{req_id}: accepted from: {}, to: {}, method: {}",
             addr,
@@ -61,35 +103,109 @@ impl RequestHandler {
 
         let response_cfg = self.listener_cfg.response();
 
+        // Answer CORS preflight locally, without querying targets
+        if let Some(cors) = self.listener_cfg.cors() {
+            if cors.is_preflight(req.method(), req.headers()) {
+                let resp = cors.preflight_response(req.headers(), self.listener_cfg);
+                info!(
+                    "{req_id}: completed, cors preflight, status={}",
+                    resp.status().as_u16()
+                );
+                return Ok(resp);
+            }
+        }
+
+        // Add own context - listener + request
+        let (req_parts, req_body) = req.into_parts();
+        let ctx = self
+            .root_ctx
+            .with_request(&addr, &req_parts, self.listener_cfg.id())
+            .with_vars(ContextMap::from([(
+                "CTX_REQUEST_ID".into(),
+                req_id.to_string(),
+            )]));
+
         // Verify is method allowed in the config
-        if !self.listener_cfg.is_method_allowed(req.method().as_ref()) {
+        if !self
+            .listener_cfg
+            .is_method_allowed(req_parts.method.as_ref())
+        {
             error!(
                 "{req_id}: rejected, not allowed method: {}, listener: {}",
-                req.method(),
+                req_parts.method,
                 self.listener_cfg.id()
             );
-            return response_cfg.empty_response(StatusCode::METHOD_NOT_ALLOWED.into());
+            return Ok(response_cfg.failure_response(
+                StatusCode::METHOD_NOT_ALLOWED.into(),
+                ErrorDetails::new(
+                    ErrorKind::MethodNotAllowed,
+                    format!("method `{}` is not allowed", req_parts.method),
+                ),
+                &ctx,
+            )?);
         }
 
         // Prepare owned body
-        let (req_parts, req_body) = req.into_parts();
-        let body_bytes = req_body
+        let mut body_bytes = req_body
             .collect()
             .await
-            .expect("Looks like a BUG!")
+            .map_err(HandlerError::RequestBody)?
             .to_bytes();
-        // Add own context - listener + request
-        let ctx = self
-            .root_ctx
-            .with_request(&addr, &req_parts, self.listener_cfg.id());
-
-        // Prepare new headers
-        let mut headers = req_parts.headers.clone();
-        headers.remove(HOST);
-        if let Some(transforms) = self.listener_cfg.headers() {
-            transforms.transform(&mut headers, &ctx)
+        // Decode request body before passing it to conditions and targets
+        let mut body_decoded = false;
+        if self
+            .listener_cfg
+            .compression()
+            .is_some_and(CompressionConfig::decode_request)
+        {
+            let limit = self
+                .listener_cfg
+                .compression()
+                .map_or(usize::MAX, CompressionConfig::max_decoded_size);
+            match compression::decode_content(&req_parts.headers, &body_bytes, limit) {
+                Ok(Some(decoded)) => {
+                    debug!(
+                        "request body decoded: {} -> {} bytes",
+                        body_bytes.len(),
+                        decoded.len()
+                    );
+                    body_bytes = decoded;
+                    body_decoded = true;
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    error!(
+                        "{req_id}: rejected, unable to decode request body, listener: {}: {e}",
+                        self.listener_cfg.id()
+                    );
+                    let (status, kind) = match e {
+                        CompressionError::Unsupported(_) => (
+                            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                            ErrorKind::UnsupportedMediaType,
+                        ),
+                        CompressionError::TooLarge { .. } => {
+                            (StatusCode::PAYLOAD_TOO_LARGE, ErrorKind::PayloadTooLarge)
+                        }
+                        _ => (StatusCode::BAD_REQUEST, ErrorKind::BadRequest),
+                    };
+                    return Ok(response_cfg.failure_response(
+                        status.into(),
+                        ErrorDetails::new(kind, format!("unable to decode request body: {e}")),
+                        &ctx,
+                    )?);
+                }
+            }
+        }
+        // Serve response from cache if possible, without querying targets
+        let cache_lookup = response_cache::lookup(self.listener_cfg, &req_parts, &ctx);
+        if let CacheLookup::Hit(resp) = cache_lookup {
+            let resp = self.finalize_response(&req_parts.headers, resp).await;
+            info!(
+                "{req_id}: completed from cache, status={}",
+                resp.status().as_u16()
+            );
+            return Ok(resp);
         }
-        debug!("request headers: {:?}", headers);
 
         // Process targets
         debug!(
@@ -100,59 +216,105 @@ impl RequestHandler {
 
         let mut target_requests = vec![];
         let mut target_ctx = vec![];
-        let mut target_ids = vec![];
+        let mut target_ids: Vec<String> = vec![];
 
         let mut targets: Vec<&TargetConfig> = vec![];
+        let failover = matches!(self.listener_cfg.strategy(), ResponseStrategy::Failover);
         let mut conditional_target_id: Option<String> = None;
 
-        // Verify conditions
-        for target in self.listener_cfg.targets() {
-            match &self.listener_cfg.strategy() {
-                // Special flow in case of conditional routing
-                ResponseStrategy::ConditionalRouting => {
-                    match target.condition().as_ref().unwrap() {
-                        // Always insert default into empty targets list
-                        TargetConditionConfig::Default => {
-                            if targets.is_empty() {
-                                targets.push(target)
+        // Extract variables and verify conditions, their input is built once and shared
+        let mut extracted_vars = ContextMap::new();
+        {
+            let condition_input = ConditionInput::new(&ctx, &addr, &req_parts, &body_bytes);
+            if let Some(extract) = self.listener_cfg.extract() {
+                extracted_vars = extract.extract(&condition_input);
+            }
+            for target in self.listener_cfg.targets() {
+                let condition_passed = match target.condition() {
+                    Some(TargetConditionConfig::Filter(_)) => {
+                        match target.check_condition(&condition_input) {
+                            Ok(passed) => passed,
+                            Err(e) => {
+                                error!(
+                                    "{req_id}: rejected, condition of target `{}` failed, listener: {}: {e}",
+                                    target.id(),
+                                    self.listener_cfg.id()
+                                );
+                                return Ok(response_cfg.failure_response(
+                                    StatusCode::BAD_REQUEST.into(),
+                                    ErrorDetails::new(
+                                        ErrorKind::BadRequest,
+                                        format!("condition of the target failed: {e}"),
+                                    )
+                                    .with_target(target.id()),
+                                    &ctx,
+                                )?);
                             }
                         }
-                        TargetConditionConfig::Filter(_) => {
-                            if target.check_condition(&ctx, &req_parts, &body_bytes) {
+                    }
+                    _ => false,
+                };
+                match &self.listener_cfg.strategy() {
+                    // Special flow in case of conditional routing
+                    ResponseStrategy::ConditionalRouting => {
+                        match target
+                            .condition()
+                            .as_ref()
+                            .expect("condition is validated to be present for `conditional_routing` strategy")
+                        {
+                            // Always insert default into empty targets list
+                            TargetConditionConfig::Default => {
                                 if targets.is_empty() {
                                     targets.push(target)
-                                } else if matches!(
-                                    targets[0].condition().as_ref().unwrap(),
-                                    TargetConditionConfig::Default
-                                ) {
-                                    // Replace default by this target
-                                    targets.pop();
-                                    targets.push(target);
-                                } else {
-                                    // Error - more than one target has true condition
-                                    error!("{req_id}: not routed: more than one targets satisfy condition, listener: {}, targets: `{}` and `{}`", self.listener_cfg.id(), targets[0].id(), target.id());
-                                    return response_cfg.no_target_response(&ctx);
                                 }
                             }
+                            TargetConditionConfig::Filter(_) => {
+                                if condition_passed {
+                                    if targets.is_empty() {
+                                        targets.push(target)
+                                    } else if matches!(
+                                        targets[0].condition(),
+                                        Some(TargetConditionConfig::Default)
+                                    ) {
+                                        // Replace default by this target
+                                        targets.pop();
+                                        targets.push(target);
+                                    } else {
+                                        // Error - more than one target has true condition
+                                        error!("{req_id}: not routed: more than one targets satisfy condition, listener: {}, targets: `{}` and `{}`", self.listener_cfg.id(), targets[0].id(), target.id());
+                                        return Ok(response_cfg.no_target_response(
+                                            ErrorDetails::new(
+                                                ErrorKind::MultipleMatches,
+                                                format!(
+                                                    "more than one target satisfy condition: `{}` and `{}`",
+                                                    targets[0].id(),
+                                                    target.id()
+                                                ),
+                                            ),
+                                            &ctx,
+                                        )?);
+                                    }
+                                }
+                            }
+                        };
+                        if !targets.is_empty() {
+                            conditional_target_id = Some(targets[0].id())
                         }
-                    };
-                    if !targets.is_empty() {
-                        conditional_target_id = Some(targets[0].id())
                     }
-                }
-                // Any other strategy
-                _ => {
-                    if let Some(condition) = target.condition().as_ref() {
-                        match condition {
-                            TargetConditionConfig::Default => targets.push(target),
-                            TargetConditionConfig::Filter(_) => {
-                                if target.check_condition(&ctx, &req_parts, &body_bytes) {
-                                    targets.push(target)
+                    // Any other strategy
+                    _ => {
+                        if let Some(condition) = target.condition().as_ref() {
+                            match condition {
+                                TargetConditionConfig::Default => targets.push(target),
+                                TargetConditionConfig::Filter(_) => {
+                                    if condition_passed {
+                                        targets.push(target)
+                                    }
                                 }
                             }
+                        } else {
+                            targets.push(target);
                         }
-                    } else {
-                        targets.push(target);
                     }
                 }
             }
@@ -165,108 +327,211 @@ impl RequestHandler {
             );
         }
 
+        // Pipeline steps are queried after the targets they depend on
+        TargetConfig::sort_by_pipeline_stage(&mut targets, self.listener_cfg.targets());
+
+        // Add extracted variables to the request context
+        let ctx = ctx.with(extracted_vars);
+
+        // Prepare new headers
+        let mut headers = req_parts.headers.clone();
+        headers.remove(HOST);
+        self.listener_cfg
+            .proxy_headers()
+            .update_request_headers(&mut headers, &addr, &req_parts);
+        if body_decoded {
+            headers.remove(CONTENT_ENCODING);
+            headers.remove(CONTENT_LENGTH);
+        }
+        if let Some(transforms) = self.listener_cfg.headers() {
+            transforms.transform(&mut headers, &ctx)
+        }
+        debug!("request headers: {:?}", headers);
+
+        let mut stage = 0;
+        let mut step_vars = ContextMap::new();
+        let mut succeeded_steps: Vec<String> = vec![];
         for target in targets.iter() {
-            let ctx = ctx.with_target(target);
-            let target_request_builder = Request::builder();
-            // Set method
-            let target_request_builder = target_request_builder.method(&req_parts.method);
-            // Set uri
-            let url = env_with_context_no_errors(target.url(), |v| ctx.get(&v.into()));
-            let uri: Uri = url.parse()?;
-            let mut target_request_builder = target_request_builder.uri(uri);
-            // Prepare headers
-            let mut headers = headers.clone();
-            if let Some(transforms) = &target.headers() {
-                transforms.transform(&mut headers, &ctx);
+            // Wait for responses of the previous pipeline steps before the next step
+            let target_stage = target.pipeline_stage(self.listener_cfg.targets());
+            if target_stage > stage {
+                stage = target_stage;
+                for (pos, dispatch) in target_requests.iter_mut().enumerate() {
+                    if let TargetDispatch::Spawned(handle) = dispatch {
+                        let (r, duration) = join_target_request(handle).await;
+                        let r =
+                            transform_result(self.listener_cfg, targets[pos], &target_ctx[pos], r);
+                        if let ResponseResult::Ok(resp) = &r {
+                            if !response_cfg.is_failed_status(targets[pos], resp.status()) {
+                                step_vars.extend(targets[pos].step_vars(&target_ctx[pos], resp));
+                                succeeded_steps.push(target_ids[pos].clone());
+                            }
+                        }
+                        *dispatch = TargetDispatch::Completed(r, duration);
+                    }
+                }
             }
-            // Add Host header if empty
-            if !headers.contains_key(HOST) {
-                let uri: Uri = url.parse()?;
-                let host = uri
-                    .host()
-                    .unwrap_or_else(|| panic!("there is no `host` part in the URI: {uri:?}"));
-
-                debug!("add host header: {host}");
-                headers.insert(HOST, HeaderValue::from_str(host)?);
+
+            let ctx = ctx.with_target(target).with_vars(step_vars.clone());
+            target_ids.push(target.id());
+            if let Some(step) = target
+                .depends_on()
+                .iter()
+                .find(|id| !succeeded_steps.contains(id))
+            {
+                warn!(
+                    "{req_id}: target `{}` is skipped since step `{step}` isn't successful, listener: {}",
+                    target.id(),
+                    self.listener_cfg.id()
+                );
+                target_requests.push(TargetDispatch::Skipped);
+                target_ctx.push(ctx);
+                continue;
             }
-            // Insert all headers into request
-            for (k, v) in &headers {
-                target_request_builder = target_request_builder.header(k, v);
+            // Failover queries targets one by one, so request is built, signed and sent only if it's needed
+            if failover {
+                target_requests.push(TargetDispatch::Deferred);
+                target_ctx.push(ctx);
+                continue;
             }
-            // Finalize request with body
-            let target_body: Bytes = if let Some(body) = &target.body() {
-                let body = env_with_context_no_errors(body, |v| ctx.get(&v.into()));
-                Bytes::from(body.into_owned().into_bytes())
-            } else {
-                body_bytes.clone()
+            let (target_request, target_body) =
+                self.target_request(req_id, target, &ctx, &req_parts, &headers, &body_bytes)?;
+            let dispatch = match self
+                .signed_target_request(req_id, target, target_request, &target_body, &ctx)
+                .await
+            {
+                Ok(request) => TargetDispatch::Spawned(tokio::spawn(request)),
+                Err(cause) => TargetDispatch::SigningFailed(cause),
             };
-            let mut target_request: Request<Full<Bytes>> =
-                target_request_builder.body(Full::from(target_body.clone()))?;
-
-            // Sign the request if this target requires AWS SigV4
-            let signing_result = target.sign_request(&mut target_request, &target_body).await;
-
-            match signing_result {
-                Ok(()) => {
-                    // Put request to queue
-                    debug!(
-                        "add to queue: target `{}` request: {:?}",
-                        target.id(),
-                        target_request
-                    );
-
-                    // Prepare target request
-                    let http_client = target.https_client(self.listener_cfg.tls());
-                    let http_request = http_client.request(target_request);
-                    let http_request = tokio::time::timeout(*target.timeout(), http_request);
+            target_requests.push(dispatch);
+            target_ctx.push(ctx);
+        }
 
-                    target_requests.push(TargetDispatch::Spawned(tokio::spawn(http_request)));
+        // Get results, they're in order of targets, `None` means the target wasn't awaited
+        let mut results: Vec<Option<ResponseResult>> =
+            target_requests.iter().map(|_| None).collect();
+        let mut durations: Vec<Duration> = vec![Duration::MAX; target_requests.len()];
+        let mut spawned = FuturesUnordered::new();
+        let is_ok = |pos: usize, r: &ResponseResult| match r {
+            ResponseResult::Ok(resp) => !response_cfg.is_failed_status(targets[pos], resp.status()),
+            _ => false,
+        };
+        let skipped: Vec<bool> = target_requests
+            .iter()
+            .map(|dispatch| matches!(dispatch, TargetDispatch::Skipped))
+            .collect();
+        let failover_deadline = response_cfg
+            .failover_timeout()
+            .map(|timeout| tokio::time::Instant::now() + timeout);
+        for (pos, dispatch) in target_requests.into_iter().enumerate() {
+            let (r, duration) = match dispatch {
+                TargetDispatch::SigningFailed(cause) => {
+                    (ResponseResult::SigningError(cause), Duration::ZERO)
                 }
-                Err(e) => {
-                    error!(
-                        "{req_id}: target `{}` signing failed, listener: {}: {e}",
-                        target.id(),
-                        self.listener_cfg.id()
+                TargetDispatch::Spawned(handle) => {
+                    let (listener_cfg, target, ctx) =
+                        (self.listener_cfg, targets[pos], &target_ctx[pos]);
+                    spawned.push(async move {
+                        let (r, duration) = join_target_request(handle).await;
+                        let r = transform_result(listener_cfg, target, ctx, r);
+                        (pos, r, duration)
+                    });
+                    continue;
+                }
+                TargetDispatch::Completed(r, duration) => (r, duration),
+                TargetDispatch::Skipped => continue,
+                TargetDispatch::Deferred => {
+                    let started = Instant::now();
+                    let request = self.deferred_target_request(
+                        req_id,
+                        targets[pos],
+                        &target_ctx[pos],
+                        &req_parts,
+                        &headers,
+                        &body_bytes,
                     );
-                    target_requests.push(TargetDispatch::SigningFailed(e.to_string()));
+                    let result = match failover_deadline {
+                        Some(deadline) => tokio::time::timeout_at(deadline, request).await.ok(),
+                        None => Some(request.await),
+                    };
+                    match result {
+                        Some((r, duration)) => (
+                            transform_result(self.listener_cfg, targets[pos], &target_ctx[pos], r),
+                            duration,
+                        ),
+                        None => (ResponseResult::Timeout, started.elapsed()),
+                    }
                 }
+            };
+            let succeeded = is_ok(pos, &r);
+            results[pos] = Some(r);
+            durations[pos] = duration;
+
+            // Failover stops on the first successful response or when the deadline is reached
+            if failover {
+                let expired = failover_deadline
+                    .is_some_and(|deadline| tokio::time::Instant::now() >= deadline);
+                if succeeded || expired {
+                    break;
+                }
+                debug!(
+                    "failover: target `{}` failed, trying next one",
+                    targets[pos].id()
+                );
             }
-            target_ctx.push(ctx);
-            target_ids.push(target.id());
         }
 
-        // Get results
-        let mut results: Vec<ResponseResult> = vec![];
-        for dispatch in target_requests {
-            let r = match dispatch {
-                TargetDispatch::SigningFailed(cause) => ResponseResult::SigningError(cause),
-                TargetDispatch::Spawned(handle) => match handle.await.unwrap() {
-                    Err(_ee) => ResponseResult::Timeout,
-                    Ok(r) => match r {
-                        Ok(r) => {
-                            // Prepare owned body
-                            let (parts, body) = r.into_parts();
-                            let body = body.collect().await.expect("Looks like a BUG!").to_bytes();
-                            let r: Response<Full<Bytes>> =
-                                Response::from_parts(parts, Full::from(body));
-                            ResponseResult::Ok(r)
-                        }
-                        Err(he) => ResponseResult::HyperError(he),
-                    },
-                },
-            };
-            results.push(r);
+        // Spawned requests are collected in order of their completion,
+        // the rest of them continue in background if quorum is reached and early return is allowed
+        let early_quorum = response_cfg
+            .quorum()
+            .filter(|quorum| quorum.early_return())
+            .filter(|_| matches!(self.listener_cfg.strategy(), ResponseStrategy::Quorum))
+            .map(|quorum| quorum.required(targets.len()));
+        let mut ok_count = results
+            .iter()
+            .enumerate()
+            .filter(|(pos, r)| r.as_ref().is_some_and(|r| is_ok(*pos, r)))
+            .count();
+        while let Some((pos, r, duration)) = spawned.next().await {
+            if is_ok(pos, &r) {
+                ok_count += 1;
+            }
+            results[pos] = Some(r);
+            durations[pos] = duration;
+            if early_quorum.is_some_and(|required| ok_count >= required) {
+                debug!(
+                    "quorum is reached, {} targets continue in background",
+                    spawned.len()
+                );
+                break;
+            }
         }
 
         // Preprocess results
-        let mut responses: ResponsesMap = ResponsesMap::new();
+        let mut target_responses = vec![];
+        let mut targets_vars = ContextMap::new();
+        let (mut targets_ok_count, mut targets_failed_count) = (0, 0);
         for (pos, res) in results.into_iter().enumerate() {
+            let Some(res) = res else {
+                // Skipped pipeline steps aren't queried, so they have the outcome only
+                if skipped[pos] {
+                    targets_vars.extend(targets_vars_of(
+                        &target_ids[pos],
+                        TargetOutcome::Skipped,
+                        None,
+                        Duration::ZERO,
+                    ));
+                }
+                continue;
+            };
             if self.listener_cfg.log_target_status() {
                 let status = match &res {
                     ResponseResult::Ok(response) => format!("ok {}", response.status().as_u16()),
                     ResponseResult::HyperError(error) => format!("error: {}", error),
                     ResponseResult::Timeout => "timeout".to_string(),
-                    ResponseResult::SigningError(cause) => format!("aws signing error: {cause}"),
+                    ResponseResult::SigningError(cause) => format!("signing error: {cause}"),
+                    ResponseResult::TargetError(cause) => format!("error: {cause}"),
                 };
                 info!(
                     "{req_id}: listener: {}, target `{}`, status: {}",
@@ -275,33 +540,91 @@ impl RequestHandler {
                     status
                 )
             }
-            match res {
+            let target = targets[pos];
+            let (resp, outcome) = match res {
                 ResponseResult::Ok(resp) => {
                     debug!("OK response: {:#?}", resp);
-                    responses.insert(target_ids[pos].clone(), (Some(resp), &target_ctx[pos]));
+                    let outcome = if response_cfg.is_failed_status(target, resp.status()) {
+                        TargetOutcome::Failed
+                    } else {
+                        TargetOutcome::Ok
+                    };
+                    (Some(resp), outcome)
                 }
                 ResponseResult::HyperError(_)
                 | ResponseResult::Timeout
-                | ResponseResult::SigningError(_) => {
+                | ResponseResult::SigningError(_)
+                | ResponseResult::TargetError(_) => {
                     debug!("ERR response: {:#?}", res);
-                    let target = targets[pos];
-                    let resp = match target.on_error() {
+                    let outcome = match res {
+                        ResponseResult::Timeout => TargetOutcome::Timeout,
+                        _ => TargetOutcome::Error,
+                    };
+                    match target.on_error() {
                         TargetOnErrorAction::Propagate => {
-                            Some(response_cfg.error_response(res, &None))
+                            let resp = response_cfg.error_response(
+                                res,
+                                &None,
+                                &target_ids[pos],
+                                &target_ctx[pos],
+                            )?;
+                            (Some(resp), outcome)
                         }
-                        TargetOnErrorAction::Status => {
-                            Some(response_cfg.error_response(res, &target.error_status()))
-                        }
-                        TargetOnErrorAction::Drop => None,
-                    };
-                    responses.insert(target_ids[pos].clone(), (resp, &target_ctx[pos]));
+                        TargetOnErrorAction::Status => (
+                            Some(response_cfg.error_response(
+                                res,
+                                &target.error_status(),
+                                &target_ids[pos],
+                                &target_ctx[pos],
+                            )?),
+                            outcome,
+                        ),
+                        TargetOnErrorAction::Drop => (None, TargetOutcome::Dropped),
+                    }
                 }
+            };
+            if outcome == TargetOutcome::Ok {
+                targets_ok_count += 1;
+            } else {
+                targets_failed_count += 1;
             }
+            targets_vars.extend(targets_vars_of(
+                &target_ids[pos],
+                outcome,
+                resp.as_ref(),
+                durations[pos],
+            ));
+            target_responses.push((pos, resp));
+        }
+        targets_vars.insert("CTX_TARGETS_OK_COUNT".into(), targets_ok_count.to_string());
+        targets_vars.insert(
+            "CTX_TARGETS_FAILED_COUNT".into(),
+            targets_failed_count.to_string(),
+        );
+
+        // Expose results of all queried targets in contexts of the response
+        drop(spawned);
+        let target_ctx: Vec<Context> = target_ctx
+            .into_iter()
+            .map(|ctx| ctx.with_vars(targets_vars.clone()))
+            .collect();
+        let ctx = ctx.with(targets_vars);
+        let mut responses: ResponsesMap = ResponsesMap::new();
+        for (pos, resp) in target_responses {
+            responses.insert(target_ids[pos].clone(), (resp, &target_ctx[pos]));
+        }
+
+        // Compare baseline and candidate responses in background, it doesn't affect the response
+        if let Some(compare) = self.listener_cfg.compare() {
+            response_compare::spawn(self.listener_cfg, compare, &responses, req_id);
         }
 
         // Select/create response according to strategy
-        let ok_target_id = response_cfg.find_first_response(&responses, ResponseKind::Ok);
-        let failed_target_id = response_cfg.find_first_response(&responses, ResponseKind::Failed);
+        let preferred_targets = response_cfg.preferred_targets(&targets, &durations);
+        let ok_target_id =
+            response_cfg.find_first_response(&preferred_targets, &responses, ResponseKind::Ok);
+        let failed_target_id =
+            response_cfg.find_first_response(&preferred_targets, &responses, ResponseKind::Failed);
         let selector_target_id = response_cfg.target_selector().clone();
         let resp =
             match &self.listener_cfg.strategy() {
@@ -309,43 +632,87 @@ impl RequestHandler {
                     response_cfg.override_empty_response(StatusCode::OK.into(), &ctx)?
                 }
                 ResponseStrategy::OkThenOverride => response_cfg
-                    .select_target_or_override_response(ok_target_id, &mut responses, &ctx),
+                    .select_target_or_override_response(ok_target_id, &mut responses, &ctx)?,
                 ResponseStrategy::FailedThenOverride => response_cfg
-                    .select_target_or_override_response(failed_target_id, &mut responses, &ctx),
+                    .select_target_or_override_response(failed_target_id, &mut responses, &ctx)?,
                 ResponseStrategy::OkThenTargetId => response_cfg.select_from_two_targets_response(
                     ok_target_id,
                     selector_target_id,
                     &mut responses,
                     &ctx,
-                ),
+                )?,
                 ResponseStrategy::FailedThenTargetId => response_cfg
                     .select_from_two_targets_response(
                         failed_target_id,
                         selector_target_id,
                         &mut responses,
                         &ctx,
-                    ),
+                    )?,
                 ResponseStrategy::OkThenFailed => response_cfg.select_from_two_targets_response(
                     ok_target_id,
                     failed_target_id,
                     &mut responses,
                     &ctx,
-                ),
+                )?,
                 ResponseStrategy::FailedThenOk => response_cfg.select_from_two_targets_response(
                     failed_target_id,
                     ok_target_id,
                     &mut responses,
                     &ctx,
-                ),
+                )?,
                 ResponseStrategy::AlwaysTargetId => response_cfg.select_target_or_error_response(
                     selector_target_id,
                     &mut responses,
                     &ctx,
-                ),
+                )?,
                 ResponseStrategy::ConditionalRouting => response_cfg
-                    .select_target_or_error_response(conditional_target_id, &mut responses, &ctx),
+                    .select_target_or_error_response(conditional_target_id, &mut responses, &ctx)?,
+                ResponseStrategy::SelectByExpression => {
+                    match response_cfg.find_response_by_expression(&target_ids, &responses) {
+                        Ok(target_id) => response_cfg.select_target_or_override_response(
+                            target_id,
+                            &mut responses,
+                            &ctx,
+                        )?,
+                        Err(e) => {
+                            error!(
+                                "{req_id}: unable to select response, listener: {}: {e}",
+                                self.listener_cfg.id()
+                            );
+                            response_cfg.no_target_response(no_targets_error(), &ctx)?
+                        }
+                    }
+                }
+                ResponseStrategy::Failover => {
+                    // The last queried target is the successful one or the last failed one
+                    let target_id = target_ids
+                        .iter()
+                        .rev()
+                        .find(|id| matches!(responses.get(*id), Some((Some(_), _))))
+                        .cloned();
+                    response_cfg.select_target_or_error_response(target_id, &mut responses, &ctx)?
+                }
+                ResponseStrategy::Quorum => {
+                    response_cfg.quorum_response(&preferred_targets, &mut responses, &ctx)?
+                }
+                ResponseStrategy::Aggregate => {
+                    match response_cfg.aggregate_response(&targets, &responses, &ctx) {
+                        Ok(resp) => resp,
+                        Err(e) => {
+                            error!(
+                                "{req_id}: unable to aggregate responses, listener: {}: {e}",
+                                self.listener_cfg.id()
+                            );
+                            response_cfg.no_target_response(no_targets_error(), &ctx)?
+                        }
+                    }
+                }
             };
 
+        // Store response in cache or replace failed response by stale one
+        let resp = cache_lookup.complete(resp).await;
+        let resp = self.finalize_response(&req_parts.headers, resp).await;
+
         // Final response
         debug!("Final response: {:?}", resp);
         info!("{req_id}: completed, status={}", resp.status().as_u16());
@@ -353,10 +720,356 @@ impl RequestHandler {
     }
 }
 
+impl RequestHandler {
+    /// Builds request of the target from the incoming one: URL, headers and body are rendered
+    /// and transformed according to the target's config.
+    /// Returns the request and its body, which is required for signing.
+    fn target_request(
+        &self,
+        req_id: Uuid,
+        target: &TargetConfig,
+        ctx: &Context,
+        req_parts: &http::request::Parts,
+        headers: &HeaderMap,
+        req_body: &Bytes,
+    ) -> Result<(Request<Full<Bytes>>, Bytes), HandlerError> {
+        let target_request_builder = Request::builder();
+        // Set method
+        let target_request_builder = target_request_builder.method(&req_parts.method);
+        // Set uri
+        let url = env_with_context_no_errors(target.url(), |v| ctx.get(&v.into()));
+        let target_error = |cause: http::Error| HandlerError::Target {
+            target_id: target.id(),
+            cause,
+        };
+        let mut uri: Uri = url
+            .parse()
+            .map_err(|e| target_error(http::Error::from(e)))?;
+        if let Some(transforms) = target.uri_transforms() {
+            uri = transforms
+                .transform(&uri, &req_parts.uri, ctx)
+                .map_err(target_error)?;
+        }
+        let mut target_request_builder = target_request_builder.uri(&uri);
+        // Prepare headers
+        let mut headers = headers.clone();
+        if let Some(transforms) = &target.headers() {
+            transforms.transform(&mut headers, ctx);
+        }
+        // Add Host header if empty
+        if !headers.contains_key(HOST) {
+            let host = uri.host().ok_or_else(|| HandlerError::TargetHost {
+                target_id: target.id(),
+                uri: uri.to_string(),
+            })?;
+
+            debug!("add host header: {host}");
+            headers.insert(
+                HOST,
+                HeaderValue::from_str(host).map_err(|e| target_error(e.into()))?,
+            );
+        }
+        // Finalize request with body
+        let mut target_body: Bytes = if let Some(body) = &target.body() {
+            let body = env_with_context_no_errors(body, |v| ctx.get(&v.into()));
+            update_body_headers(&mut headers, body.len(), target.content_type());
+            Bytes::from(body.into_owned().into_bytes())
+        } else {
+            req_body.clone()
+        };
+        // Compress body if it's required and body isn't encoded yet
+        if let Some(encoding) = target.compress_request() {
+            if !target_body.is_empty() && !headers.contains_key(CONTENT_ENCODING) {
+                match compression::encode(encoding, &target_body) {
+                    Ok(encoded) => {
+                        target_body = encoded;
+                        headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.into()));
+                        headers.insert(CONTENT_LENGTH, HeaderValue::from(target_body.len()));
+                    }
+                    Err(e) => warn!(
+                        "{req_id}: target `{}` request isn't compressed, listener: {}: {e}",
+                        target.id(),
+                        self.listener_cfg.id()
+                    ),
+                }
+            }
+        }
+        // Insert all headers into request
+        for (k, v) in &headers {
+            target_request_builder = target_request_builder.header(k, v);
+        }
+        let target_request = target_request_builder
+            .body(Full::from(target_body.clone()))
+            .map_err(target_error)?;
+
+        Ok((target_request, target_body))
+    }
+
+    /// Authorizes and signs the target's request if it's configured,
+    /// returns the future which sends the request and measures time of the target's response.
+    async fn signed_target_request(
+        &self,
+        req_id: Uuid,
+        target: &'static TargetConfig,
+        mut target_request: Request<Full<Bytes>>,
+        target_body: &Bytes,
+        ctx: &Context<'_>,
+    ) -> Result<TargetRequest, String> {
+        // Authorize and sign the request if this target requires OAuth2, HMAC or AWS SigV4
+        let tls_config = target.tls_config(self.listener_cfg.tls());
+        let http_client = target.https_client(self.listener_cfg.tls());
+        if let Err(e) = target
+            .sign_request(
+                &mut target_request,
+                target_body,
+                ctx,
+                &http_client,
+                tls_config,
+            )
+            .await
+        {
+            error!(
+                "{req_id}: target `{}` signing failed, listener: {}: {e}",
+                target.id(),
+                self.listener_cfg.id()
+            );
+            return Err(e.to_string());
+        }
+        debug!("target `{}` request: {:?}", target.id(), target_request);
+
+        // OAuth2 token rejected by the target shouldn't be reused
+        let oauth2_token = target
+            .oauth2()
+            .zip(target_request.headers().get(AUTHORIZATION).cloned());
+
+        let http_request = http_client.request(target_request);
+        let timeout = *target.timeout();
+        Ok(Box::pin(async move {
+            let started = Instant::now();
+            let result = tokio::time::timeout(timeout, http_request).await;
+            let elapsed = started.elapsed();
+            if let (Some((cfg, token)), Ok(Ok(resp))) = (&oauth2_token, &result) {
+                if resp.status() == StatusCode::UNAUTHORIZED {
+                    oauth2_auth::invalidate_rejected_token(cfg, timeout, tls_config, token).await;
+                }
+            }
+            (result, elapsed)
+        }))
+    }
+
+    /// Builds, signs and sends the target's request which has been deferred by failover.
+    /// Unlike other strategies, failure to build the request is reported as the target's error,
+    /// so the next target is tried.
+    async fn deferred_target_request(
+        &self,
+        req_id: Uuid,
+        target: &'static TargetConfig,
+        ctx: &Context<'_>,
+        req_parts: &http::request::Parts,
+        headers: &HeaderMap,
+        req_body: &Bytes,
+    ) -> (ResponseResult, Duration) {
+        let (target_request, target_body) =
+            match self.target_request(req_id, target, ctx, req_parts, headers, req_body) {
+                Ok(request) => request,
+                Err(e) => {
+                    error!("{req_id}: {e}, listener: {}", self.listener_cfg.id());
+                    return (ResponseResult::TargetError(e.to_string()), Duration::ZERO);
+                }
+            };
+        match self
+            .signed_target_request(req_id, target, target_request, &target_body, ctx)
+            .await
+        {
+            Ok(request) => {
+                let (r, duration) = request.await;
+                (into_response_result(r).await, duration)
+            }
+            Err(cause) => (ResponseResult::SigningError(cause), Duration::ZERO),
+        }
+    }
+
+    /// Creates error response for the failed request processing,
+    /// context includes request ID only since request context may be unavailable yet
+    fn failure_response(&self, req_id: Uuid, e: HandlerError) -> Response<Full<Bytes>> {
+        let ctx = self.root_ctx.with(ContextMap::from([(
+            "CTX_REQUEST_ID".into(),
+            req_id.to_string(),
+        )]));
+        let (status, details) = e.into_details();
+        self.listener_cfg
+            .response()
+            .failure_response(status.into(), details, &ctx)
+            .unwrap_or_else(|e| {
+                error!("{req_id}: unable to create error response: {e}");
+                let mut resp = Response::new(Full::default());
+                *resp.status_mut() = status;
+                resp
+            })
+    }
+
+    /// Compresses final response and adds CORS headers, if it's configured
+    async fn finalize_response(
+        &self,
+        req_headers: &HeaderMap,
+        resp: Response<Full<Bytes>>,
+    ) -> Response<Full<Bytes>> {
+        let mut resp = if let Some(compression) = self.listener_cfg.compression() {
+            compression::compress_response(compression, req_headers, resp).await
+        } else {
+            resp
+        };
+        if let Some(cors) = self.listener_cfg.cors() {
+            cors.apply_cors_headers(req_headers, &mut resp);
+        }
+
+        resp
+    }
+}
+
+/// Waits for the spawned target's request and converts its result,
+/// aborted request is reported as the target's error
+async fn join_target_request(
+    handle: impl Future<Output = Result<(TargetResult, Duration), JoinError>>,
+) -> (ResponseResult, Duration) {
+    match handle.await {
+        Ok((r, duration)) => (into_response_result(r).await, duration),
+        Err(e) => (
+            ResponseResult::TargetError(format!("request has been aborted: {e}")),
+            Duration::ZERO,
+        ),
+    }
+}
+
+/// Converts target's result into response result with owned body
+async fn into_response_result(result: TargetResult) -> ResponseResult {
+    match result {
+        Err(_ee) => ResponseResult::Timeout,
+        Ok(r) => match r {
+            Ok(r) => {
+                // Prepare owned body
+                let (parts, body) = r.into_parts();
+                match body.collect().await {
+                    Ok(body) => {
+                        let r: Response<Full<Bytes>> =
+                            Response::from_parts(parts, Full::from(body.to_bytes()));
+                        ResponseResult::Ok(r)
+                    }
+                    Err(e) => {
+                        ResponseResult::TargetError(format!("unable to read response body: {e}"))
+                    }
+                }
+            }
+            Err(he) => ResponseResult::HyperError(he),
+        },
+    }
+}
+
+/// Result of the target's request exposed in the response context
+#[derive(Debug, Clone, Copy, PartialEq, Display)]
+#[strum(serialize_all = "snake_case")]
+enum TargetOutcome {
+    Ok,
+    Failed,
+    Timeout,
+    Error,
+    Dropped,
+    /// Pipeline step which isn't queried since some of its dependencies failed
+    Skipped,
+}
+
+/// Returns context variables with the target's result
+fn targets_vars_of(
+    target_id: &str,
+    outcome: TargetOutcome,
+    resp: Option<&Response<Full<Bytes>>>,
+    duration: Duration,
+) -> ContextMap {
+    // CTX_TARGETS_<UPPERCASE_TARGET_ID>_STATUS
+    // CTX_TARGETS_<UPPERCASE_TARGET_ID>_OUTCOME
+    // CTX_TARGETS_<UPPERCASE_TARGET_ID>_DURATION_MS
+    let prefix = targets_prefix(target_id);
+    let mut vars = ContextMap::new();
+    if let Some(resp) = resp {
+        vars.insert(
+            format!("{prefix}STATUS"),
+            resp.status().as_u16().to_string(),
+        );
+    }
+    vars.insert(format!("{prefix}OUTCOME"), outcome.to_string());
+    vars.insert(
+        format!("{prefix}DURATION_MS"),
+        duration.as_millis().to_string(),
+    );
+
+    vars
+}
+
+/// Applies proxy headers processing and target's response transformations to the successful result,
+/// so they're taken into account by any further processing
+fn transform_result(
+    listener: &ListenerConfig,
+    target: &TargetConfig,
+    ctx: &Context,
+    result: ResponseResult,
+) -> ResponseResult {
+    match result {
+        ResponseResult::Ok(mut resp) => {
+            let version = resp.version();
+            listener
+                .proxy_headers()
+                .update_response_headers(resp.headers_mut(), version);
+            ResponseResult::Ok(target.transform_response(resp, ctx))
+        }
+        result => result,
+    }
+}
+
+/// Failure of the request processing, it's responded with the configured error response
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum HandlerError {
+    #[error("unable to read request body: {0}")]
+    RequestBody(hyper::Error),
+    #[error("unable to prepare request of target `{target_id}`: {cause}")]
+    Target {
+        target_id: String,
+        cause: http::Error,
+    },
+    #[error("there is no `host` part in URI `{uri}` of target `{target_id}`")]
+    TargetHost { target_id: String, uri: String },
+    #[error("unable to create response: {0}")]
+    Response(#[from] http::Error),
+}
+
+impl HandlerError {
+    /// Returns status and details of the error response
+    fn into_details(self) -> (StatusCode, ErrorDetails) {
+        let message = self.to_string();
+        match self {
+            HandlerError::RequestBody(_) => (
+                StatusCode::BAD_REQUEST,
+                ErrorDetails::new(ErrorKind::BadRequest, message),
+            ),
+            HandlerError::Target { target_id, .. } | HandlerError::TargetHost { target_id, .. } => {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    ErrorDetails::new(ErrorKind::Internal, message).with_target(target_id),
+                )
+            }
+            HandlerError::Response(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorDetails::new(ErrorKind::Internal, message),
+            ),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum ResponseResult {
     Ok(Response<Full<Bytes>>),
     HyperError(HyperError),
     Timeout,
     SigningError(String),
+    /// Any other failure of the target's request
+    TargetError(String),
 }
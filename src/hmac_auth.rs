@@ -0,0 +1,174 @@
+use crate::{
+    config::hmac::{HmacAlgorithm, HmacConfig, HmacEncoding},
+    context::{Context, ContextMap},
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use hmac::{Hmac, Mac};
+use http_body_util::Full;
+use hyper::{
+    body::Bytes,
+    header::{HeaderName, HeaderValue, InvalidHeaderValue},
+    Request,
+};
+use shellexpand::env_with_context_no_errors;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+const INVALID_HEADER_NAME_ERROR: &str = "header name is validated on load, looks like a BUG";
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum HmacAuthError {
+    #[error("HMAC signature isn't a valid value of `{header}` header: {cause}")]
+    InvalidSignature {
+        header: String,
+        cause: InvalidHeaderValue,
+    },
+}
+
+/// Signs the request with HMAC according to the target's `hmac` config:
+/// computes the signature over the (templated) payload and puts it into the configured header,
+/// along with the timestamp header if it's configured.
+pub(crate) fn sign_request(
+    hmac_cfg: &HmacConfig,
+    request: &mut Request<Full<Bytes>>,
+    body: &Bytes,
+    ctx: &Context,
+) -> Result<(), HmacAuthError> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let payload: Bytes = if let Some(template) = hmac_cfg.payload_template() {
+        // CTX_HMAC_TIMESTAMP
+        // CTX_HMAC_BODY
+        let mut own = ContextMap::new();
+        own.insert("CTX_HMAC_TIMESTAMP".into(), timestamp.to_string());
+        own.insert(
+            "CTX_HMAC_BODY".into(),
+            String::from_utf8_lossy(body).into_owned(),
+        );
+        let ctx = ctx.with(own);
+        let payload = env_with_context_no_errors(template, |v| ctx.get(&v.into()));
+        Bytes::from(payload.into_owned().into_bytes())
+    } else {
+        body.clone()
+    };
+
+    let signature = sign(
+        hmac_cfg.algorithm(),
+        hmac_cfg.secret().expose().as_bytes(),
+        &payload,
+    );
+    let signature = match hmac_cfg.encoding() {
+        HmacEncoding::Hex => hex::encode(signature),
+        HmacEncoding::Base64 => BASE64.encode(signature),
+    };
+    let signature = format!("{}{signature}", hmac_cfg.prefix().unwrap_or_default());
+    debug!(header = %hmac_cfg.header(), "hmac signature computed");
+
+    let headers = request.headers_mut();
+    if let Some(timestamp_header) = hmac_cfg.timestamp_header() {
+        let name =
+            HeaderName::from_bytes(timestamp_header.as_bytes()).expect(INVALID_HEADER_NAME_ERROR);
+        headers.insert(name, HeaderValue::from(timestamp));
+    }
+    let name =
+        HeaderName::from_bytes(hmac_cfg.header().as_bytes()).expect(INVALID_HEADER_NAME_ERROR);
+    let value =
+        HeaderValue::from_str(&signature).map_err(|cause| HmacAuthError::InvalidSignature {
+            header: hmac_cfg.header().into(),
+            cause,
+        })?;
+    headers.insert(name, value);
+
+    Ok(())
+}
+
+fn sign(algorithm: HmacAlgorithm, key: &[u8], payload: &[u8]) -> Vec<u8> {
+    const KEY_LENGTH_ERROR: &str = "HMAC accepts key of any size, looks like a BUG";
+    match algorithm {
+        HmacAlgorithm::Sha1 => {
+            let mut mac = Hmac::<sha1::Sha1>::new_from_slice(key).expect(KEY_LENGTH_ERROR);
+            mac.update(payload);
+            mac.finalize().into_bytes().to_vec()
+        }
+        HmacAlgorithm::Sha256 => {
+            let mut mac = Hmac::<sha2::Sha256>::new_from_slice(key).expect(KEY_LENGTH_ERROR);
+            mac.update(payload);
+            mac.finalize().into_bytes().to_vec()
+        }
+        HmacAlgorithm::Sha512 => {
+            let mut mac = Hmac::<sha2::Sha512>::new_from_slice(key).expect(KEY_LENGTH_ERROR);
+            mac.update(payload);
+            mac.finalize().into_bytes().to_vec()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::test_context::get_test_ctx;
+
+    fn test_request(body: &'static str) -> Request<Full<Bytes>> {
+        Request::builder()
+            .uri("https://example.com/webhook")
+            .body(Full::from(body))
+            .unwrap()
+    }
+
+    #[test]
+    fn rfc4231_test_case_2() {
+        // https://www.rfc-editor.org/rfc/rfc4231#section-4.3
+        let signature = sign(
+            HmacAlgorithm::Sha256,
+            b"Jefe",
+            b"what do ya want for nothing?",
+        );
+        assert_eq!(
+            hex::encode(signature),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn signs_raw_body_by_default() {
+        let cfg: HmacConfig =
+            serde_yaml_ng::from_str("header: X-Signature\nsecret: Jefe\nprefix: sha256=").unwrap();
+        let body = Bytes::from_static(b"what do ya want for nothing?");
+        let mut request = test_request("what do ya want for nothing?");
+
+        sign_request(&cfg, &mut request, &body, get_test_ctx()).unwrap();
+
+        assert_eq!(
+            request.headers().get("x-signature").unwrap(),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn signs_templated_payload_with_timestamp() {
+        let cfg: HmacConfig = serde_yaml_ng::from_str(
+            "header: X-Signature\nsecret: s\nencoding: base64\ntimestamp_header: X-Timestamp\npayload_template: \"${CTX_HMAC_TIMESTAMP}.${CTX_HMAC_BODY}\"",
+        )
+        .unwrap();
+        let body = Bytes::from_static(b"{}");
+        let mut request = test_request("{}");
+
+        sign_request(&cfg, &mut request, &body, get_test_ctx()).unwrap();
+
+        let timestamp = request
+            .headers()
+            .get("x-timestamp")
+            .unwrap()
+            .to_str()
+            .unwrap();
+        let expected = BASE64.encode(sign(
+            HmacAlgorithm::Sha256,
+            b"s",
+            format!("{timestamp}.{{}}").as_bytes(),
+        ));
+        assert_eq!(request.headers().get("x-signature").unwrap(), &expected);
+    }
+}
@@ -4,8 +4,16 @@ pub mod context;
 pub mod signal;
 
 mod aws_auth;
+mod body_parser;
+mod compression;
+mod condition;
 mod handler;
 mod health_check;
+mod hmac_auth;
+mod oauth2_auth;
+mod refreshing_cache;
+mod response_cache;
+mod response_compare;
 
 use cli::CliConfig;
 use config::{listener::ListenerConfig, AppConfig};
@@ -0,0 +1,316 @@
+use crate::{
+    config::{
+        listener::TlsConfig,
+        oauth2::{OAuth2ClientAuth, OAuth2Config},
+        target::HttpsClient,
+    },
+    refreshing_cache::{Expiring, RefreshingCache},
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    body::Bytes,
+    header::{HeaderValue, ACCEPT, AUTHORIZATION, CONTENT_TYPE},
+    Method, Request,
+};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock, RwLock},
+    time::Duration,
+};
+use tracing::debug;
+
+/// Lifetime of the token if the token endpoint doesn't report its `expires_in`
+const DEFAULT_TOKEN_TTL: Duration = Duration::from_secs(300);
+const UNABLE_TO_LOCK_TOKENS_ERROR: &str = "unable to lock oauth2 tokens cache, looks like a BUG";
+
+/// Token endpoint is queried with the timeout and TLS config of the target,
+/// so targets share the provider only if these settings are identical as well
+type ProviderKey = (OAuth2Config, Duration, TlsConfig);
+
+static TOKENS: LazyLock<RwLock<HashMap<ProviderKey, Arc<TokenProvider>>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum OAuth2Error {
+    #[error("failed to request OAuth2 token from `{token_url}`: {cause}")]
+    Request { token_url: String, cause: String },
+    #[error("OAuth2 token request to `{token_url}` has been timed out")]
+    Timeout { token_url: String },
+    #[error("OAuth2 token endpoint `{token_url}` responded with status {status}")]
+    Status { token_url: String, status: u16 },
+    #[error("invalid OAuth2 token response from `{token_url}`: {cause}")]
+    InvalidResponse {
+        token_url: String,
+        cause: serde_json::Error,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+}
+
+/// Client credentials grant request to the token endpoint
+struct TokenEndpoint {
+    cfg: OAuth2Config,
+    client: HttpsClient,
+    timeout: Duration,
+}
+
+/// Token endpoint and its cached token
+struct TokenProvider {
+    endpoint: TokenEndpoint,
+    token: RefreshingCache<String>,
+}
+
+/// Builds client credentials grant request (RFC 6749, section 4.4)
+fn token_request(cfg: &OAuth2Config) -> Request<Full<Bytes>> {
+    let mut form = form_urlencoded::Serializer::new(String::new());
+    form.append_pair("grant_type", "client_credentials");
+    if !cfg.scopes().is_empty() {
+        form.append_pair("scope", &cfg.scopes().join(" "));
+    }
+    if let Some(audience) = cfg.audience() {
+        form.append_pair("audience", audience);
+    }
+
+    let mut request = Request::builder()
+        .method(Method::POST)
+        .uri(cfg.token_url())
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .header(ACCEPT, "application/json");
+    match cfg.client_auth() {
+        OAuth2ClientAuth::Basic => {
+            // RFC 6749, section 2.3.1: both parts are form-urlencoded before encoding to base64
+            let credentials = format!(
+                "{}:{}",
+                form_urlencoded::byte_serialize(cfg.client_id().as_bytes()).collect::<String>(),
+                form_urlencoded::byte_serialize(cfg.client_secret().expose().as_bytes())
+                    .collect::<String>()
+            );
+            request = request.header(
+                AUTHORIZATION,
+                format!("Basic {}", BASE64.encode(credentials)),
+            );
+        }
+        OAuth2ClientAuth::Body => {
+            form.append_pair("client_id", cfg.client_id());
+            form.append_pair("client_secret", cfg.client_secret().expose());
+        }
+    }
+
+    request
+        .body(Full::from(form.finish()))
+        .expect("token URL is validated on load, looks like a BUG")
+}
+
+impl TokenEndpoint {
+    async fn request_token(&self) -> Result<Expiring<String>, OAuth2Error> {
+        let token_url = self.cfg.token_url().to_string();
+        debug!(%token_url, "requesting oauth2 token");
+
+        let response =
+            tokio::time::timeout(self.timeout, self.client.request(token_request(&self.cfg)))
+                .await
+                .map_err(|_| OAuth2Error::Timeout {
+                    token_url: token_url.clone(),
+                })?
+                .map_err(|e| OAuth2Error::Request {
+                    token_url: token_url.clone(),
+                    cause: e.to_string(),
+                })?;
+
+        let status = response.status();
+        let body = response
+            .into_body()
+            .collect()
+            .await
+            .map_err(|e| OAuth2Error::Request {
+                token_url: token_url.clone(),
+                cause: e.to_string(),
+            })?
+            .to_bytes();
+        if !status.is_success() {
+            return Err(OAuth2Error::Status {
+                token_url,
+                status: status.as_u16(),
+            });
+        }
+
+        let token: TokenResponse = serde_json::from_slice(&body)
+            .map_err(|cause| OAuth2Error::InvalidResponse { token_url, cause })?;
+        Ok(Expiring {
+            value: token.access_token,
+            validity: token.expires_in.map(Duration::from_secs),
+        })
+    }
+}
+
+impl TokenProvider {
+    /// Returns cached token or requests fresh one
+    async fn token(&self) -> Result<String, OAuth2Error> {
+        self.token
+            .get_or_refresh(|| self.endpoint.request_token())
+            .await
+    }
+}
+
+/// Returns cached token provider for the config and client settings, or creates new one.
+/// All targets with identical `oauth2` config, timeout and TLS config share the same provider (and token).
+fn provider_for(
+    cfg: &OAuth2Config,
+    client: &HttpsClient,
+    timeout: Duration,
+    tls_config: &TlsConfig,
+) -> Arc<TokenProvider> {
+    let key = (cfg.clone(), timeout, tls_config.clone());
+    if let Some(provider) = TOKENS.read().expect(UNABLE_TO_LOCK_TOKENS_ERROR).get(&key) {
+        return provider.clone();
+    }
+
+    let label = format!("{}@{}", cfg.client_id(), cfg.token_url());
+    let provider = Arc::new(TokenProvider {
+        endpoint: TokenEndpoint {
+            cfg: cfg.clone(),
+            client: client.clone(),
+            timeout,
+        },
+        token: RefreshingCache::new("oauth2 token", label, Some(DEFAULT_TOKEN_TTL)),
+    });
+
+    TOKENS
+        .write()
+        .expect(UNABLE_TO_LOCK_TOKENS_ERROR)
+        .entry(key)
+        .or_insert(provider)
+        .clone()
+}
+
+/// Obtains (cached or fresh) access token and puts it into `Authorization: Bearer` header of the request.
+pub(crate) async fn authorize_request(
+    cfg: &OAuth2Config,
+    request: &mut Request<Full<Bytes>>,
+    client: &HttpsClient,
+    timeout: Duration,
+    tls_config: &TlsConfig,
+) -> Result<(), OAuth2Error> {
+    let token = provider_for(cfg, client, timeout, tls_config)
+        .token()
+        .await?;
+    let value =
+        HeaderValue::from_str(&format!("Bearer {token}")).map_err(|e| OAuth2Error::Request {
+            token_url: cfg.token_url().to_string(),
+            cause: format!("token can't be used as header value: {e}"),
+        })?;
+    request.headers_mut().insert(AUTHORIZATION, value);
+
+    Ok(())
+}
+
+/// Drops the cached token if the target has rejected it with `401 Unauthorized`,
+/// so the next request obtains fresh token instead of reusing the revoked one until it expires.
+/// `authorization` is the header value of the rejected request,
+/// `timeout` and `tls_config` are the target's ones which the token has been obtained with.
+pub(crate) async fn invalidate_rejected_token(
+    cfg: &OAuth2Config,
+    timeout: Duration,
+    tls_config: &TlsConfig,
+    authorization: &HeaderValue,
+) {
+    let Some(token) = authorization
+        .to_str()
+        .ok()
+        .and_then(|v| v.strip_prefix("Bearer "))
+    else {
+        return;
+    };
+    let provider = TOKENS
+        .read()
+        .expect(UNABLE_TO_LOCK_TOKENS_ERROR)
+        .get(&(cfg.clone(), timeout, tls_config.clone()))
+        .cloned();
+    if let Some(provider) = provider {
+        debug!(token_url = %cfg.token_url(), "oauth2 token is rejected by the target");
+        provider.token.invalidate(&token.to_string()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{listener::TlsVerifyConfig, target::TargetConfig};
+
+    fn config(yaml: &str) -> OAuth2Config {
+        serde_yaml_ng::from_str(yaml).unwrap()
+    }
+
+    #[tokio::test]
+    async fn token_request_with_basic_auth() {
+        let cfg = config(
+            "token_url: https://auth.example.com/token\nclient_id: my id\nclient_secret: p@ss\nscopes: [read, write]\naudience: api",
+        );
+        let request = token_request(&cfg);
+
+        assert_eq!(request.method(), Method::POST);
+        assert_eq!(request.uri(), "https://auth.example.com/token");
+        assert_eq!(
+            request.headers().get(AUTHORIZATION).unwrap(),
+            &format!("Basic {}", BASE64.encode("my+id:p%40ss"))
+        );
+        let body = request.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            body,
+            "grant_type=client_credentials&scope=read+write&audience=api"
+        );
+    }
+
+    #[tokio::test]
+    async fn token_request_with_body_auth() {
+        let cfg = config(
+            "token_url: https://auth.example.com/token\nclient_id: id\nclient_secret: secret\nclient_auth: body",
+        );
+        let request = token_request(&cfg);
+
+        assert!(request.headers().get(AUTHORIZATION).is_none());
+        let body = request.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            body,
+            "grant_type=client_credentials&client_id=id&client_secret=secret"
+        );
+    }
+
+    #[test]
+    fn providers_are_shared_by_identical_client_settings() {
+        let cfg = config(
+            "token_url: https://auth.example.com/token\nclient_id: shared\nclient_secret: secret",
+        );
+        let target: &'static TargetConfig = Box::leak(Box::new(
+            serde_yaml_ng::from_str("url: https://api.example.com/").unwrap(),
+        ));
+        let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+        let verified: &'static TlsConfig = Box::leak(Box::default());
+        let unverified = TlsConfig {
+            verify: TlsVerifyConfig::No,
+            ca: None,
+        };
+        let client = target.https_client(verified);
+        let timeout = Duration::from_secs(5);
+
+        let provider = provider_for(&cfg, &client, timeout, verified);
+        assert!(Arc::ptr_eq(
+            &provider,
+            &provider_for(&cfg, &client, timeout, verified)
+        ));
+        assert!(!Arc::ptr_eq(
+            &provider,
+            &provider_for(&cfg, &client, timeout, &unverified)
+        ));
+        assert!(!Arc::ptr_eq(
+            &provider,
+            &provider_for(&cfg, &client, Duration::from_secs(10), verified)
+        ));
+    }
+}
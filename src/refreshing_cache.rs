@@ -0,0 +1,301 @@
+use std::{
+    fmt,
+    future::Future,
+    time::{Duration, SystemTime},
+};
+use tokio::sync::RwLock;
+use tracing::{debug, warn};
+
+/// Value obtained from its source with optional validity window
+#[derive(Debug, Clone)]
+pub(crate) struct Expiring<T> {
+    pub(crate) value: T,
+    pub(crate) validity: Option<Duration>,
+}
+
+#[derive(Debug)]
+struct CachedEntry<T> {
+    value: T,
+    refresh_at: Option<SystemTime>,
+    expires_at: Option<SystemTime>,
+}
+
+/// Caches a single value like credentials or access token, refreshing it
+/// proactively at half of the value's remaining validity window
+/// instead of waiting until it's about to expire.
+/// Value without validity is cached for `default_ttl`, or forever if it isn't set.
+pub(crate) struct RefreshingCache<T> {
+    cache: RwLock<Option<CachedEntry<T>>>,
+    kind: &'static str,
+    label: String,
+    default_ttl: Option<Duration>,
+}
+
+impl<T> fmt::Debug for RefreshingCache<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RefreshingCache")
+            .field("kind", &self.kind)
+            .field("label", &self.label)
+            .finish()
+    }
+}
+
+impl<T: Clone> RefreshingCache<T> {
+    /// `kind` and `label` are used in logs only
+    pub(crate) fn new(
+        kind: &'static str,
+        label: impl Into<String>,
+        default_ttl: Option<Duration>,
+    ) -> Self {
+        Self {
+            cache: RwLock::new(None),
+            kind,
+            label: label.into(),
+            default_ttl,
+        }
+    }
+
+    /// Returns cached value or fetches fresh one if it's time to refresh.
+    /// If refresh fails, cached value is returned until it expires.
+    pub(crate) async fn get_or_refresh<F, Fut, E>(&self, fetch: F) -> Result<T, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Expiring<T>, E>>,
+        E: fmt::Display,
+    {
+        let now = SystemTime::now();
+        {
+            let cache = self.cache.read().await;
+            if let Some(entry) = cache.as_ref() {
+                if entry.refresh_at.is_none_or(|refresh_at| now < refresh_at) {
+                    return Ok(entry.value.clone());
+                }
+            }
+        }
+
+        let mut cache = self.cache.write().await;
+        // Double-check: another task may have refreshed while we waited for the write lock.
+        let now = SystemTime::now();
+        if let Some(entry) = cache.as_ref() {
+            if entry.refresh_at.is_none_or(|refresh_at| now < refresh_at) {
+                return Ok(entry.value.clone());
+            }
+        }
+
+        match fetch().await {
+            Ok(fetched) => {
+                let now = SystemTime::now();
+                let validity = fetched.validity.or(self.default_ttl);
+                let expires_at = validity.map(|validity| now + validity);
+                let refresh_at = validity.map(|validity| now + validity / 2);
+                debug!(label = %self.label, ?expires_at, ?refresh_at, "{} refreshed", self.kind);
+                *cache = Some(CachedEntry {
+                    value: fetched.value.clone(),
+                    refresh_at,
+                    expires_at,
+                });
+                Ok(fetched.value)
+            }
+            Err(err) => {
+                if let Some(entry) = cache.as_ref() {
+                    if entry.expires_at.is_none_or(|expires_at| now < expires_at) {
+                        warn!(
+                            label = %self.label,
+                            error = %err,
+                            "failed to refresh {}, serving stale cached value",
+                            self.kind
+                        );
+                        return Ok(entry.value.clone());
+                    }
+                }
+                Err(err)
+            }
+        }
+    }
+}
+
+impl<T: PartialEq> RefreshingCache<T> {
+    /// Drops cached value if it's still the given one, so the next call fetches fresh value.
+    /// Value which has been refreshed by another task in the meantime is kept.
+    pub(crate) async fn invalidate(&self, value: &T) {
+        let mut cache = self.cache.write().await;
+        if cache.as_ref().is_some_and(|entry| entry.value == *value) {
+            debug!(label = %self.label, "{} invalidated", self.kind);
+            *cache = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use tokio::sync::Mutex as AsyncMutex;
+
+    #[derive(Debug, Clone)]
+    enum FakeResponse {
+        Value { validity: Option<Duration> },
+        Err,
+    }
+
+    /// Source of values `value-<call number>` which responds according to the script,
+    /// the last response is repeated
+    #[derive(Debug)]
+    struct FakeSource {
+        calls: AtomicUsize,
+        script: AsyncMutex<Vec<FakeResponse>>,
+    }
+
+    impl FakeSource {
+        fn new(script: Vec<FakeResponse>) -> Arc<Self> {
+            Arc::new(Self {
+                calls: AtomicUsize::new(0),
+                script: AsyncMutex::new(script),
+            })
+        }
+
+        fn call_count(&self) -> usize {
+            self.calls.load(Ordering::SeqCst)
+        }
+
+        async fn fetch(&self) -> Result<Expiring<String>, String> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst);
+            let mut script = self.script.lock().await;
+            let response = if script.len() > 1 {
+                script.remove(0)
+            } else {
+                script[0].clone()
+            };
+            match response {
+                FakeResponse::Value { validity } => Ok(Expiring {
+                    value: format!("value-{call}"),
+                    validity,
+                }),
+                FakeResponse::Err => Err("fake failure".into()),
+            }
+        }
+    }
+
+    async fn get(
+        cache: &RefreshingCache<String>,
+        source: &Arc<FakeSource>,
+    ) -> Result<String, String> {
+        cache.get_or_refresh(|| source.fetch()).await
+    }
+
+    fn valid_for(millis: u64) -> FakeResponse {
+        FakeResponse::Value {
+            validity: Some(Duration::from_millis(millis)),
+        }
+    }
+
+    #[tokio::test]
+    async fn caches_until_half_life_then_refreshes() {
+        let source = FakeSource::new(vec![valid_for(200)]);
+        let cache = RefreshingCache::new("test value", "test", None);
+
+        assert_eq!(get(&cache, &source).await.unwrap(), "value-0");
+
+        // well before half-life (100ms): still cached
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        assert_eq!(get(&cache, &source).await.unwrap(), "value-0");
+        assert_eq!(source.call_count(), 1);
+
+        // past half-life: refreshes
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        assert_eq!(get(&cache, &source).await.unwrap(), "value-1");
+        assert_eq!(source.call_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn no_validity_is_cached_forever_without_default_ttl() {
+        let source = FakeSource::new(vec![FakeResponse::Value { validity: None }]);
+        let cache = RefreshingCache::new("test value", "test", None);
+
+        for _ in 0..5 {
+            get(&cache, &source).await.unwrap();
+        }
+        assert_eq!(source.call_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn no_validity_is_cached_for_default_ttl() {
+        let source = FakeSource::new(vec![FakeResponse::Value { validity: None }]);
+        let cache = RefreshingCache::new("test value", "test", Some(Duration::from_millis(60)));
+
+        assert_eq!(get(&cache, &source).await.unwrap(), "value-0");
+        assert_eq!(get(&cache, &source).await.unwrap(), "value-0");
+
+        // past half of default TTL
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        assert_eq!(get(&cache, &source).await.unwrap(), "value-1");
+    }
+
+    #[tokio::test]
+    async fn concurrent_refreshes_single_flight() {
+        let source = FakeSource::new(vec![valid_for(1)]);
+        let cache = Arc::new(RefreshingCache::new("test value", "test", None));
+
+        get(&cache, &source).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await; // past half-life
+
+        let tasks: Vec<_> = (0..20)
+            .map(|_| {
+                let (cache, source) = (cache.clone(), source.clone());
+                tokio::spawn(async move { get(&cache, &source).await.unwrap() })
+            })
+            .collect();
+        for t in tasks {
+            t.await.unwrap();
+        }
+
+        assert_eq!(
+            source.call_count(),
+            2,
+            "20 concurrent refreshers should collapse into a single real fetch"
+        );
+    }
+
+    #[tokio::test]
+    async fn serves_stale_value_when_refresh_fails() {
+        let source = FakeSource::new(vec![valid_for(100), FakeResponse::Err]);
+        let cache = RefreshingCache::new("test value", "test", None);
+
+        let first = get(&cache, &source).await.unwrap();
+        // past half-life (50ms) but before real expiry (100ms)
+        tokio::time::sleep(Duration::from_millis(70)).await;
+        let second = get(&cache, &source).await.unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(source.call_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn errors_once_stale_value_truly_expires() {
+        let source = FakeSource::new(vec![valid_for(30), FakeResponse::Err]);
+        let cache = RefreshingCache::new("test value", "test", None);
+
+        get(&cache, &source).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(60)).await; // past real expiry too
+
+        assert!(get(&cache, &source).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn invalidates_the_given_value_only() {
+        let source = FakeSource::new(vec![valid_for(60_000)]);
+        let cache = RefreshingCache::new("test value", "test", None);
+
+        assert_eq!(get(&cache, &source).await.unwrap(), "value-0");
+        cache.invalidate(&"value-0".to_string()).await;
+        assert_eq!(get(&cache, &source).await.unwrap(), "value-1");
+
+        // Outdated value doesn't drop the fresh one
+        cache.invalidate(&"value-0".to_string()).await;
+        assert_eq!(get(&cache, &source).await.unwrap(), "value-1");
+        assert_eq!(source.call_count(), 2);
+    }
+}
@@ -0,0 +1,614 @@
+use crate::{
+    config::{cache::CacheConfig, listener::ListenerConfig},
+    context::Context,
+};
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    body::Bytes,
+    header::{HeaderName, HeaderValue, AGE, AUTHORIZATION, CACHE_CONTROL, SET_COOKIE, VARY},
+    http::request::Parts,
+    HeaderMap, Method, Response, StatusCode,
+};
+use shellexpand::env_with_context_no_errors;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, LazyLock, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+use tracing::debug;
+
+const X_CACHE: HeaderName = HeaderName::from_static("x-cache");
+const UNABLE_TO_LOCK_CACHE_ERROR: &str = "unable to lock response cache, looks like a BUG";
+
+static CACHES: LazyLock<RwLock<HashMap<String, Arc<ResponseCache>>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// Value of the `x-cache` header added to responses of listeners with cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CacheStatus {
+    Hit,
+    Miss,
+    Stale,
+    Bypass,
+}
+
+impl CacheStatus {
+    fn as_header_value(self) -> HeaderValue {
+        HeaderValue::from_static(match self {
+            CacheStatus::Hit => "HIT",
+            CacheStatus::Miss => "MISS",
+            CacheStatus::Stale => "STALE",
+            CacheStatus::Bypass => "BYPASS",
+        })
+    }
+}
+
+/// Cache-related directives of the `Cache-Control` header
+#[derive(Debug, Default, PartialEq, Eq)]
+struct CacheControl {
+    no_store: bool,
+    no_cache: bool,
+    private: bool,
+    public: bool,
+    max_age: Option<Duration>,
+    s_maxage: Option<Duration>,
+    stale_if_error: Option<Duration>,
+}
+
+impl CacheControl {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let mut cc = Self::default();
+        let directives = headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','));
+
+        for directive in directives {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name, Some(value.trim().trim_matches('"'))),
+                None => (directive, None),
+            };
+            let seconds = value
+                .and_then(|v| v.parse::<u64>().ok())
+                .map(Duration::from_secs);
+            match name.trim().to_lowercase().as_str() {
+                "no-store" => cc.no_store = true,
+                "no-cache" => cc.no_cache = true,
+                "private" => cc.private = true,
+                "public" => cc.public = true,
+                "max-age" => cc.max_age = seconds,
+                "s-maxage" => cc.s_maxage = seconds,
+                "stale-if-error" => cc.stale_if_error = seconds,
+                _ => {}
+            }
+        }
+
+        cc
+    }
+
+    /// Returns `true` if response to the request with `Authorization` may be stored and reused, RFC 9111, section 3.5
+    fn is_shared(&self) -> bool {
+        self.public || self.s_maxage.is_some()
+    }
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    size: usize,
+    stored_at: Instant,
+    fresh_until: Instant,
+    stale_until: Instant,
+    shared: bool,
+    used: u64,
+}
+
+impl CacheEntry {
+    fn to_response(&self, now: Instant, status: CacheStatus) -> Response<Full<Bytes>> {
+        let mut resp = Response::new(Full::from(self.body.clone()));
+        *resp.status_mut() = self.status;
+        *resp.headers_mut() = self.headers.clone();
+        let age = now.saturating_duration_since(self.stored_at).as_secs();
+        resp.headers_mut().insert(AGE, HeaderValue::from(age));
+        resp.headers_mut().insert(X_CACHE, status.as_header_value());
+        resp
+    }
+}
+
+/// Cached entries and their usage order, the least recently used entry is the first one in `lru`
+#[derive(Debug, Default)]
+struct CacheStore {
+    entries: HashMap<String, CacheEntry>,
+    lru: BTreeMap<u64, String>,
+    tick: u64,
+    bytes: usize,
+}
+
+impl CacheStore {
+    fn touch(&mut self, key: &str) {
+        self.tick += 1;
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.lru.remove(&entry.used);
+            entry.used = self.tick;
+            self.lru.insert(self.tick, key.to_string());
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.lru.remove(&entry.used);
+            self.bytes -= entry.size;
+        }
+    }
+
+    fn evict_lru(&mut self) {
+        if let Some((_, key)) = self.lru.pop_first() {
+            debug!(%key, "evict cached response");
+            if let Some(entry) = self.entries.remove(&key) {
+                self.bytes -= entry.size;
+            }
+        }
+    }
+}
+
+/// Per-listener in-memory LRU cache of the final responses
+#[derive(Debug)]
+pub(crate) struct ResponseCache {
+    cfg: &'static CacheConfig,
+    store: Mutex<CacheStore>,
+}
+
+/// Result of the cache lookup, it's used to complete request processing
+/// after the response has been produced by the strategy.
+pub(crate) enum CacheLookup {
+    /// Listener has no cache
+    Disabled,
+    /// Request can't be served from cache
+    Bypass,
+    /// Fresh response has been found
+    Hit(Response<Full<Bytes>>),
+    /// Nothing usable is in the cache, response should be stored under the key,
+    /// flag is `true` if request has `Authorization` header
+    Miss(Arc<ResponseCache>, String, bool),
+    /// Expired response which may be returned if targets fail
+    Stale(Arc<ResponseCache>, String, bool, Response<Full<Bytes>>),
+}
+
+impl ResponseCache {
+    fn new(cfg: &'static CacheConfig) -> Self {
+        Self {
+            cfg,
+            store: Mutex::new(CacheStore::default()),
+        }
+    }
+
+    /// Returns cached response and whether it's still fresh.
+    /// Expired entries are returned only within the stale-if-error window,
+    /// requests with `Authorization` get shared entries only.
+    fn get(
+        &self,
+        key: &str,
+        now: Instant,
+        authorized: bool,
+    ) -> Option<(Response<Full<Bytes>>, bool)> {
+        let mut store = self.store.lock().expect(UNABLE_TO_LOCK_CACHE_ERROR);
+        let entry = store.entries.get(key)?;
+        if authorized && !entry.shared {
+            debug!(%key, "cached response isn't shared with authorized requests");
+            return None;
+        }
+        let fresh = now < entry.fresh_until;
+        if !fresh && now >= entry.stale_until {
+            debug!(%key, "cached response has expired");
+            store.remove(key);
+            return None;
+        }
+        let status = if fresh {
+            CacheStatus::Hit
+        } else {
+            CacheStatus::Stale
+        };
+        let resp = entry.to_response(now, status);
+        store.touch(key);
+
+        Some((resp, fresh))
+    }
+
+    /// Stores response if it's allowed by its status, headers and size limits.
+    /// Responses with `Set-Cookie` or `Vary` are never stored, since the cache key doesn't cover
+    /// the request headers they depend on. Responses to requests with `Authorization`
+    /// are stored only if they're explicitly shared by `public` or `s-maxage`.
+    fn put(
+        &self,
+        key: String,
+        status: StatusCode,
+        headers: &HeaderMap,
+        body: &Bytes,
+        now: Instant,
+        authorized: bool,
+    ) {
+        if !status.is_success() {
+            return;
+        }
+        if headers.contains_key(SET_COOKIE) {
+            debug!(%key, "response isn't cacheable because of set-cookie");
+            return;
+        }
+        if headers.contains_key(VARY) {
+            debug!(%key, "response isn't cacheable because of vary");
+            return;
+        }
+        let cc = CacheControl::from_headers(headers);
+        if cc.no_store || cc.no_cache || cc.private {
+            debug!(%key, "response isn't cacheable because of cache-control");
+            return;
+        }
+        if authorized && !cc.is_shared() {
+            debug!(%key, "response to authorized request isn't cacheable without public or s-maxage");
+            return;
+        }
+        let ttl = cc.s_maxage.or(cc.max_age).unwrap_or(self.cfg.ttl());
+        if ttl.is_zero() {
+            return;
+        }
+        let stale = cc
+            .stale_if_error
+            .or(self.cfg.stale_if_error())
+            .unwrap_or_default();
+
+        let size = key.len()
+            + body.len()
+            + headers
+                .iter()
+                .map(|(n, v)| n.as_str().len() + v.len())
+                .sum::<usize>();
+        if self.cfg.max_bytes().is_some_and(|max| size > max) {
+            debug!(%key, %size, "response is too large to cache");
+            return;
+        }
+
+        let mut store = self.store.lock().expect(UNABLE_TO_LOCK_CACHE_ERROR);
+        store.remove(&key);
+        while store.entries.len() >= self.cfg.max_entries()
+            || self
+                .cfg
+                .max_bytes()
+                .is_some_and(|max| store.bytes + size > max)
+        {
+            store.evict_lru();
+        }
+
+        let mut headers = headers.clone();
+        headers.remove(X_CACHE);
+        headers.remove(AGE);
+        store.tick += 1;
+        let used = store.tick;
+        store.lru.insert(used, key.clone());
+        store.bytes += size;
+        store.entries.insert(
+            key,
+            CacheEntry {
+                status,
+                headers,
+                body: body.clone(),
+                size,
+                stored_at: now,
+                fresh_until: now + ttl,
+                stale_until: now + ttl + stale,
+                shared: cc.is_shared(),
+                used,
+            },
+        );
+    }
+}
+
+/// Returns cache of the listener, creates it on first use
+fn cache_for(listener: &'static ListenerConfig, cfg: &'static CacheConfig) -> Arc<ResponseCache> {
+    let id = listener.id();
+    if let Some(cache) = CACHES.read().expect(UNABLE_TO_LOCK_CACHE_ERROR).get(&id) {
+        return cache.clone();
+    }
+
+    CACHES
+        .write()
+        .expect(UNABLE_TO_LOCK_CACHE_ERROR)
+        .entry(id)
+        .or_insert_with(|| Arc::new(ResponseCache::new(cfg)))
+        .clone()
+}
+
+/// Looks up the request in the listener's cache.
+/// Only `GET` and `HEAD` requests are cached, request's `Cache-Control: no-store` bypasses cache,
+/// and `no-cache` forces targets querying but the fresh response is stored.
+/// Requests with `Authorization` use shared responses only, see `ResponseCache::put`.
+pub(crate) fn lookup(listener: &'static ListenerConfig, req: &Parts, ctx: &Context) -> CacheLookup {
+    let Some(cfg) = listener.cache() else {
+        return CacheLookup::Disabled;
+    };
+    if req.method != Method::GET && req.method != Method::HEAD {
+        return CacheLookup::Bypass;
+    }
+    let cc = CacheControl::from_headers(&req.headers);
+    if cc.no_store {
+        return CacheLookup::Bypass;
+    }
+
+    let authorized = req.headers.contains_key(AUTHORIZATION);
+
+    let cache = cache_for(listener, cfg);
+    let key = env_with_context_no_errors(cfg.key(), |v| ctx.get(&v.into())).into_owned();
+    if cc.no_cache {
+        return CacheLookup::Miss(cache, key, authorized);
+    }
+
+    match cache.get(&key, Instant::now(), authorized) {
+        Some((resp, true)) => CacheLookup::Hit(resp),
+        Some((resp, false)) => CacheLookup::Stale(cache, key, authorized, resp),
+        None => CacheLookup::Miss(cache, key, authorized),
+    }
+}
+
+impl CacheLookup {
+    /// Stores the response produced by the strategy or replaces it with stale one if targets have failed,
+    /// and marks the response with `x-cache` header.
+    pub(crate) async fn complete(self, resp: Response<Full<Bytes>>) -> Response<Full<Bytes>> {
+        let (cache, key, authorized, stale) = match self {
+            CacheLookup::Disabled => return resp,
+            CacheLookup::Hit(resp) => return resp,
+            CacheLookup::Bypass => {
+                let mut resp = resp;
+                resp.headers_mut()
+                    .insert(X_CACHE, CacheStatus::Bypass.as_header_value());
+                return resp;
+            }
+            CacheLookup::Miss(cache, key, authorized) => (cache, key, authorized, None),
+            CacheLookup::Stale(cache, key, authorized, stale) => {
+                (cache, key, authorized, Some(stale))
+            }
+        };
+
+        if resp.status().is_server_error() {
+            if let Some(stale) = stale {
+                debug!(%key, status = %resp.status(), "serving stale response");
+                return stale;
+            }
+        }
+
+        let (mut parts, body) = resp.into_parts();
+        let body = body
+            .collect()
+            .await
+            .expect("collecting of in-memory body can't fail")
+            .to_bytes();
+        cache.put(
+            key,
+            parts.status,
+            &parts.headers,
+            &body,
+            Instant::now(),
+            authorized,
+        );
+        parts
+            .headers
+            .insert(X_CACHE, CacheStatus::Miss.as_header_value());
+
+        Response::from_parts(parts, Full::from(body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(yaml: &str) -> &'static CacheConfig {
+        Box::leak(Box::new(serde_yaml_ng::from_str(yaml).unwrap()))
+    }
+
+    fn headers(cache_control: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(cc) = cache_control {
+            headers.insert(CACHE_CONTROL, HeaderValue::from_str(cc).unwrap());
+        }
+        headers
+    }
+
+    fn put(cache: &ResponseCache, key: &str, body: &'static str, now: Instant) {
+        cache.put(
+            key.into(),
+            StatusCode::OK,
+            &HeaderMap::new(),
+            &Bytes::from_static(body.as_bytes()),
+            now,
+            false,
+        );
+    }
+
+    #[test]
+    fn parses_cache_control() {
+        let cc = CacheControl::from_headers(&headers(Some(
+            "public, max-age=60, s-maxage=\"120\", stale-if-error=30, No-Cache",
+        )));
+        assert_eq!(
+            cc,
+            CacheControl {
+                no_store: false,
+                no_cache: true,
+                private: false,
+                public: true,
+                max_age: Some(Duration::from_secs(60)),
+                s_maxage: Some(Duration::from_secs(120)),
+                stale_if_error: Some(Duration::from_secs(30)),
+            }
+        );
+        assert_eq!(
+            CacheControl::from_headers(&headers(None)),
+            CacheControl::default()
+        );
+    }
+
+    #[test]
+    fn expires_after_ttl_and_stale_window() {
+        let cache = ResponseCache::new(config("ttl: 10s\nstale_if_error: 5s"));
+        let now = Instant::now();
+        put(&cache, "key", "body", now);
+
+        let (resp, fresh) = cache
+            .get("key", now + Duration::from_secs(9), false)
+            .unwrap();
+        assert!(fresh);
+        assert_eq!(resp.headers()[X_CACHE], "HIT");
+        assert_eq!(resp.headers()[AGE], "9");
+
+        let (resp, fresh) = cache
+            .get("key", now + Duration::from_secs(12), false)
+            .unwrap();
+        assert!(!fresh);
+        assert_eq!(resp.headers()[X_CACHE], "STALE");
+
+        assert!(cache
+            .get("key", now + Duration::from_secs(15), false)
+            .is_none());
+        assert!(cache.store.lock().unwrap().entries.is_empty());
+    }
+
+    #[test]
+    fn respects_response_cache_control() {
+        let cache = ResponseCache::new(config("ttl: 10s"));
+        let now = Instant::now();
+        let body = Bytes::from_static(b"body");
+
+        for cc in ["no-store", "private", "no-cache", "max-age=0"] {
+            cache.put(
+                cc.into(),
+                StatusCode::OK,
+                &headers(Some(cc)),
+                &body,
+                now,
+                false,
+            );
+            assert!(
+                cache.get(cc, now, false).is_none(),
+                "`{cc}` should not be cached"
+            );
+        }
+
+        cache.put(
+            "max-age".into(),
+            StatusCode::OK,
+            &headers(Some("max-age=100")),
+            &body,
+            now,
+            false,
+        );
+        assert!(cache
+            .get("max-age", now + Duration::from_secs(50), false)
+            .is_some());
+
+        cache.put(
+            "error".into(),
+            StatusCode::BAD_GATEWAY,
+            &headers(None),
+            &body,
+            now,
+            false,
+        );
+        assert!(cache.get("error", now, false).is_none());
+
+        let mut with_cookie = headers(Some("public, max-age=100"));
+        with_cookie.insert(SET_COOKIE, HeaderValue::from_static("session=abc"));
+        cache.put(
+            "cookie".into(),
+            StatusCode::OK,
+            &with_cookie,
+            &body,
+            now,
+            false,
+        );
+        assert!(cache.get("cookie", now, false).is_none());
+
+        let mut with_vary = headers(Some("public, max-age=100"));
+        with_vary.insert(VARY, HeaderValue::from_static("accept-language"));
+        cache.put("vary".into(), StatusCode::OK, &with_vary, &body, now, false);
+        assert!(cache.get("vary", now, false).is_none());
+    }
+
+    #[test]
+    fn authorized_requests() {
+        let cache = ResponseCache::new(config("ttl: 10s"));
+        let now = Instant::now();
+        let body = Bytes::from_static(b"body");
+
+        // Only shared responses to authorized requests are stored
+        cache.put(
+            "private".into(),
+            StatusCode::OK,
+            &headers(None),
+            &body,
+            now,
+            true,
+        );
+        assert!(cache.get("private", now, false).is_none());
+        for cc in ["public", "s-maxage=100"] {
+            cache.put(
+                cc.into(),
+                StatusCode::OK,
+                &headers(Some(cc)),
+                &body,
+                now,
+                true,
+            );
+            assert!(
+                cache.get(cc, now, true).is_some(),
+                "`{cc}` should be cached"
+            );
+        }
+
+        // Only shared responses are reused by authorized requests
+        cache.put(
+            "anonymous".into(),
+            StatusCode::OK,
+            &headers(None),
+            &body,
+            now,
+            false,
+        );
+        assert!(cache.get("anonymous", now, true).is_none());
+        assert!(cache.get("anonymous", now, false).is_some());
+    }
+
+    #[test]
+    fn evicts_least_recently_used_entries() {
+        let cache = ResponseCache::new(config("max_entries: 2"));
+        let now = Instant::now();
+        put(&cache, "one", "1", now);
+        put(&cache, "two", "2", now);
+        // Make `one` most recently used
+        cache.get("one", now, false).unwrap();
+        put(&cache, "three", "3", now);
+
+        assert!(cache.get("one", now, false).is_some());
+        assert!(cache.get("two", now, false).is_none());
+        assert!(cache.get("three", now, false).is_some());
+    }
+
+    #[test]
+    fn evicts_entries_to_fit_max_bytes() {
+        let cache = ResponseCache::new(config("max_bytes: 20"));
+        let now = Instant::now();
+        put(&cache, "one", "1234567", now);
+        put(&cache, "two", "1234567", now);
+        assert_eq!(cache.store.lock().unwrap().bytes, 20);
+
+        put(&cache, "three", "123", now);
+        assert!(cache.get("one", now, false).is_none());
+        assert!(cache.get("two", now, false).is_some());
+        assert!(cache.get("three", now, false).is_some());
+
+        put(&cache, "huge", "123456789012345678901234567890", now);
+        assert!(cache.get("huge", now, false).is_none());
+        assert!(cache.get("two", now, false).is_some());
+    }
+}
@@ -0,0 +1,400 @@
+use crate::{
+    body_parser::{parse_body, ParsedBody},
+    config::{
+        compare::{format_json_path, CompareConfig, JsonPathItem},
+        listener::ListenerConfig,
+        response::response_body,
+    },
+    handler::ResponsesMap,
+};
+use http_body_util::Full;
+use hyper::{body::Bytes, HeaderMap, Response};
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, LazyLock, RwLock,
+    },
+};
+use tracing::{debug, info};
+use uuid::Uuid;
+
+/// Max number of body differences in a single diff, the rest of them are omitted
+const MAX_BODY_DIFFERENCES: usize = 20;
+const UNABLE_TO_LOCK_STATS_ERROR: &str = "unable to lock compare statistics, looks like a BUG";
+
+static STATS: LazyLock<RwLock<HashMap<String, Arc<CompareStats>>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// Counters of the listener's comparisons, each candidate's response is counted separately
+#[derive(Debug, Default)]
+pub(crate) struct CompareStats {
+    /// Number of compared pairs of responses
+    compared: AtomicU64,
+    /// Number of pairs without any difference
+    matched: AtomicU64,
+    status_mismatches: AtomicU64,
+    header_mismatches: AtomicU64,
+    body_mismatches: AtomicU64,
+    /// Number of pairs which aren't compared since some of responses is absent
+    missing: AtomicU64,
+}
+
+/// Point-in-time values of [`CompareStats`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub(crate) struct CompareStatsSnapshot {
+    pub(crate) compared: u64,
+    pub(crate) matched: u64,
+    pub(crate) status_mismatches: u64,
+    pub(crate) header_mismatches: u64,
+    pub(crate) body_mismatches: u64,
+    pub(crate) missing: u64,
+}
+
+impl CompareStats {
+    fn count(&self, diff: &ResponseDiff) {
+        self.compared.fetch_add(1, Ordering::Relaxed);
+        if diff.is_empty() {
+            self.matched.fetch_add(1, Ordering::Relaxed);
+        }
+        if diff.status.is_some() {
+            self.status_mismatches.fetch_add(1, Ordering::Relaxed);
+        }
+        if !diff.headers.is_empty() {
+            self.header_mismatches.fetch_add(1, Ordering::Relaxed);
+        }
+        if !diff.body.is_empty() {
+            self.body_mismatches.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> CompareStatsSnapshot {
+        CompareStatsSnapshot {
+            compared: self.compared.load(Ordering::Relaxed),
+            matched: self.matched.load(Ordering::Relaxed),
+            status_mismatches: self.status_mismatches.load(Ordering::Relaxed),
+            header_mismatches: self.header_mismatches.load(Ordering::Relaxed),
+            body_mismatches: self.body_mismatches.load(Ordering::Relaxed),
+            missing: self.missing.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Returns comparison statistics of the listener, creates it on first use
+pub(crate) fn stats_for(listener_id: &str) -> Arc<CompareStats> {
+    if let Some(stats) = STATS
+        .read()
+        .expect(UNABLE_TO_LOCK_STATS_ERROR)
+        .get(listener_id)
+    {
+        return stats.clone();
+    }
+
+    STATS
+        .write()
+        .expect(UNABLE_TO_LOCK_STATS_ERROR)
+        .entry(listener_id.into())
+        .or_default()
+        .clone()
+}
+
+/// Owned copy of the target's response to compare in background
+#[derive(Debug)]
+struct ComparedResponse {
+    status: u16,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl From<&Response<Full<Bytes>>> for ComparedResponse {
+    fn from(resp: &Response<Full<Bytes>>) -> Self {
+        Self {
+            status: resp.status().as_u16(),
+            headers: resp.headers().clone(),
+            body: response_body(resp),
+        }
+    }
+}
+
+/// Pair of different values, `null` means the value is absent
+#[derive(Debug, PartialEq, Serialize)]
+struct ValueDiff<T> {
+    baseline: T,
+    candidate: T,
+}
+
+/// Structured difference between baseline and candidate responses
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct ResponseDiff {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<ValueDiff<u16>>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    headers: BTreeMap<String, ValueDiff<Option<String>>>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    body: BTreeMap<String, ValueDiff<Option<JsonValue>>>,
+}
+
+impl ResponseDiff {
+    fn is_empty(&self) -> bool {
+        self.status.is_none() && self.headers.is_empty() && self.body.is_empty()
+    }
+}
+
+/// Returns all values of the header joined by `, `
+fn header_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    let values: Vec<String> = headers
+        .get_all(name)
+        .iter()
+        .map(|v| String::from_utf8_lossy(v.as_bytes()).into_owned())
+        .collect();
+    (!values.is_empty()).then(|| values.join(", "))
+}
+
+/// Collects differences of two JSON values recursively, except ignored paths
+fn diff_json(
+    cfg: &CompareConfig,
+    path: &mut Vec<JsonPathItem>,
+    baseline: Option<&JsonValue>,
+    candidate: Option<&JsonValue>,
+    diff: &mut BTreeMap<String, ValueDiff<Option<JsonValue>>>,
+) {
+    if diff.len() >= MAX_BODY_DIFFERENCES || cfg.is_ignored_json_path(path) {
+        return;
+    }
+    match (baseline, candidate) {
+        (Some(JsonValue::Object(baseline)), Some(JsonValue::Object(candidate))) => {
+            let keys: BTreeSet<&String> = baseline.keys().chain(candidate.keys()).collect();
+            for key in keys {
+                path.push(JsonPathItem::Key(key.clone()));
+                diff_json(cfg, path, baseline.get(key), candidate.get(key), diff);
+                path.pop();
+            }
+        }
+        (Some(JsonValue::Array(baseline)), Some(JsonValue::Array(candidate))) => {
+            for index in 0..baseline.len().max(candidate.len()) {
+                path.push(JsonPathItem::Index(index));
+                diff_json(cfg, path, baseline.get(index), candidate.get(index), diff);
+                path.pop();
+            }
+        }
+        (baseline, candidate) if baseline != candidate => {
+            diff.insert(
+                format_json_path(path),
+                ValueDiff {
+                    baseline: baseline.cloned(),
+                    candidate: candidate.cloned(),
+                },
+            );
+        }
+        _ => {}
+    }
+}
+
+/// Compares status, headers and body of the responses, body is compared as JSON if both bodies are structured
+fn diff(
+    cfg: &CompareConfig,
+    baseline: &ComparedResponse,
+    candidate: &ComparedResponse,
+) -> ResponseDiff {
+    let mut diff = ResponseDiff::default();
+    if baseline.status != candidate.status {
+        diff.status = Some(ValueDiff {
+            baseline: baseline.status,
+            candidate: candidate.status,
+        });
+    }
+
+    let names: BTreeSet<&str> = baseline
+        .headers
+        .keys()
+        .chain(candidate.headers.keys())
+        .map(|name| name.as_str())
+        .filter(|name| !cfg.is_ignored_header(name))
+        .collect();
+    for name in names {
+        let baseline = header_value(&baseline.headers, name);
+        let candidate = header_value(&candidate.headers, name);
+        if baseline != candidate {
+            diff.headers.insert(
+                name.into(),
+                ValueDiff {
+                    baseline,
+                    candidate,
+                },
+            );
+        }
+    }
+
+    if baseline.body != candidate.body {
+        let into_json = |body: ParsedBody| body.text.map_or(body.body, JsonValue::String);
+        let baseline = into_json(parse_body(&baseline.headers, &baseline.body));
+        let candidate = into_json(parse_body(&candidate.headers, &candidate.body));
+        diff_json(
+            cfg,
+            &mut vec![],
+            Some(&baseline),
+            Some(&candidate),
+            &mut diff.body,
+        );
+    }
+
+    diff
+}
+
+/// Compares responses of the baseline and candidate targets in background,
+/// logs differences and counts them in the listener's statistics
+pub(crate) fn spawn(
+    listener: &'static ListenerConfig,
+    cfg: &'static CompareConfig,
+    responses: &ResponsesMap,
+    req_id: Uuid,
+) {
+    let response = |id: &str| {
+        responses
+            .get(id)
+            .and_then(|(resp, _)| resp.as_ref())
+            .map(ComparedResponse::from)
+    };
+    let baseline = response(cfg.baseline());
+    let candidates: Vec<(String, Option<ComparedResponse>)> = cfg
+        .candidates(listener.targets())
+        .into_iter()
+        .map(|id| {
+            let resp = response(&id);
+            (id, resp)
+        })
+        .collect();
+
+    tokio::spawn(async move {
+        let stats = stats_for(&listener.id());
+        for (id, candidate) in candidates {
+            let (Some(baseline), Some(candidate)) = (&baseline, candidate) else {
+                stats.missing.fetch_add(1, Ordering::Relaxed);
+                debug!(
+                    "{req_id}: responses of `{}` and `{id}` aren't compared, some of them is absent, listener: {}",
+                    cfg.baseline(),
+                    listener.id()
+                );
+                continue;
+            };
+
+            let diff = diff(cfg, baseline, &candidate);
+            stats.count(&diff);
+            if diff.is_empty() {
+                debug!(
+                    "{req_id}: responses of `{}` and `{id}` are equal, listener: {}",
+                    cfg.baseline(),
+                    listener.id()
+                );
+            } else {
+                info!(
+                    "{req_id}: responses of `{}` and `{id}` differ, listener: {}, diff: {}, stats: {}",
+                    cfg.baseline(),
+                    listener.id(),
+                    serde_json::to_string(&diff).unwrap_or_default(),
+                    serde_json::to_string(&stats.snapshot()).unwrap_or_default()
+                );
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+    use serde_json::json;
+
+    fn config(yaml: &str) -> CompareConfig {
+        serde_yaml_ng::from_str(yaml).unwrap()
+    }
+
+    fn response(
+        status: u16,
+        headers: &[(&'static str, &'static str)],
+        body: &'static str,
+    ) -> ComparedResponse {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(*name, HeaderValue::from_static(value));
+        }
+        ComparedResponse {
+            status,
+            headers: map,
+            body: Bytes::from_static(body.as_bytes()),
+        }
+    }
+
+    #[test]
+    fn equal_responses() {
+        let cfg = config("baseline: A");
+        let resp = || response(200, &[("x-version", "1")], r#"{"id": 1}"#);
+        assert!(diff(&cfg, &resp(), &resp()).is_empty());
+    }
+
+    #[test]
+    fn different_responses() {
+        let cfg = config(
+            "baseline: A\nignore_headers: [X-Request-Id]\nignore_json_paths: [.meta.took, '.items[].updated']",
+        );
+        let baseline = response(
+            200,
+            &[("x-request-id", "1"), ("x-version", "1"), ("x-old", "yes")],
+            r#"{"meta": {"took": 5, "total": 2}, "items": [{"id": 1, "updated": 1}, {"id": 2}]}"#,
+        );
+        let candidate = response(
+            201,
+            &[("x-request-id", "2"), ("x-version", "2")],
+            r#"{"meta": {"took": 7, "total": 3}, "items": [{"id": 1, "updated": 2}], "extra": true}"#,
+        );
+
+        let diff = diff(&cfg, &baseline, &candidate);
+        assert_eq!(
+            serde_json::to_value(&diff).unwrap(),
+            json!({
+                "status": {"baseline": 200, "candidate": 201},
+                "headers": {
+                    "x-old": {"baseline": "yes", "candidate": null},
+                    "x-version": {"baseline": "1", "candidate": "2"}
+                },
+                "body": {
+                    ".extra": {"baseline": null, "candidate": true},
+                    ".items[1]": {"baseline": {"id": 2}, "candidate": null},
+                    ".meta.total": {"baseline": 2, "candidate": 3}
+                }
+            })
+        );
+
+        let stats = CompareStats::default();
+        stats.count(&diff);
+        stats.count(&ResponseDiff::default());
+        assert_eq!(
+            stats.snapshot(),
+            CompareStatsSnapshot {
+                compared: 2,
+                matched: 1,
+                status_mismatches: 1,
+                header_mismatches: 1,
+                body_mismatches: 1,
+                missing: 0
+            }
+        );
+    }
+
+    #[test]
+    fn text_bodies() {
+        let cfg = config("baseline: A");
+        let headers = [("content-type", "text/plain")];
+        let diff = diff(
+            &cfg,
+            &response(200, &headers, "old"),
+            &response(200, &headers, "new"),
+        );
+        assert_eq!(
+            serde_json::to_value(&diff).unwrap(),
+            json!({"body": {".": {"baseline": "old", "candidate": "new"}}})
+        );
+    }
+}
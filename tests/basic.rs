@@ -1,10 +1,12 @@
 mod common;
 
 use crate::common::run_test_with_config;
-use common::{init_logging, test_one_case, TestConfig};
+use common::{init_logging, test_one_case, wait_for_listeners, TestConfig};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
 use futures_util::future::join_all;
-use reqwest::Method;
+use reqwest::{Client, Method};
 use serde_json::json;
+use std::io::{Read, Write};
 
 const TEST_CONFIG_PATH: &str = "tests/configs/integration/basic.yaml";
 const TEST_PORT: u16 = 3000;
@@ -267,14 +269,575 @@ async fn basic_functionality() {
     init_logging();
 
     let result = run_test_with_config(TEST_CONFIG_PATH, TEST_PORT, 60, false, async {
+        wait_for_listeners(8000..=8040).await;
         let client = reqwest::Client::new();
         let tasks: Vec<_> = prepare_test_cases()
             .into_iter()
             .map(|t| test_one_case(&client, t))
             .collect();
         join_all(tasks).await;
+        test_cors(&client).await;
+        test_cache(&client).await;
+        test_compression(&client).await;
+        test_body_formats(&client).await;
+        test_extract(&client).await;
+        test_select_by_expression(&client).await;
+        test_aggregate(&client).await;
+        test_prefer(&client).await;
+        test_failover(&client).await;
+        test_quorum(&client).await;
+        test_pipeline(&client).await;
+        test_compare(&client).await;
+        test_target_response(&client).await;
+        test_override_rules(&client).await;
+        test_targets_results(&client).await;
+        test_error_bodies(&client).await;
+        test_handler_errors(&client).await;
+        test_body_headers(&client).await;
+        test_proxy_headers(&client).await;
+        test_oauth2_token_invalidation(&client).await;
+        test_uri_transforms(&client).await;
     })
     .await;
 
     assert_eq!(result, Ok(()))
 }
+
+async fn test_cors(client: &Client) {
+    // Preflight should be answered by the listener itself
+    let resp = client
+        .request(Method::OPTIONS, "http://localhost:8011/")
+        .header("origin", "https://app.example.com")
+        .header("access-control-request-method", "POST")
+        .header("access-control-request-headers", "content-type")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 204, "cors: allowed preflight");
+    assert_eq!(
+        resp.headers()["access-control-allow-origin"],
+        "https://app.example.com"
+    );
+    assert_eq!(resp.headers()["access-control-allow-methods"], "GET, POST");
+    assert_eq!(
+        resp.headers()["access-control-allow-headers"],
+        "content-type"
+    );
+    assert_eq!(resp.headers()["access-control-max-age"], "3600");
+    assert!(resp.headers().get("x-target-id").is_none());
+
+    let resp = client
+        .request(Method::OPTIONS, "http://localhost:8011/")
+        .header("origin", "https://evil.example.com")
+        .header("access-control-request-method", "POST")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 403, "cors: rejected preflight");
+
+    // Actual request gets CORS headers in the response selected by strategy
+    let resp = client
+        .get("http://localhost:8011/")
+        .header("origin", "https://app.example.com")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 200, "cors: actual request");
+    assert_eq!(resp.headers()["x-target-id"], "GOOD");
+    assert_eq!(
+        resp.headers()["access-control-allow-origin"],
+        "https://app.example.com"
+    );
+    assert_eq!(
+        resp.headers()["access-control-expose-headers"],
+        "x-target-id"
+    );
+}
+
+async fn test_cache(client: &Client) {
+    // Echo server returns request headers back, so `x-marker` shows which request has been cached
+    let get = |path: &'static str, marker: &'static str| {
+        client
+            .get(format!("http://localhost:8012/{path}"))
+            .header("x-marker", marker)
+    };
+
+    let resp = get("0?cached=yes", "first").send().await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200, "cache: first request");
+    assert_eq!(resp.headers()["x-cache"], "MISS");
+    assert_eq!(resp.headers()["x-marker"], "first");
+
+    let resp = get("0?cached=yes", "second").send().await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200, "cache: repeated request");
+    assert_eq!(resp.headers()["x-cache"], "HIT");
+    assert_eq!(resp.headers()["x-marker"], "first");
+
+    let resp = get("0?cached=no", "third").send().await.unwrap();
+    assert_eq!(resp.headers()["x-cache"], "MISS", "cache: another key");
+    assert_eq!(resp.headers()["x-marker"], "third");
+
+    let resp = get("0?cached=yes", "fourth")
+        .header("cache-control", "no-cache")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.headers()["x-cache"], "MISS", "cache: no-cache request");
+    assert_eq!(resp.headers()["x-marker"], "fourth");
+
+    let resp = client
+        .post("http://localhost:8012/0?cached=yes")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.headers()["x-cache"], "BYPASS", "cache: post request");
+}
+
+fn gzip(body: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(body).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn gunzip(body: &[u8]) -> String {
+    let mut decoded = String::new();
+    GzDecoder::new(body).read_to_string(&mut decoded).unwrap();
+    decoded
+}
+
+async fn test_compression(client: &Client) {
+    let json_body = json!({"key": "value"}).to_string();
+
+    // Encoded request body is decoded before conditions evaluation and passing to target
+    let resp = client
+        .post("http://localhost:8013/")
+        .header("content-encoding", "gzip")
+        .body(gzip(json_body.as_bytes()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 200, "compression: decode request");
+    assert_eq!(resp.headers()["x-target-id"], "JSON");
+    assert!(resp.headers().get("content-encoding").is_none());
+    assert_eq!(resp.text().await.unwrap(), json_body);
+
+    let resp = client
+        .post("http://localhost:8013/")
+        .header("content-encoding", "compress")
+        .body(json_body.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        resp.status().as_u16(),
+        415,
+        "compression: unsupported encoding"
+    );
+
+    // Decoded body can't exceed the limit
+    let resp = client
+        .post("http://localhost:8013/")
+        .header("content-encoding", "gzip")
+        .body(gzip(&[b' '; 10000]))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 413, "compression: too large body");
+    let body: serde_json::Value = serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+    assert_eq!(body["kind"], "payload_too_large");
+
+    // Response is compressed according to accept-encoding
+    let resp = client
+        .post("http://localhost:8013/")
+        .header("accept-encoding", "gzip")
+        .body(json_body.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 200, "compression: encode response");
+    assert_eq!(resp.headers()["x-target-id"], "JSON");
+    assert_eq!(resp.headers()["content-encoding"], "gzip");
+    let body = resp.bytes().await.unwrap();
+    assert_eq!(gunzip(&body), json_body);
+
+    // Target request is compressed, echo server returns it as is
+    let json_body = json!({"key": "another value"}).to_string();
+    let resp = client
+        .post("http://localhost:8013/")
+        .header("x-compress-request", "yes")
+        .body(json_body.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 200, "compression: encode request");
+    assert_eq!(resp.headers()["x-target-id"], "COMPRESSED");
+    assert_eq!(resp.headers()["content-encoding"], "gzip");
+    let body = resp.bytes().await.unwrap();
+    assert_eq!(gunzip(&body), json_body);
+}
+
+async fn test_body_formats(client: &Client) {
+    let cases = [
+        ("application/x-www-form-urlencoded", "tag=a&tag=b", "FORM"),
+        (
+            "application/xml",
+            r#"<event type="push"><repo>crate</repo></event>"#,
+            "XML",
+        ),
+        ("text/plain", "ping pong", "TEXT"),
+        ("text/plain", "pong ping", "DEFAULT"),
+    ];
+
+    for (content_type, body, expected_target) in cases {
+        let resp = client
+            .post("http://localhost:8014/")
+            .header("content-type", content_type)
+            .body(body)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 200, "body formats: {body}");
+        assert_eq!(
+            resp.headers()["x-target-id"],
+            expected_target,
+            "body formats: {body}"
+        );
+    }
+
+    // Parsed query parameters and cookies
+    for (url, expected_target) in [
+        ("http://localhost:8014/?tag=a&tag=b", "ATTRIBUTES"),
+        ("http://localhost:8014/?tag=a", "DEFAULT"),
+    ] {
+        let resp = client
+            .get(url)
+            .header("cookie", "team=blue; lang=en")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 200, "request attributes: {url}");
+        assert_eq!(
+            resp.headers()["x-target-id"],
+            expected_target,
+            "request attributes: {url}"
+        );
+    }
+
+    // Condition evaluation error rejects the request
+    for (amount, expected_status) in [("200", 200), ("many", 400)] {
+        let resp = client
+            .get("http://localhost:8014/")
+            .header("x-amount", amount)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            resp.status().as_u16(),
+            expected_status,
+            "condition error: {amount}"
+        );
+    }
+}
+
+async fn test_extract(client: &Client) {
+    let resp = client
+        .post("http://localhost:8015/?page=3")
+        .body(json!({"user": {"id": 42}}).to_string())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 200, "extract");
+    assert_eq!(resp.headers()["x-user-id"], "42");
+    assert_eq!(resp.headers()["x-page"], "3");
+    assert_eq!(resp.text().await.unwrap(), "user=42");
+}
+
+async fn test_select_by_expression(client: &Client) {
+    let resp = client
+        .get("http://localhost:8016/")
+        .header("x-pick", "newest")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 200, "select_by_expression");
+    assert_eq!(resp.headers()["x-target-id"], "NEWEST");
+    assert_eq!(resp.text().await.unwrap(), r#"{"version": 3}"#);
+
+    // Expression returns null, so override is used
+    let resp = client.get("http://localhost:8016/").send().await.unwrap();
+    assert_eq!(
+        resp.status().as_u16(),
+        200,
+        "select_by_expression: override"
+    );
+    assert_eq!(resp.headers()["x-target-id"], "${CTX_TARGET_ID}");
+    assert_eq!(resp.text().await.unwrap(), "");
+}
+
+async fn test_aggregate(client: &Client) {
+    let resp = client.get("http://localhost:8017/").send().await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200, "aggregate");
+    assert_eq!(resp.headers()["content-type"], "application/json");
+    let body: serde_json::Value = serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+    assert_eq!(body["USERS"], serde_json::json!({"users": [1, 2]}));
+    assert_eq!(body["ORDERS"], serde_json::json!({"orders": [3]}));
+    assert_eq!(body["TEXT"]["status"], 200);
+    assert!(body["TEXT"]["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("body isn't valid JSON"));
+
+    // Failed response of the target with `fail` action fails the whole request
+    let resp = client
+        .get("http://localhost:8017/")
+        .header("x-include-wrong-port", "yes")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 500, "aggregate: failed target");
+}
+
+async fn test_prefer(client: &Client) {
+    // STRICT has the highest priority but its response is treated as failed
+    let resp = client.get("http://localhost:8018/").send().await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200, "prefer priority");
+    assert_eq!(resp.headers()["x-target-id"], "PREFERRED");
+
+    let resp = client.get("http://localhost:8019/").send().await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200, "prefer fastest");
+    assert_eq!(resp.headers()["x-target-id"], "FAST");
+}
+
+async fn test_failover(client: &Client) {
+    let resp = client.get("http://localhost:8020/").send().await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200, "failover");
+    assert_eq!(resp.headers()["x-target-id"], "BACKUP");
+
+    // Total timeout is reached while the first target is queried
+    let started = std::time::Instant::now();
+    let resp = client.get("http://localhost:8021/").send().await.unwrap();
+    assert_eq!(resp.status().as_u16(), 504, "failover: total timeout");
+    assert_eq!(resp.headers()["x-target-id"], "SLOW");
+    assert!(started.elapsed() < std::time::Duration::from_secs(3));
+
+    // Unused fallback isn't signed, so its slow token endpoint isn't queried
+    let started = std::time::Instant::now();
+    let resp = client.get("http://localhost:8040/").send().await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200, "failover: unused fallback");
+    assert_eq!(resp.headers()["x-target-id"], "PRIMARY");
+    assert!(started.elapsed() < std::time::Duration::from_secs(2));
+}
+
+async fn test_quorum(client: &Client) {
+    let resp = client.get("http://localhost:8022/").send().await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200, "quorum");
+    assert_eq!(resp.headers()["x-target-id"], "FIRST");
+
+    // Quorum isn't reached, failed response is returned
+    let resp = client
+        .get("http://localhost:8022/")
+        .header("x-exclude-second", "yes")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 502, "quorum: not reached");
+    assert_eq!(resp.headers()["x-target-id"], "DOWN");
+
+    // Slow target isn't awaited
+    let started = std::time::Instant::now();
+    let resp = client.get("http://localhost:8023/").send().await.unwrap();
+    assert_eq!(resp.status().as_u16(), 202, "quorum: early return");
+    assert!(started.elapsed() < std::time::Duration::from_secs(1));
+}
+
+async fn test_pipeline(client: &Client) {
+    let resp = client
+        .get("http://localhost:8024/")
+        .header("x-delay", "0")
+        .header("x-user", "john")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 200, "pipeline");
+    assert_eq!(resp.headers()["x-token"], "abc-john");
+    assert_eq!(resp.headers()["x-auth-status"], "200 OK");
+
+    // The first step timed out, so the next one is skipped and there is no target's response
+    let resp = client
+        .get("http://localhost:8024/")
+        .header("x-delay", "2")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 500, "pipeline: step failed");
+    assert!(!resp.headers().contains_key("x-token"));
+}
+
+async fn test_compare(client: &Client) {
+    // Differences and absent responses of candidates don't affect the response
+    let resp = client
+        .get("http://localhost:8025/")
+        .header("x-version", "1")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 200, "compare");
+    assert_eq!(resp.headers()["x-version"], "1");
+}
+
+async fn test_target_response(client: &Client) {
+    // The first target's status is mapped to failed one, so the second one is selected
+    let resp = client
+        .post("http://localhost:8026/")
+        .header("content-type", "application/json")
+        .body(r#"{"value": 42}"#)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 200, "target response");
+    assert_eq!(resp.headers()["x-target-id"], "FILTERED");
+    assert_eq!(resp.headers()["content-type"], "application/json");
+    let body: serde_json::Value = serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+    assert_eq!(body, serde_json::json!({"wrapped": 42}));
+}
+
+async fn test_override_rules(client: &Client) {
+    let resp = client.get("http://localhost:8027/").send().await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200, "override rules");
+    assert_eq!(resp.headers()["x-target-id"], "OK");
+
+    // Only the failed target is queried, so the first rule is applied
+    let resp = client
+        .get("http://localhost:8027/")
+        .header("x-only-down", "yes")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 503, "override rules: failed");
+    assert!(!resp.headers().contains_key("x-target-id"));
+    assert_eq!(resp.text().await.unwrap(), r#"{"error": "try later"}"#);
+}
+
+async fn test_targets_results(client: &Client) {
+    let resp = client.get("http://localhost:8028/").send().await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200, "targets results");
+    let duration: u64 = resp.headers()["x-ok-duration"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(duration < 1000);
+    assert_eq!(
+        resp.text().await.unwrap(),
+        "ok=1 failed=3 OK=ok/200 DOWN=error/502 SLOW=timeout/504 DROPPED=dropped/none SKIPPED=skipped/none"
+    );
+}
+
+async fn test_error_bodies(client: &Client) {
+    let resp = client.get("http://localhost:8029/").send().await.unwrap();
+    assert_eq!(resp.status().as_u16(), 502, "connect error");
+    assert_eq!(
+        resp.headers()["content-type"],
+        "application/problem+json",
+        "connect error"
+    );
+    let body: serde_json::Value = serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+    assert_eq!(body["status"], 502);
+    assert_eq!(body["kind"], "connect");
+    assert_eq!(body["target_id"], "DOWN");
+    assert!(body["request_id"].is_string());
+    assert!(body["detail"].is_string());
+
+    let resp = client.post("http://localhost:8029/").send().await.unwrap();
+    assert_eq!(resp.status().as_u16(), 405, "method not allowed");
+    let body: serde_json::Value = serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+    assert_eq!(body["kind"], "method_not_allowed");
+
+    let resp = client.get("http://localhost:8030/").send().await.unwrap();
+    assert_eq!(resp.status().as_u16(), 504, "error template");
+    assert_eq!(resp.headers()["content-type"], "text/plain");
+    assert_eq!(resp.text().await.unwrap(), "504 timeout target=hidden");
+}
+
+async fn test_handler_errors(client: &Client) {
+    let resp = client
+        .get("http://localhost:8031/path")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 500, "target without host");
+    let body: serde_json::Value = serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+    assert_eq!(body["kind"], "internal");
+    assert_eq!(body["target_id"], "RELATIVE");
+}
+
+async fn test_body_headers(client: &Client) {
+    let resp = client
+        .post("http://localhost:8032/")
+        .header("content-type", "application/octet-stream")
+        .body("original body")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 200, "body headers");
+    assert_eq!(resp.headers()["x-echo-length"], "14");
+    assert_eq!(resp.headers()["x-echo-type"], "text/plain");
+    assert_eq!(resp.headers()["content-type"], "application/json");
+    assert_eq!(resp.headers()["content-length"], "16");
+    assert_eq!(resp.text().await.unwrap(), r#"{"echoed": true}"#);
+}
+
+async fn test_proxy_headers(client: &Client) {
+    // Echo server returns request headers back
+    let resp = client
+        .get("http://localhost:8033/")
+        .header("connection", "x-hop")
+        .header("x-hop", "1")
+        .header("proxy-authorization", "Basic xxx")
+        .header("x-end-to-end", "1")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 200, "proxy headers");
+    let headers = resp.headers();
+    assert!(!headers.contains_key("x-hop"));
+    assert!(!headers.contains_key("proxy-authorization"));
+    assert_eq!(headers["x-end-to-end"], "1");
+    assert_eq!(headers["via"], "1.1 http-dragonfly, 1.1 http-dragonfly");
+    assert_eq!(headers["x-forwarded-for"], "127.0.0.1");
+    assert_eq!(headers["x-forwarded-proto"], "http");
+    assert_eq!(headers["x-forwarded-host"], "localhost:8033");
+
+    let resp = client.get("http://localhost:8034/").send().await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200, "forwarded header");
+    let headers = resp.headers();
+    assert!(!headers.contains_key("via"));
+    assert!(!headers.contains_key("x-forwarded-for"));
+    assert_eq!(
+        headers["forwarded"],
+        r#"for=127.0.0.1;proto=http;host="localhost:8034""#
+    );
+}
+
+async fn test_oauth2_token_invalidation(client: &Client) {
+    // Target rejects any token, so each request obtains a new one
+    let mut tokens = vec![];
+    for _ in 0..2 {
+        let resp = client.get("http://localhost:8037/").send().await.unwrap();
+        assert_eq!(resp.status().as_u16(), 401, "rejected oauth2 token");
+        let token = resp.text().await.unwrap();
+        assert!(token.starts_with("Bearer "), "unexpected token `{token}`");
+        tokens.push(token);
+    }
+    assert_ne!(tokens[0], tokens[1]);
+}
+
+async fn test_uri_transforms(client: &Client) {
+    // Untouched query parameters are passed to the target as is
+    let resp = client
+        .get("http://localhost:8038/?flag&name=a%20b&secret=1&page=1")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 200, "uri transforms");
+    assert_eq!(resp.text().await.unwrap(), "flag&name=a%20b&page=2");
+}
@@ -94,6 +94,19 @@ async fn run_test_with_config_and_server(
     }
 }
 
+/// Waits until all listeners accept connections, since config loading may take a while
+#[allow(dead_code)]
+pub async fn wait_for_listeners(ports: impl IntoIterator<Item = u16>) {
+    for port in ports {
+        while tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .is_err()
+        {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+}
+
 pub async fn test_one_case(client: &Client, test_config: TestConfig) {
     let mut req = match test_config.method {
         Method::GET => client.get(format!("http://localhost:{}/", test_config.port)),